@@ -0,0 +1,338 @@
+//! A benchmark modelled after leveldb's `db_bench`.
+//!
+//! Run with `cargo bench --bench db_bench -- [flags]`, where the flags are:
+//!
+//! * `--benchmarks=a,b,c`: the benchmarks to run, in order, out of
+//!   `fillseq`, `fillrandom`, `overwrite`, `fillbatch`, `readrandom`,
+//!   `readseq`, `readreverse`, `seekrandom` and `readsnapshot`
+//! * `--num=N`: the number of entries (default 1000000)
+//! * `--reads=N`: the number of random reads and seeks (default: `--num`)
+//! * `--value_size=N`: the size of values in bytes (default 100)
+//! * `--batch_size=N`: the entries per batch for `fillbatch` (default 1000)
+//! * `--cache_size=N`: the size of the block cache in bytes, 0 for none
+//!   (default 8MB)
+//! * `--compression=snappy|none`: how to compress blocks (default snappy)
+//! * `--bloom_bits=N`: bits per key of a bloom filter, negative for none
+//!   (default -1)
+//! * `--db=PATH`: where to create databases (default: a temporary
+//!   directory)
+//!
+//! The `fill*` benchmarks start with a fresh database; all others use the
+//! database left by the previous benchmark. Values are half compressible.
+extern crate leveldb;
+extern crate leveldb_sys;
+extern crate tempdir;
+
+use leveldb::byte_key::ByteKey;
+use leveldb::database::Database;
+use leveldb::database::batch::{Batch, Writebatch};
+use leveldb::database::cache::Cache;
+use leveldb::database::filter_policy::FilterPolicy;
+use leveldb::database::kv::KV;
+use leveldb::iterator::{Iterable, LevelDBIterator};
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use leveldb::snapshots::Snapshots;
+use leveldb_sys::Compression;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::time::Instant;
+use tempdir::TempDir;
+
+const KEY_SIZE: usize = 16;
+const DEFAULT_BENCHMARKS: &str = "fillseq,fillrandom,overwrite,fillbatch,readrandom,readseq,readreverse,\
+                                  seekrandom,readsnapshot";
+
+struct Config {
+    benchmarks: Vec<String>,
+    num: u64,
+    reads: u64,
+    value_size: usize,
+    batch_size: u64,
+    cache_size: usize,
+    compression: Compression,
+    bloom_bits: i32,
+    db: Option<PathBuf>,
+}
+
+/// xorshift64*, so runs are repeatable without extra dependencies
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn uniform(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+struct Stats {
+    ops: u64,
+    bytes: u64,
+    found: u64,
+}
+
+fn usage(message: &str) -> ! {
+    eprintln!("db_bench: {}", message);
+    process::exit(1)
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| usage(&format!("invalid value for --{}: {}", flag, value)))
+}
+
+impl Config {
+    fn from_args() -> Config {
+        let mut config = Config {
+            benchmarks: vec![],
+            num: 1_000_000,
+            reads: 0,
+            value_size: 100,
+            batch_size: 1000,
+            cache_size: 8 << 20,
+            compression: Compression::Snappy,
+            bloom_bits: -1,
+            db: None,
+        };
+        let mut benchmarks = DEFAULT_BENCHMARKS.to_string();
+        let mut reads = None;
+        for arg in env::args().skip(1) {
+            // cargo passes `--bench` to benchmarks
+            if arg == "--bench" {
+                continue;
+            }
+            let (flag, value) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (arg[2..i].to_string(), arg[i + 1..].to_string()),
+                _ => usage(&format!("invalid argument: {}", arg)),
+            };
+            match &flag[..] {
+                "benchmarks" => benchmarks = value,
+                "num" => config.num = parse(&flag, &value),
+                "reads" => reads = Some(parse(&flag, &value)),
+                "value_size" => config.value_size = parse(&flag, &value),
+                "batch_size" => config.batch_size = parse(&flag, &value),
+                "cache_size" => config.cache_size = parse(&flag, &value),
+                "bloom_bits" => config.bloom_bits = parse(&flag, &value),
+                "db" => config.db = Some(PathBuf::from(value)),
+                "compression" => {
+                    config.compression = match &value[..] {
+                        "snappy" => Compression::Snappy,
+                        "none" => Compression::No,
+                        _ => usage(&format!("invalid value for --compression: {}", value)),
+                    }
+                }
+                _ => usage(&format!("unknown flag: --{}", flag)),
+            }
+        }
+        config.benchmarks = benchmarks.split(',').filter(|b| !b.is_empty()).map(String::from).collect();
+        config.reads = reads.unwrap_or(config.num);
+        if config.batch_size == 0 {
+            usage("--batch_size must be positive");
+        }
+        config
+    }
+
+    fn options(&self) -> Options {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        options.compression = self.compression;
+        if self.cache_size > 0 {
+            options.cache = Some(Cache::new(self.cache_size));
+        }
+        if self.bloom_bits >= 0 {
+            options.filter_policy = Some(FilterPolicy::bloom(self.bloom_bits));
+        }
+        options
+    }
+}
+
+fn key(n: u64) -> ByteKey {
+    ByteKey(format!("{:01$}", n, KEY_SIZE).into_bytes())
+}
+
+/// Generate a value of `size` bytes whose second half repeats the first.
+fn value(random: &mut Random, size: usize) -> Vec<u8> {
+    let half = (size + 1) / 2;
+    let mut value: Vec<u8> = (0..half).map(|_| b' ' + random.uniform(95) as u8).collect();
+    let repeat = value[..size - half].to_vec();
+    value.extend_from_slice(&repeat);
+    value
+}
+
+struct Bench {
+    config: Config,
+    dir: PathBuf,
+    fresh: u32,
+    database: Option<Database<ByteKey>>,
+    random: Random,
+}
+
+impl Bench {
+    fn fresh_database(&mut self) -> &Database<ByteKey> {
+        self.database = None;
+        self.fresh += 1;
+        let path = self.dir.join(format!("db{}", self.fresh));
+        let database = Database::open(&path, self.config.options())
+            .unwrap_or_else(|e| usage(&format!("opening {}: {:?}", path.display(), e)));
+        self.database = Some(database);
+        self.database.as_ref().unwrap()
+    }
+
+    fn write(&mut self, fresh: bool, random: bool, batch_size: u64) -> Stats {
+        let num = self.config.num;
+        let value_size = self.config.value_size;
+        let mut keys = Random(self.random.next() | 1);
+        let mut values = Random(self.random.next() | 1);
+        if fresh || self.database.is_none() {
+            self.fresh_database();
+        }
+        let database = self.database.as_ref().unwrap();
+        let mut batch = Writebatch::new();
+        let mut n = 0;
+        while n < num {
+            batch.clear();
+            for i in n..num.min(n + batch_size) {
+                let k = if random { keys.uniform(num) } else { i };
+                batch.put(key(k), &value(&mut values, value_size));
+            }
+            database.write(WriteOptions::new(), &batch).unwrap();
+            n += batch_size;
+        }
+        Stats {
+            ops: num,
+            bytes: num * (KEY_SIZE + value_size) as u64,
+            found: num,
+        }
+    }
+
+    fn database(&self) -> &Database<ByteKey> {
+        self.database.as_ref().unwrap_or_else(|| usage("no database to read from, run a fill benchmark first"))
+    }
+
+    fn read_random(&mut self, snapshot: bool) -> Stats {
+        let mut random = Random(self.random.next() | 1);
+        let (num, reads) = (self.config.num, self.config.reads);
+        let database = self.database();
+        let mut stats = Stats { ops: reads, bytes: 0, found: 0 };
+        let mut record = |value: Option<Vec<u8>>| {
+            if let Some(value) = value {
+                stats.found += 1;
+                stats.bytes += (KEY_SIZE + value.len()) as u64;
+            }
+        };
+        if snapshot {
+            let snapshot = database.snapshot();
+            for _ in 0..reads {
+                record(snapshot.get(ReadOptions::new(), key(random.uniform(num))).unwrap());
+            }
+        } else {
+            for _ in 0..reads {
+                record(database.get(ReadOptions::new(), key(random.uniform(num))).unwrap());
+            }
+        }
+        stats
+    }
+
+    fn read_sequential(&self, reverse: bool) -> Stats {
+        let database = self.database();
+        let mut stats = Stats { ops: 0, bytes: 0, found: 0 };
+        let mut record = |(key, value): (ByteKey, Vec<u8>)| {
+            stats.ops += 1;
+            stats.found += 1;
+            stats.bytes += (key.0.len() + value.len()) as u64;
+        };
+        if reverse {
+            database.iter(ReadOptions::new()).reverse().for_each(&mut record);
+        } else {
+            database.iter(ReadOptions::new()).for_each(&mut record);
+        }
+        stats
+    }
+
+    fn seek_random(&mut self) -> Stats {
+        let mut random = Random(self.random.next() | 1);
+        let (num, reads) = (self.config.num, self.config.reads);
+        let database = self.database();
+        let mut stats = Stats { ops: reads, bytes: 0, found: 0 };
+        for _ in 0..reads {
+            let target = key(random.uniform(num));
+            if let Some((key, value)) = database.iter(ReadOptions::new()).from(&target).next() {
+                if key == target {
+                    stats.found += 1;
+                }
+                stats.bytes += (key.0.len() + value.len()) as u64;
+            }
+        }
+        stats
+    }
+
+    fn run(&mut self, name: &str) -> Option<Stats> {
+        let batch_size = self.config.batch_size;
+        Some(match name {
+            "fillseq" => self.write(true, false, 1),
+            "fillrandom" => self.write(true, true, 1),
+            "overwrite" => self.write(false, true, 1),
+            "fillbatch" => self.write(true, false, batch_size),
+            "readrandom" => self.read_random(false),
+            "readsnapshot" => self.read_random(true),
+            "readseq" => self.read_sequential(false),
+            "readreverse" => self.read_sequential(true),
+            "seekrandom" => self.seek_random(),
+            _ => return None,
+        })
+    }
+}
+
+fn main() {
+    let config = Config::from_args();
+    let tmp = TempDir::new("db_bench").unwrap();
+    let dir = config.db.clone().unwrap_or_else(|| tmp.path().to_path_buf());
+
+    println!("Keys:        {} bytes each", KEY_SIZE);
+    println!("Values:      {} bytes each ({} bytes after compression)",
+             config.value_size,
+             config.value_size / 2);
+    println!("Entries:     {}", config.num);
+    println!("Compression: {}",
+             match config.compression {
+                 Compression::Snappy => "snappy",
+                 Compression::No => "none",
+             });
+    println!("Cache:       {} bytes", config.cache_size);
+    if config.bloom_bits >= 0 {
+        println!("Bloom:       {} bits per key", config.bloom_bits);
+    }
+    println!("------------------------------------------------");
+
+    let benchmarks = config.benchmarks.clone();
+    let mut bench = Bench {
+        config,
+        dir,
+        fresh: 0,
+        database: None,
+        random: Random(301),
+    };
+    for name in &benchmarks {
+        let start = Instant::now();
+        let stats = bench.run(name).unwrap_or_else(|| usage(&format!("unknown benchmark: {}", name)));
+        let elapsed = start.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        let ops = stats.ops.max(1) as f64;
+        let mut line = format!("{:<12} : {:>11.3} micros/op; {:>10.0} ops/sec;",
+                               name,
+                               secs * 1e6 / ops,
+                               ops / secs);
+        if stats.bytes > 0 {
+            line.push_str(&format!(" {:>7.1} MB/s;", stats.bytes as f64 / 1048576.0 / secs));
+        }
+        if stats.found != stats.ops {
+            line.push_str(&format!(" ({} of {} found)", stats.found, stats.ops));
+        }
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,69 @@
+//! Timings of the most frequent calls into leveldb.
+//!
+//! Run with `cargo bench --bench hot_paths`. Each line reports the mean
+//! time per call; compare runs before and after a change.
+extern crate leveldb;
+extern crate tempdir;
+
+use leveldb::database::Database;
+use leveldb::database::batch::{Batch, Writebatch};
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use leveldb::snapshots::Snapshots;
+use std::time::Instant;
+use tempdir::TempDir;
+
+const OPS: i32 = 200_000;
+
+fn report(name: &str, ops: i32, f: &mut dyn FnMut(i32)) {
+    let start = Instant::now();
+    for i in 0..ops {
+        f(i);
+    }
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    println!("{:<40} {:>8} ns/op", name, nanos / ops as u64);
+}
+
+fn main() {
+    let tmp = TempDir::new("hot_paths").unwrap();
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    let database: Database<i32> = Database::open(tmp.path(), options).unwrap();
+    let value = [0u8; 100];
+
+    report("put", OPS, &mut |i| {
+        database.put(WriteOptions::new(), i, &value).unwrap();
+    });
+    report("get", OPS, &mut |i| {
+        database.get(ReadOptions::new(), i).unwrap();
+    });
+    report("get_bytes", OPS, &mut |i| {
+        database.get_bytes(ReadOptions::new(), i).unwrap();
+    });
+    report("get without filling the cache", OPS, &mut |i| {
+        let mut options = ReadOptions::new();
+        options.fill_cache = false;
+        database.get(options, i).unwrap();
+    });
+    {
+        let snapshot = database.snapshot();
+        report("get from snapshot", OPS, &mut |i| {
+            snapshot.get(ReadOptions::new(), i).unwrap();
+        });
+    }
+    report("batch write (10 puts)", OPS / 10, &mut |i| {
+        let mut batch = Writebatch::new();
+        for j in 0..10 {
+            batch.put(i * 10 + j, &value);
+        }
+        database.write(WriteOptions::new(), &batch).unwrap();
+    });
+    report("iterator creation and first entry", OPS / 10, &mut |_| {
+        database.iter(ReadOptions::new()).next().unwrap();
+    });
+    report("delete", OPS, &mut |i| {
+        database.delete(WriteOptions::new(), i).unwrap();
+    });
+}
@@ -0,0 +1,37 @@
+//! Writes keys to a database ordered by a custom comparator, so leveldb
+//! calls back into Rust, and checks iteration follows the comparator.
+#![no_main]
+use leveldb::byte_key::ByteKey;
+use leveldb::comparator::OrdComparator;
+use leveldb::database::Database;
+use leveldb::iterator::Iterable;
+use leveldb::kv::KV;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeSet;
+use tempdir::TempDir;
+
+fuzz_target!(|data: &[u8]| {
+    let tmp = TempDir::new("fuzz_comparator").unwrap();
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    // a small write buffer, so comparisons also happen during compactions
+    options.write_buffer_size = Some(64 << 10);
+    let comparator = OrdComparator::<ByteKey>::new("fuzz_comparator");
+    let database = Database::open_with_comparator(tmp.path(), options, comparator).unwrap();
+
+    // the input is a sequence of length-prefixed keys
+    let mut keys = BTreeSet::new();
+    let mut rest = data;
+    while let Some((&len, tail)) = rest.split_first() {
+        let len = (len as usize).min(tail.len());
+        let key = ByteKey::new(&tail[..len]);
+        database.put(WriteOptions::new(), &key, &tail[..len]).unwrap();
+        assert_eq!(database.get(ReadOptions::new(), &key).unwrap(), Some(key.0.clone()));
+        keys.insert(key);
+        rest = &tail[len..];
+    }
+
+    let found: Vec<ByteKey> = database.keys_iter(ReadOptions::new()).collect();
+    assert_eq!(found, keys.into_iter().collect::<Vec<_>>());
+});
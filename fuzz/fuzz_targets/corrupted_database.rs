@@ -0,0 +1,73 @@
+//! Opens database directories whose files were corrupted, which must fail
+//! with errors rather than panics or crashes.
+//!
+//! The input selects a file of a freshly written database and a list of
+//! edits to it: overwriting bytes, or truncating the file.
+#![no_main]
+use leveldb::batch::{Batch, Writebatch};
+use leveldb::byte_key::ByteKey;
+use leveldb::database::Database;
+use leveldb::iterator::Iterable;
+use leveldb::kv::KV;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use libfuzzer_sys::fuzz_target;
+use std::fs;
+use std::path::Path;
+use tempdir::TempDir;
+
+fn populate(path: &Path) {
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    options.write_buffer_size = Some(16 << 10);
+    let database: Database<ByteKey> = Database::open(path, options).unwrap();
+    for i in 0..40u32 {
+        let mut batch = Writebatch::new();
+        for j in 0..25u32 {
+            let key = format!("{:04}/{:04}", i, j);
+            batch.put(ByteKey::new(key.as_bytes()), &[i as u8; 32]);
+        }
+        database.write(WriteOptions::new(), &batch).unwrap();
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let tmp = TempDir::new("fuzz_corrupted").unwrap();
+    populate(tmp.path());
+
+    let mut files: Vec<_> = fs::read_dir(tmp.path()).unwrap().map(|e| e.unwrap().path()).collect();
+    files.retain(|f| !f.ends_with("LOCK") && !f.ends_with("LOG"));
+    files.sort();
+    let file = &files[data[0] as usize % files.len()];
+    let mut contents = fs::read(file).unwrap();
+    let paranoid = data[1] & 1 == 1;
+
+    for edit in data[2..].chunks(5) {
+        if contents.is_empty() || edit.len() < 5 {
+            break;
+        }
+        let offset = (edit[1] as usize) << 16 | (edit[2] as usize) << 8 | edit[3] as usize;
+        let offset = offset % contents.len();
+        if edit[0] == 0 {
+            contents.truncate(offset);
+        } else {
+            contents[offset] ^= edit[4] | 1;
+        }
+    }
+    fs::write(file, &contents).unwrap();
+
+    let mut options = Options::new();
+    options.paranoid_checks = paranoid;
+    let database: Database<ByteKey> = match Database::open(tmp.path(), options) {
+        Ok(database) => database,
+        Err(_) => return,
+    };
+    let mut verify = ReadOptions::new();
+    verify.verify_checksums = paranoid;
+    for (key, _) in database.iter(verify) {
+        let _ = database.get(ReadOptions::new(), &key);
+    }
+    let _ = database.get(ReadOptions::new(), ByteKey::new(b"0000/0000"));
+});
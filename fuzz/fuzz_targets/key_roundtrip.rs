@@ -0,0 +1,15 @@
+//! Decodes keys through `from_u8` and checks they encode to the same bytes.
+#![no_main]
+use db_key::Key;
+use leveldb::byte_key::ByteKey;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let key = ByteKey::from_u8(data);
+    assert_eq!(key.as_slice(|k| k.to_vec()), data);
+
+    if data.len() == 4 {
+        let key = i32::from_u8(data);
+        assert_eq!(key.as_slice(|k| k.to_vec()), data);
+    }
+});
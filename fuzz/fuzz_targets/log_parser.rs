@@ -0,0 +1,32 @@
+//! Parses arbitrary bytes as a write-ahead log, decoding every batch.
+#![no_main]
+use leveldb::batch::WritebatchIterator;
+use leveldb::byte_key::ByteKey;
+use leveldb::log::LogReader;
+use libfuzzer_sys::fuzz_target;
+
+struct Count(usize);
+
+impl WritebatchIterator for Count {
+    type K = ByteKey;
+
+    fn put(&mut self, _key: ByteKey, _value: &[u8]) {
+        self.0 += 1;
+    }
+
+    fn deleted(&mut self, _key: ByteKey) {
+        self.0 += 1;
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let reader = LogReader::new(data);
+    for batch in reader.take(10_000) {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(_) => continue,
+        };
+        let count = batch.iterate(Box::new(Count(0)));
+        assert_eq!(count.0, batch.len());
+    }
+});
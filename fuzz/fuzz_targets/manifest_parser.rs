@@ -0,0 +1,24 @@
+//! Parses arbitrary bytes as a MANIFEST, both as a single version edit and
+//! as a log of edits replayed into a version set.
+#![no_main]
+use leveldb::log::LogReader;
+use leveldb::manifest::{VersionEdit, VersionSet};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = VersionEdit::decode(data);
+
+    let mut reader = LogReader::new(data);
+    let mut edits = vec![];
+    while let Ok(Some(record)) = reader.read_record() {
+        match VersionEdit::decode(&record) {
+            Ok(edit) => edits.push(edit),
+            Err(_) => return,
+        }
+    }
+    if let Ok(versions) = VersionSet::from_edits(edits) {
+        for level in 0..versions.levels().len() {
+            let _ = versions.files_at_level(level);
+        }
+    }
+});
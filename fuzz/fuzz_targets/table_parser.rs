@@ -0,0 +1,26 @@
+//! Parses arbitrary bytes as a table file, reading every entry.
+#![no_main]
+use leveldb::table::Table;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let table = match Table::from_bytes(data.to_vec()) {
+        Ok(table) => table,
+        Err(_) => return,
+    };
+    let _ = table.filter_policy();
+    if let Ok(blocks) = table.data_blocks() {
+        for block in &blocks {
+            let _ = table.key_may_match(block, b"key");
+        }
+    }
+    for entry in table.iter() {
+        if entry.is_err() {
+            break;
+        }
+    }
+    let mut iter = table.iter();
+    if iter.seek(&data[..data.len().min(8)]).is_ok() {
+        let _ = iter.next();
+    }
+});
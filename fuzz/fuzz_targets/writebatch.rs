@@ -0,0 +1,93 @@
+//! Builds write batches from a sequence of puts, deletes and clears,
+//! checks iterating a batch replays it, and that writing it to a database
+//! has the same effect as applying it to a map.
+#![no_main]
+use leveldb::batch::{Batch, Writebatch, WritebatchIterator};
+use leveldb::byte_key::ByteKey;
+use leveldb::database::Database;
+use leveldb::iterator::Iterable;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use libfuzzer_sys::fuzz_target;
+use std::collections::BTreeMap;
+use tempdir::TempDir;
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+struct Replay(Vec<Op>);
+
+impl WritebatchIterator for Replay {
+    type K = ByteKey;
+
+    fn put(&mut self, key: ByteKey, value: &[u8]) {
+        self.0.push(Op::Put(key.0, value.to_vec()));
+    }
+
+    fn deleted(&mut self, key: ByteKey) {
+        self.0.push(Op::Delete(key.0));
+    }
+}
+
+/// Take a slice of up to 15 bytes, prefixed by its length.
+fn take<'a>(data: &mut &'a [u8]) -> &'a [u8] {
+    let (&len, rest) = match data.split_first() {
+        Some(split) => split,
+        None => return &[],
+    };
+    let (taken, rest) = rest.split_at((len as usize % 16).min(rest.len()));
+    *data = rest;
+    taken
+}
+
+fuzz_target!(|data: &[u8]| {
+    let tmp = TempDir::new("fuzz_writebatch").unwrap();
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    let database: Database<ByteKey> = Database::open(tmp.path(), options).unwrap();
+
+    let mut batch = Writebatch::new();
+    let mut ops = vec![];
+    let mut model = BTreeMap::new();
+    let mut pending = BTreeMap::new();
+    let mut data = data;
+    while let Some((&op, rest)) = data.split_first() {
+        data = rest;
+        match op % 4 {
+            0 => {
+                let key = take(&mut data).to_vec();
+                let value = take(&mut data).to_vec();
+                batch.put(ByteKey(key.clone()), &value);
+                pending.insert(key.clone(), Some(value.clone()));
+                ops.push(Op::Put(key, value));
+            }
+            1 => {
+                let key = take(&mut data).to_vec();
+                batch.delete(ByteKey(key.clone()));
+                pending.insert(key.clone(), None);
+                ops.push(Op::Delete(key));
+            }
+            2 => {
+                batch.clear();
+                pending.clear();
+                ops.clear();
+            }
+            _ => {
+                let replay = batch.iterate(Box::new(Replay(vec![])));
+                assert_eq!(replay.0, ops);
+                database.write(WriteOptions::new(), &batch).unwrap();
+                for (key, value) in pending.iter() {
+                    match *value {
+                        Some(ref value) => model.insert(key.clone(), value.clone()),
+                        None => model.remove(key),
+                    };
+                }
+            }
+        }
+    }
+
+    let found: BTreeMap<Vec<u8>, Vec<u8>> = database.iter(ReadOptions::new()).map(|(k, v)| (k.0, v)).collect();
+    assert_eq!(found, model);
+});
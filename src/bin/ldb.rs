@@ -0,0 +1,400 @@
+//! `ldb`, a command-line tool for inspecting and operating on leveldb
+//! databases.
+//!
+//! Run `ldb help` for usage.
+extern crate leveldb;
+
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+
+use leveldb::byte_key::ByteKey;
+use leveldb::compaction::Compaction;
+use leveldb::database::Database;
+use leveldb::dump::{self, DumpHeader, DumpWriter, ImportOptions};
+use leveldb::ingest::BYTEWISE_COMPARATOR;
+use leveldb::iterator::{Iterable, LevelDBIterator};
+use leveldb::kv::KV;
+use leveldb::management;
+use leveldb::manifest::VersionSet;
+use leveldb::options::{Options, ReadOptions, WriteOptions};
+use leveldb::properties::Properties;
+
+const USAGE: &str = "usage: ldb <db> <command> [arguments] [flags]
+
+commands:
+    get <key>                 print the value of a key
+    put <key> <value>         store a value
+    delete <key>              delete a key
+    scan                      print all entries, tab-separated
+    count                     count entries
+    dump [file]               write entries in the dump format to file or stdout
+    load [file]               import a dump from file or stdin
+    compact                   compact the whole database
+    repair                    repair the database
+    destroy                   destroy the database
+    stats                     print database properties
+    manifest                  print the files at each level of a closed database
+
+flags:
+    --from <key>              scan/count: start at key (inclusive)
+    --to <key>                scan/count: stop at key (exclusive)
+    --prefix <key>            scan/count: only keys starting with prefix
+    --limit <n>               scan: print at most n entries
+    --key-format <format>     hex, escaped or utf8 (default: escaped)
+    --value-format <format>   hex, escaped or utf8 (default: escaped)
+    --create                  create the database if missing
+    --sync                    sync writes to disk";
+
+#[derive(Clone, Copy)]
+enum Format {
+    Hex,
+    Escaped,
+    Utf8,
+}
+
+struct Flags {
+    from: Option<Vec<u8>>,
+    to: Option<Vec<u8>>,
+    prefix: Option<Vec<u8>>,
+    limit: Option<usize>,
+    key_format: Format,
+    value_format: Format,
+    create: bool,
+    sync: bool,
+}
+
+impl Format {
+    fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "hex" => Ok(Format::Hex),
+            "escaped" => Ok(Format::Escaped),
+            "utf8" => Ok(Format::Utf8),
+            _ => Err(format!("unknown format: {}", name)),
+        }
+    }
+
+    fn decode(self, input: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Format::Hex => decode_hex(input),
+            Format::Escaped => unescape(input),
+            Format::Utf8 => Ok(input.as_bytes().to_vec()),
+        }
+    }
+
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Format::Hex => encode_hex(bytes),
+            Format::Escaped => escape(bytes),
+            Format::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_byte(high: u8, low: u8) -> Option<u8> {
+    let digit = |c: u8| (c as char).to_digit(16);
+    match (digit(high), digit(low)) {
+        (Some(high), Some(low)) => Some((high << 4 | low) as u8),
+        _ => None,
+    }
+}
+
+fn decode_hex(input: &str) -> Result<Vec<u8>, String> {
+    let bytes = input.as_bytes();
+    if bytes.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits: {}", input));
+    }
+    bytes.chunks(2)
+        .map(|pair| hex_byte(pair[0], pair[1]).ok_or_else(|| format!("invalid hex: {}", input)))
+        .collect()
+}
+
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    out
+}
+
+fn unescape(input: &str) -> Result<Vec<u8>, String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            Some(&b'\\') => out.push(b'\\'),
+            Some(&b'n') => out.push(b'\n'),
+            Some(&b't') => out.push(b'\t'),
+            Some(&b'x') if i + 4 <= bytes.len() => {
+                let byte = hex_byte(bytes[i + 2], bytes[i + 3])
+                    .ok_or_else(|| format!("invalid escape sequence in {}", input))?;
+                out.push(byte);
+                i += 2;
+            }
+            _ => return Err(format!("invalid escape sequence in {}", input)),
+        }
+        i += 2;
+    }
+    Ok(out)
+}
+
+fn parse_flags(args: &mut Vec<String>) -> Result<Flags, String> {
+    let mut flags = Flags {
+        from: None,
+        to: None,
+        prefix: None,
+        limit: None,
+        key_format: Format::Escaped,
+        value_format: Format::Escaped,
+        create: false,
+        sync: false,
+    };
+    let mut raw_keys = vec![];
+    let mut positional = vec![];
+    let mut iter = args.drain(..);
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_ref() {
+            "--from" | "--to" | "--prefix" => raw_keys.push((arg.clone(), value(&arg)?)),
+            "--limit" => {
+                flags.limit = Some(value(&arg)?.parse().map_err(|_| "invalid --limit".to_string())?)
+            }
+            "--key-format" => flags.key_format = Format::parse(&value(&arg)?)?,
+            "--value-format" => flags.value_format = Format::parse(&value(&arg)?)?,
+            "--create" => flags.create = true,
+            "--sync" => flags.sync = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown flag: {}", arg)),
+            _ => positional.push(arg),
+        }
+    }
+    drop(iter);
+    for (name, raw) in raw_keys {
+        let key = Some(flags.key_format.decode(&raw)?);
+        match name.as_ref() {
+            "--from" => flags.from = key,
+            "--to" => flags.to = key,
+            _ => flags.prefix = key,
+        }
+    }
+    *args = positional;
+    Ok(flags)
+}
+
+fn open(path: &Path, flags: &Flags) -> Result<Database<ByteKey>, String> {
+    let mut options = Options::new();
+    options.create_if_missing = flags.create;
+    Database::open(path, options).map_err(|e| e.to_string())
+}
+
+fn write_options(flags: &Flags) -> WriteOptions {
+    let mut options = WriteOptions::new();
+    options.sync = flags.sync;
+    options
+}
+
+fn arg(args: &[String], index: usize, name: &str) -> Result<String, String> {
+    args.get(index).cloned().ok_or_else(|| format!("missing argument: {}", name))
+}
+
+/// Call `f` for every entry selected by the scan flags, until it returns
+/// `false`.
+fn scan<F>(database: &Database<ByteKey>, flags: &Flags, mut f: F) -> Result<(), String>
+    where F: FnMut(&[u8], &[u8]) -> io::Result<bool>
+{
+    let start = match (&flags.from, &flags.prefix) {
+        (&Some(ref from), &Some(ref prefix)) => ByteKey(from.clone().max(prefix.clone())),
+        (&Some(ref from), &None) => ByteKey(from.clone()),
+        (&None, &Some(ref prefix)) => ByteKey(prefix.clone()),
+        (&None, &None) => ByteKey::default(),
+    };
+    let mut iter = database.iter(ReadOptions::new()).from(&start);
+    while iter.advance() {
+        let (key, value) = iter.entry();
+        if flags.to.as_ref().map_or(false, |to| key.0 >= *to) {
+            break;
+        }
+        if flags.prefix.as_ref().map_or(false, |prefix| !key.starts_with(prefix)) {
+            break;
+        }
+        if !f(&key, &value).map_err(|e| e.to_string())? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn load<R: Read>(database: &Database<ByteKey>, flags: &Flags, input: R) -> Result<u64, String> {
+    let mut options = ImportOptions::new();
+    options.write_options = write_options(flags);
+    dump::import(database, input, &options, |_| ()).map_err(|e| e.to_string())
+}
+
+fn run(mut args: Vec<String>) -> Result<(), String> {
+    let flags = parse_flags(&mut args)?;
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let path = Path::new(&args[0]);
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    match args[1].as_ref() {
+        "get" => {
+            let database = open(path, &flags)?;
+            let key = flags.key_format.decode(&arg(&args, 2, "key")?)?;
+            match database.get(ReadOptions::new(), ByteKey(key)).map_err(|e| e.to_string())? {
+                Some(value) => writeln!(out, "{}", flags.value_format.encode(&value)),
+                None => return Err("not found".to_string()),
+            }
+            .map_err(|e| e.to_string())?;
+        }
+        "put" => {
+            let database = open(path, &flags)?;
+            let key = flags.key_format.decode(&arg(&args, 2, "key")?)?;
+            let value = flags.value_format.decode(&arg(&args, 3, "value")?)?;
+            database.put(write_options(&flags), ByteKey(key), &value).map_err(|e| e.to_string())?;
+        }
+        "delete" => {
+            let database = open(path, &flags)?;
+            let key = flags.key_format.decode(&arg(&args, 2, "key")?)?;
+            database.delete(write_options(&flags), ByteKey(key)).map_err(|e| e.to_string())?;
+        }
+        "scan" => {
+            let database = open(path, &flags)?;
+            let mut remaining = flags.limit.unwrap_or(usize::max_value());
+            scan(&database, &flags, |key, value| {
+                if remaining == 0 {
+                    return Ok(false);
+                }
+                remaining -= 1;
+                writeln!(out,
+                         "{}\t{}",
+                         flags.key_format.encode(key),
+                         flags.value_format.encode(value))?;
+                Ok(true)
+            })?;
+        }
+        "count" => {
+            let database = open(path, &flags)?;
+            let mut count = 0u64;
+            scan(&database, &flags, |_, _| {
+                count += 1;
+                Ok(true)
+            })?;
+            writeln!(out, "{}", count).map_err(|e| e.to_string())?;
+        }
+        "dump" => {
+            let database = open(path, &flags)?;
+            let target: Box<dyn Write> = match args.get(2) {
+                Some(file) => Box::new(BufWriter::new(File::create(file).map_err(|e| e.to_string())?)),
+                None => Box::new(out),
+            };
+            let header = DumpHeader::new(BYTEWISE_COMPARATOR, &Options::new());
+            let mut writer = DumpWriter::new(target, &header).map_err(|e| e.to_string())?;
+            scan(&database, &flags, |key, value| {
+                writer.write_entry(key, value)
+                    .map(|()| true)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+            })?;
+            writer.finish().map_err(|e| e.to_string())?;
+            return Ok(());
+        }
+        "load" => {
+            let database = open(path, &flags)?;
+            let loaded = match args.get(2) {
+                Some(file) => {
+                    let input = File::open(file).map_err(|e| e.to_string())?;
+                    load(&database, &flags, BufReader::new(input))?
+                }
+                None => {
+                    let stdin = io::stdin();
+                    let input = stdin.lock();
+                    load(&database, &flags, input)?
+                }
+            };
+            writeln!(out, "loaded {} entries", loaded).map_err(|e| e.to_string())?;
+        }
+        "compact" => {
+            let database = open(path, &flags)?;
+            if let Some(first) = database.keys_iter(ReadOptions::new()).next() {
+                if let Some(last) = database.keys_iter(ReadOptions::new()).last() {
+                    database.compact(&first, &last);
+                }
+            }
+        }
+        "repair" => management::repair(path, Options::new()).map_err(|e| e.to_string())?,
+        "destroy" => management::destroy(path, Options::new()).map_err(|e| e.to_string())?,
+        "stats" => {
+            let database = open(path, &flags)?;
+            for level in 0..7 {
+                let name = format!("leveldb.num-files-at-level{}", level);
+                if let Some(value) = database.property(&name) {
+                    writeln!(out, "{}: {}", name, value).map_err(|e| e.to_string())?;
+                }
+            }
+            for name in &["leveldb.approximate-memory-usage", "leveldb.stats", "leveldb.sstables"] {
+                if let Some(value) = database.property(name) {
+                    writeln!(out, "{}:\n{}", name, value.trim_end()).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        "manifest" => {
+            let version = VersionSet::recover(path).map_err(|e| e.to_string())?;
+            let format = flags.key_format;
+            let mut print = || -> io::Result<()> {
+                if let Some(ref comparator) = version.comparator {
+                    writeln!(out, "comparator: {}", comparator)?;
+                }
+                writeln!(out, "log number: {}", version.log_number)?;
+                writeln!(out, "next file number: {}", version.next_file_number)?;
+                writeln!(out, "last sequence: {}", version.last_sequence)?;
+                for (level, files) in version.levels().iter().enumerate() {
+                    writeln!(out, "level {}: {} files", level, files.len())?;
+                    for file in files {
+                        writeln!(out,
+                                 "    {:06} {} bytes [{} .. {}]",
+                                 file.number,
+                                 file.file_size,
+                                 format.encode(&file.smallest.user_key),
+                                 format.encode(&file.largest.user_key))?;
+                    }
+                }
+                Ok(())
+            };
+            print().map_err(|e| e.to_string())?;
+        }
+        "help" => writeln!(out, "{}", USAGE).map_err(|e| e.to_string())?,
+        command => return Err(format!("unknown command: {}\n\n{}", command, USAGE)),
+    }
+    out.flush().map_err(|e| e.to_string())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map_or(false, |a| a == "help" || a == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(message) = run(args) {
+        eprintln!("ldb: {}", message);
+        process::exit(1);
+    }
+}
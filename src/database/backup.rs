@@ -0,0 +1,266 @@
+//! Consistent online backups of a live database.
+//!
+//! A backup is taken by linking the table files of the open database into
+//! a staging directory, next to copies of its MANIFEST and logs. leveldb
+//! never changes a table file once written, and the logs are copied as a
+//! prefix of the writes, so the backup reflects a single point in time
+//! while writers continue. If a compaction replaces files while they are
+//! staged, staging starts over.
+//!
+//! The staged files are then moved into the backup directory's `shared`
+//! area, named by their checksum and size. Table files that are unchanged
+//! since an earlier backup are stored only once, so a backup only adds the
+//! tables written since the previous one, plus the MANIFEST and logs.
+//!
+//! Layout of a backup directory:
+//!
+//! * `shared/`: the content-addressed database files
+//! * `meta/<id>`: the list of files making up backup `<id>`
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::Database;
+use super::crc32c;
+use super::error::Error;
+use super::key::Key;
+use super::staging::stage;
+
+/// How often staging starts over while the database keeps changing.
+const MAX_ATTEMPTS: usize = 10;
+
+/// Manages the backups stored in one directory.
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+/// Describes a stored backup.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// The id of the backup, increasing with every backup taken.
+    pub id: u64,
+    /// When the backup was taken, in seconds since the epoch.
+    pub timestamp: u64,
+    /// The number of files in the backup.
+    pub files: usize,
+    /// The total size of the files in the backup.
+    pub size: u64,
+}
+
+struct BackupFile {
+    shared: String,
+    name: String,
+    size: u64,
+}
+
+fn error_for(action: &str, path: &Path, error: &::std::io::Error) -> Error {
+    Error::new(format!("{} {}: {}", action, path.display(), error))
+}
+
+fn checksum(path: &Path) -> Result<(u32, u64), Error> {
+    let mut file = File::open(path).map_err(|e| error_for("reading", path, &e))?;
+    let mut buf = vec![0; 64 << 10];
+    let mut crc = 0;
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf).map_err(|e| error_for("reading", path, &e))?;
+        if read == 0 {
+            return Ok((crc, size));
+        }
+        crc = crc32c::extend(crc, &buf[..read]);
+        size += read as u64;
+    }
+}
+
+impl BackupEngine {
+    /// Open the backup directory `dir`, creating it if needed.
+    pub fn open(dir: &Path) -> Result<BackupEngine, Error> {
+        for sub in &["shared", "meta"] {
+            let path = dir.join(sub);
+            fs::create_dir_all(&path).map_err(|e| error_for("creating", &path, &e))?;
+        }
+        Ok(BackupEngine { dir: dir.to_path_buf() })
+    }
+
+    /// Back up a database.
+    ///
+    /// The backup is restored with the files of the database, so it must
+    /// be opened with the comparator the database uses.
+    pub fn create_backup<K: Key>(&self, database: &Database<K>) -> Result<BackupInfo, Error> {
+        let id = self.list()?.last().map_or(1, |b| b.id + 1);
+        let staging = self.dir.join(format!("staging-{}", id));
+        let result = self.stage_files(database.path(), &staging)
+            .and_then(|_| self.store(id, &staging));
+        let _ = fs::remove_dir_all(&staging);
+        result
+    }
+
+    fn stage_files(&self, dir: &Path, staging: &Path) -> Result<(), Error> {
+        for _ in 0..MAX_ATTEMPTS {
+            if staging.exists() {
+                fs::remove_dir_all(staging).map_err(|e| error_for("removing", staging, &e))?;
+            }
+            fs::create_dir_all(staging).map_err(|e| error_for("creating", staging, &e))?;
+            if stage(dir, staging)? {
+                return Ok(());
+            }
+        }
+        Err(Error::new(format!("{} kept changing while it was backed up", dir.display())))
+    }
+
+    fn store(&self, id: u64, staging: &Path) -> Result<BackupInfo, Error> {
+        let mut files = vec![];
+        let entries = fs::read_dir(staging).map_err(|e| error_for("reading", staging, &e))?;
+        for entry in entries {
+            let path = entry?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let (crc, size) = checksum(&path)?;
+            let shared = format!("{:08x}-{}-{}", crc, size, name);
+            let target = self.dir.join("shared").join(&shared);
+            if !target.exists() {
+                fs::rename(&path, &target).map_err(|e| error_for("moving", &path, &e))?;
+            }
+            files.push(BackupFile { shared, name, size });
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut meta = format!("timestamp {}\n", timestamp);
+        for file in &files {
+            meta.push_str(&format!("file {} {} {}\n", file.shared, file.size, file.name));
+        }
+        let path = self.meta_path(id);
+        let partial = path.with_extension("tmp");
+        {
+            let mut out = File::create(&partial).map_err(|e| error_for("creating", &partial, &e))?;
+            out.write_all(meta.as_bytes())?;
+            out.sync_all()?;
+        }
+        fs::rename(&partial, &path).map_err(|e| error_for("moving", &partial, &e))?;
+
+        Ok(BackupInfo {
+            id,
+            timestamp,
+            files: files.len(),
+            size: files.iter().map(|f| f.size).sum(),
+        })
+    }
+
+    fn meta_path(&self, id: u64) -> PathBuf {
+        self.dir.join("meta").join(id.to_string())
+    }
+
+    fn read_meta(&self, id: u64) -> Result<(u64, Vec<BackupFile>), Error> {
+        let path = self.meta_path(id);
+        let file = File::open(&path).map_err(|e| error_for("reading", &path, &e))?;
+        let mut timestamp = 0;
+        let mut files = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.splitn(4, ' ').collect();
+            match fields[..] {
+                ["timestamp", value] => timestamp = value.parse().unwrap_or(0),
+                ["file", shared, size, name] => {
+                    files.push(BackupFile {
+                        shared: shared.to_string(),
+                        name: name.to_string(),
+                        size: size.parse().unwrap_or(0),
+                    })
+                }
+                _ => return Err(Error::new(format!("corrupt backup metadata {}", path.display()))),
+            }
+        }
+        Ok((timestamp, files))
+    }
+
+    /// List all backups, oldest first.
+    pub fn list(&self) -> Result<Vec<BackupInfo>, Error> {
+        let meta = self.dir.join("meta");
+        let mut ids = vec![];
+        for entry in fs::read_dir(&meta).map_err(|e| error_for("reading", &meta, &e))? {
+            if let Some(id) = entry?.file_name().to_str().and_then(|n| n.parse::<u64>().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        ids.into_iter()
+            .map(|id| {
+                let (timestamp, files) = self.read_meta(id)?;
+                Ok(BackupInfo {
+                    id,
+                    timestamp,
+                    files: files.len(),
+                    size: files.iter().map(|f| f.size).sum(),
+                })
+            })
+            .collect()
+    }
+
+    /// Delete the backup `id`.
+    pub fn delete_backup(&self, id: u64) -> Result<(), Error> {
+        let path = self.meta_path(id);
+        fs::remove_file(&path).map_err(|e| error_for("removing", &path, &e))?;
+        self.collect_garbage()
+    }
+
+    /// Delete all but the `keep` most recent backups.
+    ///
+    /// Returns the number of backups deleted.
+    pub fn purge_old_backups(&self, keep: usize) -> Result<usize, Error> {
+        let backups = self.list()?;
+        let purge = backups.len().saturating_sub(keep);
+        for backup in &backups[..purge] {
+            let path = self.meta_path(backup.id);
+            fs::remove_file(&path).map_err(|e| error_for("removing", &path, &e))?;
+        }
+        self.collect_garbage()?;
+        Ok(purge)
+    }
+
+    fn collect_garbage(&self) -> Result<(), Error> {
+        let mut referenced = HashSet::new();
+        for backup in self.list()? {
+            for file in self.read_meta(backup.id)?.1 {
+                referenced.insert(file.shared);
+            }
+        }
+        let shared = self.dir.join("shared");
+        for entry in fs::read_dir(&shared).map_err(|e| error_for("reading", &shared, &e))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !referenced.contains(&name) {
+                fs::remove_file(entry.path()).map_err(|e| error_for("removing", &entry.path(), &e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restore backup `id` into the directory `target`.
+    ///
+    /// `target` must not exist yet. The restored database is opened like
+    /// any other, using the comparator the backup was taken with.
+    pub fn restore(&self, id: u64, target: &Path) -> Result<(), Error> {
+        if target.exists() {
+            return Err(Error::new(format!("restore target {} exists", target.display())));
+        }
+        let (_, files) = self.read_meta(id)?;
+        fs::create_dir_all(target).map_err(|e| error_for("creating", target, &e))?;
+        for file in files {
+            let source = self.dir.join("shared").join(&file.shared);
+            fs::copy(&source, target.join(&file.name)).map_err(|e| error_for("copying", &source, &e))?;
+        }
+        Ok(())
+    }
+
+    /// Restore the most recent backup into the directory `target`.
+    pub fn restore_latest(&self, target: &Path) -> Result<(), Error> {
+        match self.list()?.last() {
+            Some(backup) => self.restore(backup.id, target),
+            None => Err(Error::new("no backups to restore".to_string())),
+        }
+    }
+}
@@ -6,10 +6,12 @@ use std::marker::PhantomData;
 use database::key::Key;
 use database::key::from_u8;
 use std::slice;
-use options::{WriteOptions, c_writeoptions};
+use options::WriteOptions;
 use super::error::Error;
 use std::ptr;
 use super::Database;
+use super::changes::batch_changes;
+use super::operands::Op;
 
 #[allow(missing_docs)]
 struct RawWritebatch {
@@ -28,6 +30,9 @@ impl Drop for RawWritebatch {
 pub struct Writebatch<K: Key> {
     #[allow(dead_code)]
     writebatch: RawWritebatch,
+    // for every operation in the batch, whether it is a merge; leveldb
+    // stores merges as puts
+    merges: Vec<bool>,
     marker: PhantomData<K>,
 }
 
@@ -39,22 +44,36 @@ pub trait Batch<K: Key> {
 
 impl<K: Key> Batch<K> for Database<K> {
     fn write(&self, options: WriteOptions, batch: &Writebatch<K>) -> Result<(), Error> {
-        unsafe {
-            let mut error = ptr::null_mut();
-            let c_writeoptions = c_writeoptions(options);
-
-            leveldb_write(self.database.ptr,
-                          c_writeoptions,
-                          batch.writebatch.ptr,
-                          &mut error);
-            leveldb_writeoptions_destroy(c_writeoptions);
-
-            if error == ptr::null_mut() {
-                Ok(())
-            } else {
-                Err(Error::new_from_char(error))
+        self.changes.publish(|| {
+            if let Some(ref operands) = self.operands {
+                let ops = batch.iterate(Box::new(Ops { ops: vec![], marker: PhantomData })).ops;
+                let ops: Vec<_> = ops.iter()
+                    .map(|(key, value)| match *value {
+                        Some((false, ref value)) => Op::Put(key, value),
+                        Some((true, ref operand)) => Op::Merge(key, operand),
+                        None => Op::Delete(key),
+                    })
+                    .collect();
+                return unsafe { operands.write(self.database.ptr, self.writeoptions(options).raw_ptr(), &ops) };
             }
-        }
+            if batch.merges.contains(&true) {
+                return Err(Error::new("the database has no merge operator".to_string()));
+            }
+            unsafe {
+                let mut error = ptr::null_mut();
+
+                leveldb_write(self.database.ptr,
+                              self.writeoptions(options).raw_ptr(),
+                              batch.writebatch.ptr,
+                              &mut error);
+
+                if error == ptr::null_mut() {
+                    Ok(())
+                } else {
+                    Err(Error::new_from_char(error))
+                }
+            }
+        }, || batch_changes(batch))
     }
 }
 
@@ -65,6 +84,7 @@ impl<K: Key> Writebatch<K> {
         let raw = RawWritebatch { ptr: ptr };
         Writebatch {
             writebatch: raw,
+            merges: vec![],
             marker: PhantomData,
         }
     }
@@ -72,6 +92,7 @@ impl<K: Key> Writebatch<K> {
     /// Clear the writebatch
     pub fn clear(&mut self) {
         unsafe { leveldb_writebatch_clear(self.writebatch.ptr) };
+        self.merges.clear();
     }
 
     /// Batch a put operation
@@ -85,6 +106,7 @@ impl<K: Key> Writebatch<K> {
                                        value.len() as size_t);
             })
         }
+        self.merges.push(false);
     }
 
     /// Batch a delete operation
@@ -96,21 +118,51 @@ impl<K: Key> Writebatch<K> {
                                           k.len() as size_t);
             })
         }
+        self.merges.push(false);
+    }
+
+    /// Batch a merge operand for `key`.
+    ///
+    /// Writing the batch fails unless the database has a merge operator.
+    pub fn merge(&mut self, key: K, operand: &[u8]) {
+        self.put(key, operand);
+        *self.merges.last_mut().unwrap() = true;
     }
 
     /// Iterate over the writebatch, returning the resulting iterator
-    pub fn iterate<T: WritebatchIterator<K = K>>(&mut self, iterator: Box<T>) -> Box<T> {
+    pub fn iterate<T: WritebatchIterator<K = K>>(&self, iterator: Box<T>) -> Box<T> {
         unsafe {
-            let iter = Box::into_raw(iterator);
+            let dispatch = Box::into_raw(Box::new(Dispatch {
+                iterator,
+                merges: &self.merges,
+                index: 0,
+            }));
             leveldb_writebatch_iterate(self.writebatch.ptr,
-                                       iter as *mut c_void,
+                                       dispatch as *mut c_void,
                                        put_callback::<K, T>,
                                        deleted_callback::<K, T>);
-            Box::from_raw(iter)
+            Box::from_raw(dispatch).iterator
         }
     }
 }
 
+/// The state passed to the iteration callbacks.
+struct Dispatch<'b, T> {
+    iterator: Box<T>,
+    merges: &'b [bool],
+    index: usize,
+}
+
+/// A key, and for puts and merges the value along with whether it is an
+/// operand.
+type BatchOp = (Vec<u8>, Option<(bool, Vec<u8>)>);
+
+/// Collects the operations of a batch, for databases with a merge operator.
+struct Ops<K: Key> {
+    ops: Vec<BatchOp>,
+    marker: PhantomData<K>,
+}
+
 /// A trait for iterators to iterate over written batches and check their validity.
 pub trait WritebatchIterator {
     /// The database key type this iterates over
@@ -121,6 +173,27 @@ pub trait WritebatchIterator {
 
     /// Callback for deleted items
     fn deleted(&mut self, key: Self::K);
+
+    /// Callback for merge operands.
+    ///
+    /// The default implementation ignores them.
+    fn merge(&mut self, _key: Self::K, _operand: &[u8]) {}
+}
+
+impl<K: Key> WritebatchIterator for Ops<K> {
+    type K = K;
+
+    fn put(&mut self, key: K, value: &[u8]) {
+        self.ops.push((key.as_slice(|k| k.to_vec()), Some((false, value.to_vec()))));
+    }
+
+    fn deleted(&mut self, key: K) {
+        self.ops.push((key.as_slice(|k| k.to_vec()), None));
+    }
+
+    fn merge(&mut self, key: K, operand: &[u8]) {
+        self.ops.push((key.as_slice(|k| k.to_vec()), Some((true, operand.to_vec()))));
+    }
 }
 
 extern "C" fn put_callback<K: Key, T: WritebatchIterator<K = K>>(state: *mut c_void,
@@ -129,11 +202,17 @@ extern "C" fn put_callback<K: Key, T: WritebatchIterator<K = K>>(state: *mut c_v
                                                                  val: *const c_char,
                                                                  vallen: size_t) {
     unsafe {
-        let iter: &mut T = &mut *(state as *mut T);
+        let dispatch: &mut Dispatch<T> = &mut *(state as *mut Dispatch<T>);
         let key_slice = slice::from_raw_parts::<u8>(key as *const u8, keylen as usize);
         let val_slice = slice::from_raw_parts::<u8>(val as *const u8, vallen as usize);
         let k = from_u8::<<T as WritebatchIterator>::K>(key_slice);
-        iter.put(k, val_slice);
+        let merge = dispatch.merges.get(dispatch.index) == Some(&true);
+        dispatch.index += 1;
+        if merge {
+            dispatch.iterator.merge(k, val_slice);
+        } else {
+            dispatch.iterator.put(k, val_slice);
+        }
     }
 }
 
@@ -141,9 +220,10 @@ extern "C" fn deleted_callback<K: Key, T: WritebatchIterator<K = K>>(state: *mut
                                                                      key: *const c_char,
                                                                      keylen: size_t) {
     unsafe {
-        let iter: &mut T = &mut *(state as *mut T);
+        let dispatch: &mut Dispatch<T> = &mut *(state as *mut Dispatch<T>);
         let key_slice = slice::from_raw_parts::<u8>(key as *const u8, keylen as usize);
         let k = from_u8::<<T as WritebatchIterator>::K>(key_slice);
-        iter.deleted(k);
+        dispatch.index += 1;
+        dispatch.iterator.deleted(k);
     }
 }
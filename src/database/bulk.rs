@@ -0,0 +1,366 @@
+//! Loading large amounts of data into a database.
+//!
+//! `BulkLoad::bulk_load` writes a stream of entries through large, unsynced
+//! batches. Unsorted input is sorted first: entries are buffered in memory
+//! and spilled to sorted run files in a temporary directory, which are
+//! merged while loading. Writing in key order keeps leveldb's compactions
+//! cheap.
+//!
+//! While loading, the number of table files at level 0 is watched through
+//! the `leveldb.num-files-at-level0` property. Loading pauses while it is
+//! high, instead of letting leveldb stall every write, and fails if it
+//! stays high for longer than `throttle_timeout`. When all entries
+//! are written, the loaded range is compacted and the log is synced.
+//!
+//! Entries are sorted by the binary value of their keys. For a key given
+//! more than once, the last value wins.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::coding::{decode_fixed32, put_fixed32};
+use super::compaction::Compaction;
+use super::error::Error;
+use super::key::Key;
+use super::properties::Properties;
+use leveldb_sys::Compression;
+use options::{Options, WriteOptions};
+
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Options for loading entries with `BulkLoad`.
+pub struct BulkLoadOptions {
+    /// Whether the entries are already sorted by key, so no sorting is
+    /// needed.
+    ///
+    /// default: false
+    pub sorted: bool,
+    /// The number of bytes after which a batch is written.
+    ///
+    /// default: 4MB
+    pub max_batch_bytes: usize,
+    /// The number of bytes of entries sorted in memory before they are
+    /// spilled to a run file.
+    ///
+    /// default: 64MB
+    pub max_run_bytes: usize,
+    /// Where run files are created.
+    ///
+    /// default: None (the system's temporary directory)
+    pub temp_dir: Option<PathBuf>,
+    /// Pause loading while level 0 holds at least this many files.
+    ///
+    /// leveldb slows writes down at 8 files and stops them at 12.
+    ///
+    /// default: 8
+    pub max_level0_files: usize,
+    /// How long to pause before checking level 0 again.
+    ///
+    /// default: 10ms
+    pub throttle_interval: Duration,
+    /// How long to wait for level 0 to shrink before giving up on the
+    /// load with an error.
+    ///
+    /// default: 60s
+    pub throttle_timeout: Duration,
+    /// Whether to compact the loaded range when done.
+    ///
+    /// default: true
+    pub compact: bool,
+}
+
+/// Loading of many entries at once.
+pub trait BulkLoad<K: Key> {
+    /// Write all `entries` to the database.
+    ///
+    /// Returns the number of entries written.
+    fn bulk_load<I>(&self, options: BulkLoadOptions, entries: I) -> Result<usize, Error>
+        where I: IntoIterator<Item = (K, Vec<u8>)>;
+}
+
+impl BulkLoadOptions {
+    /// Return a `BulkLoadOptions` struct with the default values.
+    pub fn new() -> BulkLoadOptions {
+        BulkLoadOptions {
+            sorted: false,
+            max_batch_bytes: 4 << 20,
+            max_run_bytes: 64 << 20,
+            temp_dir: None,
+            max_level0_files: 8,
+            throttle_interval: Duration::from_millis(10),
+            throttle_timeout: Duration::from_secs(60),
+            compact: true,
+        }
+    }
+}
+
+/// Return `Options` suited to opening a database for a bulk load.
+///
+/// The write buffer is enlarged, so fewer and larger files are flushed to
+/// level 0.
+pub fn bulk_load_options() -> Options {
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    options.write_buffer_size = Some(64 << 20);
+    options.max_open_files = Some(1000);
+    options.block_size = Some(64 << 10);
+    options.compression = Compression::Snappy;
+    options
+}
+
+fn error_for(action: &str, path: &Path, e: &io::Error) -> Error {
+    Error::new(format!("{} {}: {}", action, path.display(), e))
+}
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// A run file of entries sorted by key.
+struct Run {
+    path: PathBuf,
+    reader: BufReader<File>,
+}
+
+impl Run {
+    fn write(path: PathBuf, entries: &[Entry]) -> Result<Run, Error> {
+        {
+            let file = File::create(&path).map_err(|e| error_for("creating", &path, &e))?;
+            let mut writer = BufWriter::new(file);
+            let mut header = Vec::with_capacity(8);
+            for (key, value) in entries {
+                header.clear();
+                put_fixed32(&mut header, key.len() as u32);
+                put_fixed32(&mut header, value.len() as u32);
+                writer.write_all(&header)
+                    .and_then(|_| writer.write_all(key))
+                    .and_then(|_| writer.write_all(value))
+                    .map_err(|e| error_for("writing", &path, &e))?;
+            }
+            writer.flush().map_err(|e| error_for("writing", &path, &e))?;
+        }
+        let file = File::open(&path).map_err(|e| error_for("opening", &path, &e))?;
+        Ok(Run {
+            path,
+            reader: BufReader::new(file),
+        })
+    }
+
+    fn next(&mut self) -> Result<Option<Entry>, Error> {
+        let mut header = [0; 8];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(error_for("reading", &self.path, &e)),
+        }
+        let mut key = vec![0; decode_fixed32(&header[..4]) as usize];
+        let mut value = vec![0; decode_fixed32(&header[4..]) as usize];
+        self.reader
+            .read_exact(&mut key)
+            .and_then(|_| self.reader.read_exact(&mut value))
+            .map_err(|e| error_for("reading", &self.path, &e))?;
+        Ok(Some((key, value)))
+    }
+}
+
+/// The head of a run during the merge. Heads are ordered by key and then
+/// by run, so later values of a key are written last.
+struct Head {
+    entry: Entry,
+    run: usize,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Head) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Head) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    // reversed, as `BinaryHeap` is a max-heap
+    fn cmp(&self, other: &Head) -> Ordering {
+        (&other.entry.0, other.run).cmp(&(&self.entry.0, self.run))
+    }
+}
+
+/// Removes the run files when dropped.
+struct Runs {
+    dir: PathBuf,
+    runs: Vec<Run>,
+}
+
+impl Runs {
+    fn new(options: &BulkLoadOptions) -> Runs {
+        let base = options.temp_dir.clone().unwrap_or_else(env::temp_dir);
+        let id = RUNS.fetch_add(1, AtomicOrdering::SeqCst);
+        Runs {
+            dir: base.join(format!("leveldb-bulk-{}-{}", process::id(), id)),
+            runs: vec![],
+        }
+    }
+
+    fn spill(&mut self, entries: &mut Vec<Entry>) -> Result<(), Error> {
+        if self.runs.is_empty() {
+            fs::create_dir_all(&self.dir).map_err(|e| error_for("creating", &self.dir, &e))?;
+        }
+        let path = self.dir.join(format!("{:06}.run", self.runs.len()));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        self.runs.push(Run::write(path, entries)?);
+        entries.clear();
+        Ok(())
+    }
+
+    fn merge<F>(&mut self, mut emit: F) -> Result<(), Error>
+        where F: FnMut(Entry) -> Result<(), Error>
+    {
+        let mut heap = BinaryHeap::new();
+        for (i, run) in self.runs.iter_mut().enumerate() {
+            if let Some(entry) = run.next()? {
+                heap.push(Head { entry, run: i });
+            }
+        }
+        while let Some(Head { entry, run }) = heap.pop() {
+            if let Some(next) = self.runs[run].next()? {
+                heap.push(Head { entry: next, run });
+            }
+            emit(entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        if !self.runs.is_empty() {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+struct Loader<'a, K: Key + 'a> {
+    database: &'a Database<K>,
+    options: &'a BulkLoadOptions,
+    batch: Writebatch<K>,
+    batch_bytes: usize,
+    written: usize,
+    smallest: Option<Vec<u8>>,
+    largest: Option<Vec<u8>>,
+}
+
+impl<'a, K: Key + 'a> Loader<'a, K> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if !matches!(self.smallest, Some(ref s) if key >= &s[..]) {
+            self.smallest = Some(key.to_vec());
+        }
+        if !matches!(self.largest, Some(ref l) if key <= &l[..]) {
+            self.largest = Some(key.to_vec());
+        }
+        self.batch.put(K::from_u8(key), value);
+        self.batch_bytes += key.len() + value.len();
+        self.written += 1;
+        if self.batch_bytes >= self.options.max_batch_bytes {
+            self.throttle()?;
+            self.database.write(WriteOptions::new(), &self.batch)?;
+            self.batch.clear();
+            self.batch_bytes = 0;
+        }
+        Ok(())
+    }
+
+    fn throttle(&self) -> Result<(), Error> {
+        let level0 = |db: &Database<K>| {
+            db.property("leveldb.num-files-at-level0")
+                .and_then(|n| n.trim().parse::<usize>().ok())
+                .unwrap_or(0)
+        };
+        let deadline = Instant::now() + self.options.throttle_timeout;
+        loop {
+            let files = level0(self.database);
+            if files < self.options.max_level0_files {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(format!("level 0 still holds {} files after {:?}",
+                                              files,
+                                              self.options.throttle_timeout)));
+            }
+            thread::sleep(self.options.throttle_interval);
+        }
+    }
+
+    fn finish(self) -> Result<usize, Error> {
+        let mut sync = WriteOptions::new();
+        sync.sync = true;
+        self.database.write(sync, &self.batch)?;
+        if self.options.compact {
+            if let (Some(smallest), Some(largest)) = (self.smallest, self.largest) {
+                self.database.compact(&K::from_u8(&smallest), &K::from_u8(&largest));
+            }
+        }
+        Ok(self.written)
+    }
+}
+
+impl<K: Key> BulkLoad<K> for Database<K> {
+    fn bulk_load<I>(&self, options: BulkLoadOptions, entries: I) -> Result<usize, Error>
+        where I: IntoIterator<Item = (K, Vec<u8>)>
+    {
+        let mut loader = Loader {
+            database: self,
+            options: &options,
+            batch: Writebatch::new(),
+            batch_bytes: 0,
+            written: 0,
+            smallest: None,
+            largest: None,
+        };
+
+        if options.sorted {
+            for (key, value) in entries {
+                loader.put(&key.as_slice(|k| k.to_vec()), &value)?;
+            }
+            return loader.finish();
+        }
+
+        let mut runs = Runs::new(&options);
+        let mut buffer = vec![];
+        let mut buffered = 0;
+        for (key, value) in entries {
+            let key = key.as_slice(|k| k.to_vec());
+            buffered += key.len() + value.len();
+            buffer.push((key, value));
+            if buffered >= options.max_run_bytes {
+                runs.spill(&mut buffer)?;
+                buffered = 0;
+            }
+        }
+        if runs.runs.is_empty() {
+            // everything fit into memory
+            buffer.sort_by(|a, b| a.0.cmp(&b.0));
+            for (key, value) in buffer {
+                loader.put(&key, &value)?;
+            }
+        } else {
+            if !buffer.is_empty() {
+                runs.spill(&mut buffer)?;
+            }
+            runs.merge(|(key, value)| loader.put(&key, &value))?;
+        }
+        loader.finish()
+    }
+}
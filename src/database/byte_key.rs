@@ -0,0 +1,67 @@
+//! A key type for arbitrary byte strings.
+use std::ops::Deref;
+
+use super::key::Key;
+
+/// A key holding an arbitrary byte string.
+///
+/// `ByteKey` orders by the binary value of the key, like leveldb's default
+/// comparator. It is the key type to use when keys are composed of other
+/// keys, e.g. prefixed by a `Keyspace`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteKey(pub Vec<u8>);
+
+impl ByteKey {
+    /// Create a key from a byte slice.
+    pub fn new(bytes: &[u8]) -> ByteKey {
+        ByteKey(bytes.to_vec())
+    }
+}
+
+impl Key for ByteKey {
+    fn from_u8(key: &[u8]) -> ByteKey {
+        ByteKey::new(key)
+    }
+
+    fn as_slice<T, F: Fn(&[u8]) -> T>(&self, f: F) -> T {
+        f(&self.0)
+    }
+}
+
+impl Deref for ByteKey {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for ByteKey {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for ByteKey {
+    fn from(bytes: Vec<u8>) -> ByteKey {
+        ByteKey(bytes)
+    }
+}
+
+impl<'a> From<&'a [u8]> for ByteKey {
+    fn from(bytes: &'a [u8]) -> ByteKey {
+        ByteKey::new(bytes)
+    }
+}
+
+impl<'a> From<&'a str> for ByteKey {
+    fn from(string: &'a str) -> ByteKey {
+        ByteKey::new(string.as_bytes())
+    }
+}
+
+impl From<ByteKey> for Vec<u8> {
+    fn from(key: ByteKey) -> Vec<u8> {
+        key.0
+    }
+}
@@ -0,0 +1,315 @@
+//! Change-data capture for writes through a `Database` handle.
+//!
+//! Every `KV::put`, `KV::delete`, `Merge::merge` and `Batch::write`
+//! performed through a database handle is published to its subscribers as
+//! a `ChangeEvent`, after leveldb has committed it. Events carry a
+//! sequence number that increases by one with every write, and are
+//! delivered in commit order.
+//!
+//! Each subscription buffers a bounded number of events. What happens
+//! when that buffer is full is decided by its `SlowSubscriberPolicy`.
+//!
+//! Writes performed through other handles or processes are not captured.
+//! `Database::subscribe` waits for writes in flight, so every write either
+//! commits before the subscription exists or is delivered to it.
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use super::Database;
+use super::batch::{Writebatch, WritebatchIterator};
+use super::error::Error;
+use super::key::Key;
+use super::snapshots::{Snapshot, Snapshots};
+
+use std::marker::PhantomData;
+
+/// A single mutation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// A key was set to a value.
+    Put {
+        /// The key written.
+        key: Vec<u8>,
+        /// The value written.
+        value: Vec<u8>,
+    },
+    /// A key was deleted.
+    Delete {
+        /// The key deleted.
+        key: Vec<u8>,
+    },
+    /// A merge operand was recorded for a key.
+    Merge {
+        /// The key merged into.
+        key: Vec<u8>,
+        /// The operand recorded.
+        operand: Vec<u8>,
+    },
+}
+
+/// The mutations of one committed write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChangeEvent {
+    /// The number of the write, increasing by one for every write.
+    pub sequence: u64,
+    /// The mutations of the write, in batch order.
+    pub changes: Vec<Change>,
+}
+
+/// What to do when a subscriber's buffer is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowSubscriberPolicy {
+    /// Block the writer until the subscriber catches up.
+    ///
+    /// This stalls all writes through the database handle.
+    Block,
+    /// Drop the event for this subscriber, counting it in
+    /// `Subscription::dropped`.
+    DropNewest,
+    /// Stop delivering events to the subscriber. It receives the events
+    /// buffered so far, then sees the subscription as disconnected.
+    Disconnect,
+}
+
+struct Subscriber {
+    id: u64,
+    sender: SyncSender<ChangeEvent>,
+    policy: SlowSubscriberPolicy,
+    dropped: Arc<AtomicUsize>,
+    lagged: Arc<AtomicBool>,
+}
+
+struct FeedState {
+    sequence: u64,
+    next_id: u64,
+    subscribers: Vec<Subscriber>,
+}
+
+/// The subscribers of a database handle.
+pub struct ChangeFeed {
+    active: AtomicBool,
+    // held shared by unpublished writes, so subscribing can wait for them
+    writers: RwLock<()>,
+    // held while publishing, so events are delivered in commit order
+    delivery: Mutex<()>,
+    state: Mutex<FeedState>,
+}
+
+/// A subscription to the writes of a database handle.
+///
+/// Dropping the subscription unsubscribes.
+pub struct Subscription {
+    receiver: Receiver<ChangeEvent>,
+    first_sequence: u64,
+    dropped: Arc<AtomicUsize>,
+    lagged: Arc<AtomicBool>,
+}
+
+struct Collector<K: Key> {
+    changes: Vec<Change>,
+    marker: PhantomData<K>,
+}
+
+impl<K: Key> WritebatchIterator for Collector<K> {
+    type K = K;
+
+    fn put(&mut self, key: K, value: &[u8]) {
+        self.changes.push(Change::Put {
+            key: key.as_slice(|k| k.to_vec()),
+            value: value.to_vec(),
+        });
+    }
+
+    fn deleted(&mut self, key: K) {
+        self.changes.push(Change::Delete { key: key.as_slice(|k| k.to_vec()) });
+    }
+
+    fn merge(&mut self, key: K, operand: &[u8]) {
+        self.changes.push(Change::Merge {
+            key: key.as_slice(|k| k.to_vec()),
+            operand: operand.to_vec(),
+        });
+    }
+}
+
+/// Collect the mutations of a batch.
+pub fn batch_changes<K: Key>(batch: &Writebatch<K>) -> Vec<Change> {
+    let collector = Collector {
+        changes: vec![],
+        marker: PhantomData,
+    };
+    batch.iterate(Box::new(collector)).changes
+}
+
+impl ChangeFeed {
+    /// Create a feed without subscribers.
+    pub fn new() -> ChangeFeed {
+        ChangeFeed {
+            active: AtomicBool::new(false),
+            writers: RwLock::new(()),
+            delivery: Mutex::new(()),
+            state: Mutex::new(FeedState {
+                sequence: 0,
+                next_id: 0,
+                subscribers: vec![],
+            }),
+        }
+    }
+
+    /// Perform `write` and publish the changes it made.
+    ///
+    /// `changes` is only called if the write succeeded and there are
+    /// subscribers. Writes are serialised while there are subscribers, so
+    /// events are published in commit order.
+    pub fn publish<W, C>(&self, write: W, changes: C) -> Result<(), Error>
+        where W: FnOnce() -> Result<(), Error>,
+              C: FnOnce() -> Vec<Change>
+    {
+        {
+            let _writer = self.writers.read().unwrap();
+            if !self.active.load(Ordering::Acquire) {
+                return write();
+            }
+        }
+        let _delivery = self.delivery.lock().unwrap();
+        let mut blocking = vec![];
+        let event = {
+            let mut state = self.state.lock().unwrap();
+            write()?;
+            state.sequence += 1;
+            let event = ChangeEvent {
+                sequence: state.sequence,
+                changes: changes(),
+            };
+            state.subscribers.retain(|subscriber| {
+                let result = match subscriber.policy {
+                    SlowSubscriberPolicy::Block => {
+                        // sent once the state is unlocked
+                        blocking.push((subscriber.id, subscriber.sender.clone()));
+                        return true;
+                    }
+                    _ => subscriber.sender.try_send(event.clone()),
+                };
+                match result {
+                    Ok(()) => true,
+                    Err(TrySendError::Full(_)) if subscriber.policy == SlowSubscriberPolicy::DropNewest => {
+                        subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+                        true
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        subscriber.lagged.store(true, Ordering::Release);
+                        false
+                    }
+                    Err(TrySendError::Disconnected(_)) => false,
+                }
+            });
+            self.deactivate_if_idle(&state);
+            event
+        };
+
+        let gone: Vec<u64> = blocking.into_iter()
+            .filter(|(_, sender)| sender.send(event.clone()).is_err())
+            .map(|(id, _)| id)
+            .collect();
+        if !gone.is_empty() {
+            let mut state = self.state.lock().unwrap();
+            state.subscribers.retain(|subscriber| !gone.contains(&subscriber.id));
+            self.deactivate_if_idle(&state);
+        }
+        Ok(())
+    }
+
+    fn deactivate_if_idle(&self, state: &FeedState) {
+        if state.subscribers.is_empty() {
+            self.active.store(false, Ordering::Release);
+        }
+    }
+
+    fn subscribe(&self, capacity: usize, policy: SlowSubscriberPolicy) -> Subscription {
+        let (sender, receiver) = sync_channel(capacity);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let lagged = Arc::new(AtomicBool::new(false));
+        // wait for writes that started without publishing
+        let _writers = self.writers.write().unwrap();
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.subscribers.push(Subscriber {
+            id,
+            sender,
+            policy,
+            dropped: dropped.clone(),
+            lagged: lagged.clone(),
+        });
+        self.active.store(true, Ordering::Release);
+        Subscription {
+            receiver,
+            first_sequence: state.sequence + 1,
+            dropped,
+            lagged,
+        }
+    }
+}
+
+impl<K: Key> Database<K> {
+    /// Subscribe to all writes through this handle.
+    ///
+    /// Up to `capacity` events are buffered for the subscription; `policy`
+    /// decides what happens to further events while the buffer is full.
+    pub fn subscribe(&self, capacity: usize, policy: SlowSubscriberPolicy) -> Subscription {
+        self.changes.subscribe(capacity, policy)
+    }
+
+    /// Take a snapshot, along with the sequence number of the last write
+    /// published before it.
+    ///
+    /// The snapshot contains exactly the writes up to that sequence number
+    /// among those published to subscribers.
+    pub fn snapshot_with_sequence(&self) -> (Snapshot<'_, K>, u64) {
+        let state = self.changes.state.lock().unwrap();
+        (self.snapshot(), state.sequence)
+    }
+}
+
+impl Subscription {
+    /// The sequence number of the first event delivered to this
+    /// subscription.
+    pub fn first_sequence(&self) -> u64 {
+        self.first_sequence
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Returns `None` once the database handle is dropped, or the
+    /// subscription was disconnected for being too slow.
+    pub fn recv(&self) -> Option<ChangeEvent> {
+        self.receiver.recv().ok()
+    }
+
+    /// Return the next event if one is buffered.
+    pub fn try_recv(&self) -> Option<ChangeEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next event.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Iterate over events as they arrive, until the subscription ends.
+    pub fn iter(&self) -> impl Iterator<Item = ChangeEvent> + '_ {
+        self.receiver.iter()
+    }
+
+    /// The number of events dropped under `SlowSubscriberPolicy::DropNewest`.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Whether the subscription was disconnected for being too slow.
+    pub fn is_disconnected(&self) -> bool {
+        self.lagged.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,71 @@
+//! Encoding helpers shared by the modules that lay out bytes themselves.
+//!
+//! Varints follow leveldb's own encoding (little-endian, 7 bits per byte).
+
+/// Append `value` as a varint.
+pub fn put_varint64(dst: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        dst.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    dst.push(value as u8);
+}
+
+/// Append a length-prefixed byte string.
+pub fn put_length_prefixed(dst: &mut Vec<u8>, value: &[u8]) {
+    put_varint64(dst, value.len() as u64);
+    dst.extend_from_slice(value);
+}
+
+/// Read a varint from the front of `src`, advancing it.
+pub fn get_varint64(src: &mut &[u8]) -> Option<u64> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    for (i, &byte) in src.iter().enumerate() {
+        if shift > 63 {
+            return None;
+        }
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            *src = &src[i + 1..];
+            return Some(result);
+        }
+        shift += 7;
+    }
+    None
+}
+
+/// Read a length-prefixed byte string from the front of `src`, advancing it.
+pub fn get_length_prefixed<'a>(src: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = get_varint64(src)? as usize;
+    if src.len() < len {
+        return None;
+    }
+    let (value, rest) = src.split_at(len);
+    *src = rest;
+    Some(value)
+}
+
+/// Append `value` as four little-endian bytes.
+pub fn put_fixed32(dst: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        dst.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// Append `value` as eight little-endian bytes.
+pub fn put_fixed64(dst: &mut Vec<u8>, value: u64) {
+    for i in 0..8 {
+        dst.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// Decode four little-endian bytes.
+pub fn decode_fixed32(src: &[u8]) -> u32 {
+    (0..4).fold(0, |acc, i| acc | (src[i] as u32) << (8 * i))
+}
+
+/// Decode eight little-endian bytes.
+pub fn decode_fixed64(src: &[u8]) -> u64 {
+    (0..8).fold(0, |acc, i| acc | (src[i] as u64) << (8 * i))
+}
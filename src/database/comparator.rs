@@ -88,11 +88,51 @@ unsafe impl<C: Comparator> InternalComparator for C {}
 
 #[allow(missing_docs)]
 pub fn create_comparator<T: Comparator>(x: Box<T>) -> *mut leveldb_comparator_t {
-    unsafe {
-        leveldb_comparator_create(Box::into_raw(x) as *mut c_void,
+    create_comparator_with_order(x).0
+}
+
+/// Create a comparator, along with a `KeyOrder` comparing keys the same
+/// way for as long as the comparator lives.
+pub(crate) fn create_comparator_with_order<T: Comparator>(x: Box<T>)
+                                                         -> (*mut leveldb_comparator_t, KeyOrder) {
+    let state = Box::into_raw(x) as *mut c_void;
+    let ptr = unsafe {
+        leveldb_comparator_create(state,
                                   <T as InternalComparator>::destructor,
                                   <T as InternalComparator>::compare,
                                   <T as InternalComparator>::name)
+    };
+    let order = KeyOrder::Custom {
+        state,
+        compare: <T as InternalComparator>::compare,
+    };
+    (ptr, order)
+}
+
+/// The order of the raw keys of a database.
+pub(crate) enum KeyOrder {
+    /// leveldb's default order, by the binary value of keys
+    Bytewise,
+    /// The order of a comparator created by `create_comparator_with_order`
+    Custom {
+        state: *mut c_void,
+        compare: extern "C" fn(*mut c_void, *const c_char, size_t, *const c_char, size_t) -> i32,
+    },
+}
+
+impl KeyOrder {
+    pub(crate) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match *self {
+            KeyOrder::Bytewise => a.cmp(b),
+            KeyOrder::Custom { state, compare } => {
+                let result = compare(state,
+                                     a.as_ptr() as *const c_char,
+                                     a.len() as size_t,
+                                     b.as_ptr() as *const c_char,
+                                     b.len() as size_t);
+                result.cmp(&0)
+            }
+        }
     }
 }
 
@@ -0,0 +1,44 @@
+//! CRC32C (Castagnoli) checksums, as used by leveldb's file formats.
+
+const POLYNOMIAL: u32 = 0x82f6_3b78;
+
+fn table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLYNOMIAL } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+/// Extend `crc` with the checksum of `data`.
+pub fn extend(crc: u32, data: &[u8]) -> u32 {
+    thread_local!(static TABLE: [u32; 256] = table());
+    TABLE.with(|table| {
+        !data.iter().fold(!crc, |crc, &b| table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+    })
+}
+
+/// Return the checksum of `data`.
+pub fn value(data: &[u8]) -> u32 {
+    extend(0, data)
+}
+
+const MASK_DELTA: u32 = 0xa282_ead8;
+
+/// Return the checksum whose masked representation is `masked`.
+///
+/// leveldb stores masked checksums in its files, since computing the CRC
+/// of a string that contains embedded CRCs is problematic.
+pub fn unmask(masked: u32) -> u32 {
+    let rot = masked.wrapping_sub(MASK_DELTA);
+    rot.rotate_left(15)
+}
+
+/// Return the masked representation of `crc`, as stored in files.
+pub fn mask(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
+}
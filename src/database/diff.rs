@@ -0,0 +1,172 @@
+//! Differences between two databases or snapshots.
+//!
+//! `Diff` walks two `Iterable`s side by side, like a merge join, and
+//! yields the entries that were added, removed or changed going from the
+//! old to the new one. `sync` applies those differences to a target
+//! database, so it ends up with the same entries as a source.
+//!
+//! Both sides must be ordered the same way. By default keys are compared
+//! by their binary value; use `Diff::with_comparator` for databases
+//! opened with a custom comparator.
+use std::cmp::Ordering;
+use std::iter::{self, Peekable};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::comparator::Comparator;
+use super::error::Error;
+use super::iterator::{Iterable, Iterator};
+use super::key::Key;
+use options::{ReadOptions, WriteOptions};
+
+/// The number of differences written per batch by `sync`.
+pub const DEFAULT_SYNC_BATCH: usize = 1000;
+
+/// A difference between two databases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Difference<K> {
+    /// The key only exists in the new database.
+    Added(K, Vec<u8>),
+    /// The key only exists in the old database, with the given value.
+    Removed(K, Vec<u8>),
+    /// The key has the old and the new value, respectively.
+    Changed(K, Vec<u8>, Vec<u8>),
+}
+
+type Compare<'a, K> = dyn Fn(&K, &K) -> Ordering + 'a;
+
+/// An iterator over the differences between two databases or snapshots,
+/// in key order.
+pub struct Diff<'a, K: Key + 'a> {
+    old: Peekable<Iterator<'a, K>>,
+    new: Peekable<Iterator<'a, K>>,
+    compare: Box<Compare<'a, K>>,
+}
+
+fn options<'a, K: Key>() -> ReadOptions<'a, K> {
+    let mut options = ReadOptions::new();
+    options.fill_cache = false;
+    options
+}
+
+impl<K> Difference<K> {
+    /// The key that differs.
+    pub fn key(&self) -> &K {
+        match *self {
+            Difference::Added(ref key, _) |
+            Difference::Removed(ref key, _) |
+            Difference::Changed(ref key, _, _) => key,
+        }
+    }
+}
+
+impl<'a, K: Key + 'a> Diff<'a, K> {
+    /// Compare `old` to `new`, ordering keys by their binary value.
+    pub fn new<A, B>(old: &'a A, new: &'a B) -> Diff<'a, K>
+        where A: Iterable<'a, K>,
+              B: Iterable<'a, K>
+    {
+        Diff {
+            old: old.iter(options()).peekable(),
+            new: new.iter(options()).peekable(),
+            compare: Box::new(|a: &K, b: &K| a.as_slice(|a| b.as_slice(|b| a.cmp(b)))),
+        }
+    }
+
+    /// Compare `old` to `new`, ordering keys by `comparator`.
+    pub fn with_comparator<A, B, C>(old: &'a A, new: &'a B, comparator: C) -> Diff<'a, K>
+        where A: Iterable<'a, K>,
+              B: Iterable<'a, K>,
+              C: Comparator<K = K> + 'a
+    {
+        Diff {
+            old: old.iter(options()).peekable(),
+            new: new.iter(options()).peekable(),
+            compare: Box::new(move |a: &K, b: &K| comparator.compare(a, b)),
+        }
+    }
+}
+
+impl<'a, K: Key + 'a> iter::Iterator for Diff<'a, K> {
+    type Item = Difference<K>;
+
+    fn next(&mut self) -> Option<Difference<K>> {
+        loop {
+            let order = match (self.old.peek(), self.new.peek()) {
+                (Some((old, _)), Some((new, _))) => (self.compare)(old, new),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return None,
+            };
+            match order {
+                Ordering::Less => {
+                    let (key, value) = self.old.next().unwrap();
+                    return Some(Difference::Removed(key, value));
+                }
+                Ordering::Greater => {
+                    let (key, value) = self.new.next().unwrap();
+                    return Some(Difference::Added(key, value));
+                }
+                Ordering::Equal => {
+                    let (_, old) = self.old.next().unwrap();
+                    let (key, new) = self.new.next().unwrap();
+                    if old != new {
+                        return Some(Difference::Changed(key, old, new));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Apply `differences` to `target` in batches of `batch_size` writes.
+///
+/// Returns the number of differences applied.
+pub fn apply<K, I>(differences: I,
+                   target: &Database<K>,
+                   options: WriteOptions,
+                   batch_size: usize)
+                   -> Result<usize, Error>
+    where K: Key,
+          I: iter::IntoIterator<Item = Difference<K>>
+{
+    let mut batch = Writebatch::new();
+    let mut pending = 0;
+    let mut applied = 0;
+    for difference in differences {
+        match difference {
+            Difference::Added(key, value) |
+            Difference::Changed(key, _, value) => batch.put(key, &value),
+            Difference::Removed(key, _) => batch.delete(key),
+        }
+        pending += 1;
+        if pending >= batch_size {
+            target.write(options, &batch)?;
+            batch.clear();
+            applied += pending;
+            pending = 0;
+        }
+    }
+    if pending > 0 {
+        target.write(options, &batch)?;
+        applied += pending;
+    }
+    Ok(applied)
+}
+
+/// Make `target` hold the same entries as `source`, ordering keys by their
+/// binary value.
+///
+/// Only the differences are written, in batches of `batch_size`. The diff
+/// is taken against the state of `target` when `sync` is called. Returns
+/// the number of keys written or deleted.
+pub fn sync<'a, K, S>(source: &'a S,
+                      target: &'a Database<K>,
+                      options: WriteOptions,
+                      batch_size: usize)
+                      -> Result<usize, Error>
+    where K: Key + 'a,
+          S: Iterable<'a, K>
+{
+    apply(Diff::new(target, source), target, options, batch_size)
+}
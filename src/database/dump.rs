@@ -0,0 +1,387 @@
+//! A portable, logical dump format for databases and snapshots.
+//!
+//! Dumps are independent of leveldb's on-disk format, so they survive
+//! leveldb version changes. A dump is a stream of:
+//!
+//! * a header: the magic bytes `LDBDUMP\0`, a format version, and a
+//!   checksummed description of the comparator and options in use
+//! * one record per entry: a tag byte, key and value length, key, value
+//!   and a CRC32C over all of them
+//! * a trailer holding the number of records, so truncated dumps are
+//!   detected
+//!
+//! All integers are little-endian.
+use std::io::{Read, Write};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::coding::{decode_fixed32, decode_fixed64, get_length_prefixed, put_fixed32,
+                    put_fixed64, put_length_prefixed, get_varint64, put_varint64};
+use super::crc32c;
+use super::error::Error;
+use super::ingest::BYTEWISE_COMPARATOR;
+use super::iterator::Iterable;
+use super::key::{Key, from_u8};
+use options::{Options, ReadOptions, WriteOptions};
+
+const MAGIC: &[u8; 8] = b"LDBDUMP\0";
+/// The version of the dump format written by this module.
+pub const FORMAT_VERSION: u32 = 1;
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+const ENTRY: u8 = 1;
+const READ_CHUNK: usize = 64 << 10;
+const TRAILER: u8 = 0xff;
+
+/// Describes the database a dump was taken from.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DumpHeader {
+    /// The name of the comparator the database was ordered by.
+    pub comparator: String,
+    /// Free-form options, as name and value.
+    pub options: Vec<(String, String)>,
+}
+
+/// Progress of an export or import.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of records processed so far.
+    ///
+    /// For imports, all of these records are written to the database.
+    pub records: u64,
+    /// The number of key and value bytes processed so far.
+    pub bytes: u64,
+}
+
+/// Options for importing a dump.
+#[derive(Clone, Copy)]
+pub struct ImportOptions {
+    /// The approximate size of key and value bytes written per batch.
+    ///
+    /// default: 4MiB
+    pub batch_bytes: usize,
+    /// The number of records to skip, e.g. to resume an aborted import
+    /// at the last reported progress.
+    ///
+    /// default: 0
+    pub resume_from: u64,
+    /// The options each batch is written with.
+    ///
+    /// default: `WriteOptions::new()`
+    pub write_options: WriteOptions,
+    /// The name of the comparator the target database was opened with.
+    /// Dumps taken with a different comparator are rejected.
+    ///
+    /// default: `"leveldb.BytewiseComparator"`
+    pub comparator: &'static str,
+}
+
+impl ImportOptions {
+    /// Return `ImportOptions` with default settings.
+    pub fn new() -> ImportOptions {
+        ImportOptions {
+            batch_bytes: 4 << 20,
+            resume_from: 0,
+            write_options: WriteOptions::new(),
+            comparator: BYTEWISE_COMPARATOR,
+        }
+    }
+}
+
+/// Writes entries in the dump format.
+pub struct DumpWriter<W: Write> {
+    writer: W,
+    progress: Progress,
+}
+
+/// Reads entries from a dump, verifying their checksums.
+pub struct DumpReader<R: Read> {
+    reader: R,
+    header: DumpHeader,
+    records: u64,
+    finished: bool,
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(format!("corrupt dump: {}", message))
+}
+
+impl DumpHeader {
+    /// Describe a database opened with `options` and `comparator`.
+    ///
+    /// Use `ingest::BYTEWISE_COMPARATOR` for databases without a custom comparator.
+    pub fn new(comparator: &str, options: &Options) -> DumpHeader {
+        let mut described = vec![("compression".to_string(), (options.compression as u8).to_string())];
+        if let Some(size) = options.block_size {
+            described.push(("block_size".to_string(), size.to_string()));
+        }
+        if let Some(interval) = options.block_restart_interval {
+            described.push(("block_restart_interval".to_string(), interval.to_string()));
+        }
+        DumpHeader {
+            comparator: comparator.to_string(),
+            options: described,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        put_length_prefixed(&mut payload, self.comparator.as_bytes());
+        put_varint64(&mut payload, self.options.len() as u64);
+        for (name, value) in &self.options {
+            put_length_prefixed(&mut payload, name.as_bytes());
+            put_length_prefixed(&mut payload, value.as_bytes());
+        }
+        payload
+    }
+
+    fn decode(mut payload: &[u8]) -> Result<DumpHeader, Error> {
+        let string = |src: &mut &[u8]| {
+            get_length_prefixed(src)
+                .map(|s| String::from_utf8_lossy(s).into_owned())
+                .ok_or_else(|| corrupt("invalid header"))
+        };
+        let comparator = string(&mut payload)?;
+        let count = get_varint64(&mut payload).ok_or_else(|| corrupt("invalid header"))?;
+        let mut options = vec![];
+        for _ in 0..count {
+            options.push((string(&mut payload)?, string(&mut payload)?));
+        }
+        Ok(DumpHeader {
+            comparator,
+            options,
+        })
+    }
+}
+
+impl<W: Write> DumpWriter<W> {
+    /// Start a dump by writing its header.
+    pub fn new(mut writer: W, header: &DumpHeader) -> Result<DumpWriter<W>, Error> {
+        let payload = header.encode();
+        let mut buf = MAGIC.to_vec();
+        put_fixed32(&mut buf, FORMAT_VERSION);
+        put_fixed32(&mut buf, payload.len() as u32);
+        buf.extend_from_slice(&payload);
+        put_fixed32(&mut buf, crc32c::value(&payload));
+        writer.write_all(&buf)?;
+        Ok(DumpWriter {
+            writer,
+            progress: Progress::default(),
+        })
+    }
+
+    /// Append an entry.
+    pub fn write_entry(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let mut buf = Vec::with_capacity(key.len() + value.len() + 13);
+        buf.push(ENTRY);
+        put_fixed32(&mut buf, key.len() as u32);
+        put_fixed32(&mut buf, value.len() as u32);
+        buf.extend_from_slice(key);
+        buf.extend_from_slice(value);
+        let crc = crc32c::value(&buf);
+        put_fixed32(&mut buf, crc);
+        self.writer.write_all(&buf)?;
+        self.progress.records += 1;
+        self.progress.bytes += (key.len() + value.len()) as u64;
+        Ok(())
+    }
+
+    /// The progress of this dump so far.
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    /// Complete the dump by writing the trailer, and return the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut buf = vec![TRAILER];
+        put_fixed64(&mut buf, self.progress.records);
+        let crc = crc32c::value(&buf);
+        put_fixed32(&mut buf, crc);
+        self.writer.write_all(&buf)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Read `len` bytes.
+///
+/// The buffer grows with the bytes actually read, so a corrupt length
+/// can't allocate more memory than the dump holds.
+fn read_exact<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(len.min(READ_CHUNK));
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() < len {
+        return Err(corrupt("truncated"));
+    }
+    Ok(buf)
+}
+
+impl<R: Read> DumpReader<R> {
+    /// Open a dump, reading and verifying its header.
+    pub fn new(mut reader: R) -> Result<DumpReader<R>, Error> {
+        let prelude = read_exact(&mut reader, 16)?;
+        if &prelude[..8] != MAGIC {
+            return Err(corrupt("not a dump"));
+        }
+        let version = decode_fixed32(&prelude[8..]);
+        if version != FORMAT_VERSION {
+            return Err(Error::new(format!("unsupported dump version {}", version)));
+        }
+        let payload = read_exact(&mut reader, decode_fixed32(&prelude[12..]) as usize)?;
+        let crc = read_exact(&mut reader, 4)?;
+        if crc32c::value(&payload) != decode_fixed32(&crc) {
+            return Err(corrupt("header checksum mismatch"));
+        }
+        Ok(DumpReader {
+            reader,
+            header: DumpHeader::decode(&payload)?,
+            records: 0,
+            finished: false,
+        })
+    }
+
+    /// The header of this dump.
+    pub fn header(&self) -> &DumpHeader {
+        &self.header
+    }
+
+    /// The number of entries read so far.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn read_entry(&mut self) -> Result<Option<Entry>, Error> {
+        let tag = read_exact(&mut self.reader, 1)?;
+        if tag[0] == TRAILER {
+            let rest = read_exact(&mut self.reader, 12)?;
+            let crc = crc32c::extend(crc32c::value(&tag), &rest[..8]);
+            if crc != decode_fixed32(&rest[8..]) {
+                return Err(corrupt("trailer checksum mismatch"));
+            }
+            if decode_fixed64(&rest) != self.records {
+                return Err(corrupt("record count mismatch"));
+            }
+            return Ok(None);
+        }
+        if tag[0] != ENTRY {
+            return Err(corrupt("unknown record type"));
+        }
+        let lengths = read_exact(&mut self.reader, 8)?;
+        let key = read_exact(&mut self.reader, decode_fixed32(&lengths) as usize)?;
+        let value = read_exact(&mut self.reader, decode_fixed32(&lengths[4..]) as usize)?;
+        let crc = read_exact(&mut self.reader, 4)?;
+        let expected = [&tag[..], &lengths, &key, &value]
+            .iter()
+            .fold(0, |crc, part| crc32c::extend(crc, part));
+        if expected != decode_fixed32(&crc) {
+            return Err(corrupt("record checksum mismatch"));
+        }
+        self.records += 1;
+        Ok(Some((key, value)))
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.read_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Export all entries of a database or snapshot.
+///
+/// `progress` is called after every 1000 records. Returns the number of
+/// records written.
+pub fn export<'a, K, S, W, F>(source: &'a S,
+                              header: &DumpHeader,
+                              writer: W,
+                              mut progress: F)
+                              -> Result<u64, Error>
+    where K: Key + 'a,
+          S: Iterable<'a, K>,
+          W: Write,
+          F: FnMut(Progress)
+{
+    let mut options = ReadOptions::new();
+    options.fill_cache = false;
+    let mut dump = DumpWriter::new(writer, header)?;
+    for (key, value) in source.iter(options) {
+        dump.write_entry(&key.as_slice(|k| k.to_vec()), &value)?;
+        if dump.progress().records % 1000 == 0 {
+            progress(dump.progress());
+        }
+    }
+    let written = dump.progress();
+    dump.finish()?;
+    progress(written);
+    Ok(written.records)
+}
+
+/// Import a dump into `database` using large write batches.
+///
+/// `progress` is called after every batch, once the batch is written. If
+/// an import is aborted, it can be resumed by passing the last reported
+/// number of records as `ImportOptions::resume_from`. Returns the number
+/// of records in the dump.
+///
+/// Fails if the dump was taken with a comparator other than
+/// `ImportOptions::comparator`.
+pub fn import<K, R, F>(database: &Database<K>,
+                       reader: R,
+                       options: &ImportOptions,
+                       mut progress: F)
+                       -> Result<u64, Error>
+    where K: Key,
+          R: Read,
+          F: FnMut(Progress)
+{
+    let mut dump = DumpReader::new(reader)?;
+    if dump.header().comparator != options.comparator {
+        return Err(Error::new(format!("dump was taken with comparator {}, expected {}",
+                                      dump.header().comparator,
+                                      options.comparator)));
+    }
+    let mut batch = Writebatch::new();
+    let mut batched = 0;
+    let mut done = Progress {
+        records: options.resume_from,
+        bytes: 0,
+    };
+
+    while let Some(entry) = dump.next() {
+        let (key, value) = entry?;
+        if dump.records() <= options.resume_from {
+            continue;
+        }
+        batched += key.len() + value.len();
+        batch.put(from_u8::<K>(&key), &value);
+        if batched >= options.batch_bytes {
+            database.write(options.write_options, &batch)?;
+            batch.clear();
+            done.bytes += batched as u64;
+            done.records = dump.records();
+            batched = 0;
+            progress(done);
+        }
+    }
+    database.write(options.write_options, &batch)?;
+    done.bytes += batched as u64;
+    done.records = dump.records();
+    progress(done);
+    Ok(dump.records())
+}
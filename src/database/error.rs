@@ -46,3 +46,9 @@ impl std::error::Error for Error {
         None
     }
 }
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Error {
+        Error::new(error.to_string())
+    }
+}
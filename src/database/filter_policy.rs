@@ -0,0 +1,37 @@
+//! Filter policies, which let leveldb skip table files that cannot hold a
+//! key.
+use leveldb_sys::{leveldb_filterpolicy_t, leveldb_filterpolicy_create_bloom, leveldb_filterpolicy_destroy};
+use libc::c_int;
+
+#[allow(missing_docs)]
+struct RawFilterPolicy {
+    ptr: *mut leveldb_filterpolicy_t,
+}
+
+impl Drop for RawFilterPolicy {
+    fn drop(&mut self) {
+        unsafe {
+            leveldb_filterpolicy_destroy(self.ptr);
+        }
+    }
+}
+
+/// Represents a leveldb filter policy
+pub struct FilterPolicy {
+    raw: RawFilterPolicy,
+}
+
+impl FilterPolicy {
+    /// Create a bloom filter policy using `bits_per_key` bits per key.
+    ///
+    /// 10 bits per key yield about 1% false positives.
+    pub fn bloom(bits_per_key: i32) -> FilterPolicy {
+        let policy = unsafe { leveldb_filterpolicy_create_bloom(bits_per_key as c_int) };
+        FilterPolicy { raw: RawFilterPolicy { ptr: policy } }
+    }
+
+    #[allow(missing_docs)]
+    pub fn raw_ptr(&self) -> *mut leveldb_filterpolicy_t {
+        self.raw.ptr
+    }
+}
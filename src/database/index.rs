@@ -0,0 +1,312 @@
+//! Secondary indexes maintained alongside primary records.
+//!
+//! An `Indexed` table stores its records in a primary `Keyspace` and every
+//! declared `Index` in a keyspace of its own. Each put or delete updates
+//! the record and all affected index entries in a single `Writebatch`, so
+//! indexes can't drift from the records when a writer crashes.
+//!
+//! Index entries are keyed by the extracted term followed by the primary
+//! key. Terms are escaped so that their binary order is preserved, which
+//! allows prefix and range queries over them.
+use super::Database;
+use super::batch::Batch;
+use super::error::Error;
+use super::iterator::{Iterable, LevelDBIterator};
+use super::key::{Key, from_u8};
+use super::keyspace::{Keyspace, KeyspaceBatch};
+use super::kv::KV;
+use options::{ReadOptions, WriteOptions};
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_ESCAPE: u8 = 0xff;
+const TERMINATOR: u8 = 0x01;
+
+/// The number of index entries written per batch while rebuilding.
+pub const REBUILD_BATCH: usize = 1000;
+
+type Extractor = dyn Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync;
+
+/// A secondary index declaration.
+pub struct Index {
+    name: String,
+    extractor: Box<Extractor>,
+}
+
+/// A table of primary records with secondary indexes.
+pub struct Indexed<'a, K: Key + 'a> {
+    primary: Keyspace<'a, K>,
+    indexes: Vec<(Index, Keyspace<'a, K>)>,
+}
+
+/// The result of verifying the indexes of a table.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct IndexReport {
+    /// Index entries that should exist, but don't.
+    pub missing: usize,
+    /// Index entries that point to no or a different record.
+    pub dangling: usize,
+}
+
+impl IndexReport {
+    /// Whether indexes and records agree.
+    pub fn is_consistent(&self) -> bool {
+        self.missing == 0 && self.dangling == 0
+    }
+}
+
+impl Index {
+    /// Declare an index called `name`.
+    ///
+    /// `extractor` returns the terms a value is indexed by. A value may
+    /// have any number of terms.
+    pub fn new<F>(name: &str, extractor: F) -> Index
+        where F: Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static
+    {
+        Index {
+            name: name.to_string(),
+            extractor: Box::new(extractor),
+        }
+    }
+
+    /// The name of this index.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn escape_term(term: &[u8], dst: &mut Vec<u8>) {
+    for &b in term {
+        dst.push(b);
+        if b == ESCAPE {
+            dst.push(ESCAPED_ESCAPE);
+        }
+    }
+}
+
+fn entry_key(term: &[u8], primary_key: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(term.len() + primary_key.len() + 2);
+    escape_term(term, &mut key);
+    key.push(ESCAPE);
+    key.push(TERMINATOR);
+    key.extend_from_slice(primary_key);
+    key
+}
+
+/// Split an index entry key into term and primary key.
+fn split_entry(key: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut term = Vec::new();
+    let mut i = 0;
+    while i < key.len() {
+        if key[i] == ESCAPE {
+            match key.get(i + 1) {
+                Some(&ESCAPED_ESCAPE) => term.push(ESCAPE),
+                Some(&TERMINATOR) => return Some((term, &key[i + 2..])),
+                _ => return None,
+            }
+            i += 2;
+        } else {
+            term.push(key[i]);
+            i += 1;
+        }
+    }
+    None
+}
+
+impl<'a, K: Key + 'a> Indexed<'a, K> {
+    /// Open the table `name` with the given indexes.
+    ///
+    /// The same indexes must be declared every time the table is opened;
+    /// use `rebuild` after changing them.
+    pub fn open(database: &'a Database<K>, name: &str, indexes: Vec<Index>) -> Result<Indexed<'a, K>, Error> {
+        let primary = Keyspace::open(database, name)?;
+        let mut opened = Vec::with_capacity(indexes.len());
+        for index in indexes {
+            let keyspace = Keyspace::open(database, &format!("{}.index.{}", name, index.name))?;
+            opened.push((index, keyspace));
+        }
+        Ok(Indexed {
+            primary,
+            indexes: opened,
+        })
+    }
+
+    /// The keyspace holding the primary records.
+    pub fn primary(&self) -> &Keyspace<'a, K> {
+        &self.primary
+    }
+
+    /// Read a primary record.
+    pub fn get(&self, options: ReadOptions<'a, K>, key: K) -> Result<Option<Vec<u8>>, Error> {
+        self.primary.get(options, key)
+    }
+
+    /// Store a record, updating all indexes in the same batch.
+    pub fn put(&self, options: WriteOptions, key: K, value: &[u8]) -> Result<(), Error> {
+        self.modify(options, key, Some(value))
+    }
+
+    /// Delete a record and its index entries.
+    pub fn delete(&self, options: WriteOptions, key: K) -> Result<(), Error> {
+        self.modify(options, key, None)
+    }
+
+    fn modify(&self, options: WriteOptions, key: K, value: Option<&[u8]>) -> Result<(), Error> {
+        let database = self.primary.database();
+        let _lock = database.lock_manager().lock(&self.primary.prefixed(&key))?;
+        let old = self.primary.get(ReadOptions::new(), &key)?;
+
+        let mut batch = KeyspaceBatch::new();
+        let pk = key.as_slice(|k| k.to_vec());
+        for (index, keyspace) in &self.indexes {
+            let old_terms = old.as_ref().map(|v| (index.extractor)(v)).unwrap_or_default();
+            let new_terms = value.map(|v| (index.extractor)(v)).unwrap_or_default();
+            for term in old_terms.iter().filter(|t| !new_terms.contains(t)) {
+                batch.delete(keyspace, from_u8(&entry_key(term, &pk)));
+            }
+            for term in new_terms.iter().filter(|t| !old_terms.contains(t)) {
+                batch.put(keyspace, from_u8(&entry_key(term, &pk)), &[]);
+            }
+        }
+        match value {
+            Some(value) => batch.put(&self.primary, key, value),
+            None => batch.delete(&self.primary, key),
+        }
+        database.write(options, batch.writebatch())
+    }
+
+    fn index(&self, name: &str) -> Result<&Keyspace<'a, K>, Error> {
+        self.indexes
+            .iter()
+            .find(|(index, _)| index.name == name)
+            .map(|(_, keyspace)| keyspace)
+            .ok_or_else(|| Error::new(format!("no index named {}", name)))
+    }
+
+    /// Scan an index from `start`, returning primary keys while `accept`
+    /// holds for the term.
+    fn scan<F>(&self, index: &str, start: &[u8], accept: F) -> Result<Vec<K>, Error>
+        where F: Fn(&[u8]) -> bool
+    {
+        let keyspace = self.index(index)?;
+        let mut escaped = Vec::new();
+        escape_term(start, &mut escaped);
+        let start: K = from_u8(&escaped);
+        let mut keys = vec![];
+        for (entry, _) in keyspace.iter(ReadOptions::new()).from(&start) {
+            let primary_key = entry.as_slice(|e| {
+                match split_entry(e) {
+                    Some((ref term, pk)) if accept(&term[..]) => Some(from_u8(pk)),
+                    _ => None,
+                }
+            });
+            match primary_key {
+                Some(pk) => keys.push(pk),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Return the primary keys of all records indexed by `term`.
+    pub fn query(&self, index: &str, term: &[u8]) -> Result<Vec<K>, Error> {
+        self.scan(index, term, |t| t == term)
+    }
+
+    /// Return the primary keys of all records with a term starting with
+    /// `prefix`, ordered by term.
+    pub fn query_prefix(&self, index: &str, prefix: &[u8]) -> Result<Vec<K>, Error> {
+        self.scan(index, prefix, |t| t.starts_with(prefix))
+    }
+
+    /// Return the primary keys of all records with a term in the range
+    /// `from..to`, ordered by term.
+    pub fn query_range(&self, index: &str, from: &[u8], to: &[u8]) -> Result<Vec<K>, Error> {
+        self.scan(index, from, |t| t < to)
+    }
+
+    /// Compare `keyspace` against the primary records, calling `f` with
+    /// every dangling entry and `false`, then with every missing entry and
+    /// `true`.
+    ///
+    /// Both sides are streamed and checked against the other by point
+    /// reads, so memory use doesn't grow with the table.
+    fn check<F>(&self, index: &Index, keyspace: &Keyspace<'a, K>, mut f: F) -> Result<(), Error>
+        where F: FnMut(K, bool) -> Result<(), Error>
+    {
+        for (entry, _) in keyspace.iter(ReadOptions::new()) {
+            let split = entry.as_slice(|e| split_entry(e).map(|(term, pk)| (term, from_u8::<K>(pk))));
+            let valid = match split {
+                Some((term, pk)) => {
+                    match self.primary.get(ReadOptions::new(), pk)? {
+                        Some(value) => (index.extractor)(&value).contains(&term),
+                        None => false,
+                    }
+                }
+                None => false,
+            };
+            if !valid {
+                f(entry, false)?;
+            }
+        }
+        for (key, value) in self.primary.iter(ReadOptions::new()) {
+            let pk = key.as_slice(|k| k.to_vec());
+            let mut terms = (index.extractor)(&value);
+            terms.sort();
+            terms.dedup();
+            for term in terms {
+                let entry: K = from_u8(&entry_key(&term, &pk));
+                if keyspace.get(ReadOptions::new(), &entry)?.is_none() {
+                    f(entry, true)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Repair all indexes from the primary records, deleting dangling and
+    /// writing missing entries.
+    ///
+    /// Concurrent writes to the table must be stopped while rebuilding.
+    /// Returns the number of index entries deleted or written.
+    pub fn rebuild(&self, options: WriteOptions) -> Result<usize, Error> {
+        let database = self.primary.database();
+        let mut repaired = 0;
+        for (index, keyspace) in &self.indexes {
+            let mut batch = KeyspaceBatch::new();
+            let mut pending = 0;
+            self.check(index, keyspace, |entry, insert| {
+                if insert {
+                    batch.put(keyspace, entry, &[]);
+                } else {
+                    batch.delete(keyspace, entry);
+                }
+                repaired += 1;
+                pending += 1;
+                if pending >= REBUILD_BATCH {
+                    database.write(options, batch.writebatch())?;
+                    batch.clear();
+                    pending = 0;
+                }
+                Ok(())
+            })?;
+            database.write(options, batch.writebatch())?;
+        }
+        Ok(repaired)
+    }
+
+    /// Compare all index entries against the primary records.
+    pub fn verify(&self) -> Result<IndexReport, Error> {
+        let mut report = IndexReport::default();
+        for (index, keyspace) in &self.indexes {
+            self.check(index, keyspace, |_, missing| {
+                if missing {
+                    report.missing += 1;
+                } else {
+                    report.dangling += 1;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(report)
+    }
+}
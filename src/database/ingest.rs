@@ -0,0 +1,168 @@
+//! Creating a database from existing table files.
+//!
+//! Tables, for example written by `table::TableBuilder`, are verified,
+//! linked (or copied) into a new database directory, and described by a
+//! fresh MANIFEST and `CURRENT` file. The result opens with
+//! `Database::open` like any other database using the default comparator.
+//!
+//! Tables whose key ranges don't overlap are placed at the last level, so
+//! leveldb doesn't compact them again. Otherwise all tables go to level 0.
+//! There, leveldb looks a key up in the file with the highest number first,
+//! so tables are numbered in order of their sequence numbers. Tables whose
+//! key ranges overlap must therefore hold disjoint ranges of sequence
+//! numbers, or no order of files returns the newest entry for every key.
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use super::error::Error;
+use super::log::LogWriter;
+use super::manifest::{FileMetaData, VersionEdit, NUM_LEVELS};
+use super::table::{InternalKey, Table};
+
+/// The name of leveldb's default comparator, which tables must be sorted by.
+pub const BYTEWISE_COMPARATOR: &str = "leveldb.BytewiseComparator";
+
+const MANIFEST_NUMBER: u64 = 1;
+
+struct TableInfo {
+    smallest: InternalKey,
+    largest: InternalKey,
+    min_sequence: u64,
+    max_sequence: u64,
+    file_size: u64,
+}
+
+fn error_for(action: &str, path: &Path, error: &::std::io::Error) -> Error {
+    Error::new(format!("{} {}: {}", action, path.display(), error))
+}
+
+/// Read the table at `path`, verifying all blocks and the order of keys.
+///
+/// Returns `None` for tables without entries.
+fn scan_table(path: &Path) -> Result<Option<TableInfo>, Error> {
+    let table = Table::open(path)?;
+    let mut info: Option<TableInfo> = None;
+    for entry in table.iter() {
+        let (key, _) = entry.map_err(|e| Error::new(format!("{}: {}", path.display(), e)))?;
+        match info {
+            Some(ref mut info) => {
+                if key.user_key < info.largest.user_key {
+                    return Err(Error::new(format!("{}: keys not in bytewise order", path.display())));
+                }
+                info.min_sequence = info.min_sequence.min(key.sequence);
+                info.max_sequence = info.max_sequence.max(key.sequence);
+                info.largest = key;
+            }
+            None => {
+                info = Some(TableInfo {
+                    smallest: key.clone(),
+                    min_sequence: key.sequence,
+                    max_sequence: key.sequence,
+                    largest: key,
+                    file_size: 0,
+                })
+            }
+        }
+    }
+    let size = fs::metadata(path).map_err(|e| error_for("reading", path, &e))?.len();
+    Ok(info.map(|info| TableInfo { file_size: size, ..info }))
+}
+
+fn link_or_copy(from: &Path, to: &Path) -> Result<(), Error> {
+    if fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map_err(|e| error_for("copying", from, &e))?;
+    File::open(to).and_then(|f| f.sync_all()).map_err(|e| error_for("syncing", to, &e))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    File::create(path)
+        .and_then(|mut f| f.write_all(contents).and_then(|_| f.sync_all()))
+        .map_err(|e| error_for("writing", path, &e))
+}
+
+/// Create a database in `dir` holding the entries of the table files
+/// `tables`.
+///
+/// The tables must be sorted by the default, bytewise comparator, and
+/// tables with overlapping key ranges must not have overlapping sequence
+/// numbers. `dir` is created if needed and must not contain a database
+/// yet; the tables themselves are left untouched. Empty tables are
+/// skipped.
+///
+/// Returns the edit written to the new MANIFEST, which lists the level,
+/// number and key range of every ingested table.
+pub fn ingest_tables<P: AsRef<Path>>(dir: &Path, tables: &[P]) -> Result<VersionEdit, Error> {
+    let mut infos = vec![];
+    for path in tables {
+        if let Some(info) = scan_table(path.as_ref())? {
+            infos.push((info, path.as_ref()));
+        }
+    }
+
+    infos.sort_by(|a, b| a.0.smallest.user_key.cmp(&b.0.smallest.user_key));
+    let disjoint = infos.windows(2).all(|w| w[0].0.largest.user_key < w[1].0.smallest.user_key);
+    let level = if disjoint {
+        NUM_LEVELS - 1
+    } else {
+        for (i, a) in infos.iter().enumerate() {
+            for b in &infos[i + 1..] {
+                let keys_overlap = b.0.smallest.user_key <= a.0.largest.user_key &&
+                                   a.0.smallest.user_key <= b.0.largest.user_key;
+                let sequences_overlap = b.0.min_sequence <= a.0.max_sequence &&
+                                        a.0.min_sequence <= b.0.max_sequence;
+                if keys_overlap && sequences_overlap {
+                    return Err(Error::new(format!("{} and {} overlap in keys and sequence numbers",
+                                                  a.1.display(),
+                                                  b.1.display())));
+                }
+            }
+        }
+        // on level 0, leveldb prefers the file with the higher number, so
+        // number tables in order of their sequence numbers
+        infos.sort_by_key(|info| info.0.max_sequence);
+        0
+    };
+
+    fs::create_dir_all(dir).map_err(|e| error_for("creating", dir, &e))?;
+    let current = dir.join("CURRENT");
+    if current.exists() {
+        return Err(Error::new(format!("{} already contains a database", dir.display())));
+    }
+
+    let mut edit = VersionEdit {
+        comparator: Some(BYTEWISE_COMPARATOR.to_string()),
+        log_number: Some(0),
+        prev_log_number: Some(0),
+        last_sequence: Some(infos.iter().map(|info| info.0.max_sequence).max().unwrap_or(0)),
+        ..VersionEdit::default()
+    };
+    let mut number = MANIFEST_NUMBER;
+    for (info, path) in infos {
+        number += 1;
+        link_or_copy(path, &dir.join(format!("{:06}.ldb", number)))?;
+        let file = FileMetaData {
+            number,
+            file_size: info.file_size,
+            smallest: info.smallest,
+            largest: info.largest,
+        };
+        edit.new_files.push((level, file));
+    }
+    edit.next_file_number = Some(number + 1);
+
+    let manifest = format!("MANIFEST-{:06}", MANIFEST_NUMBER);
+    let path = dir.join(&manifest);
+    let file = File::create(&path).map_err(|e| error_for("writing", &path, &e))?;
+    let mut writer = LogWriter::new(file);
+    writer.add_record(&edit.encode())?;
+    writer.into_inner().sync_all().map_err(|e| error_for("syncing", &path, &e))?;
+
+    // publish the MANIFEST atomically, like leveldb does
+    let temp = dir.join(format!("{:06}.dbtmp", MANIFEST_NUMBER));
+    write_file(&temp, format!("{}\n", manifest).as_bytes())?;
+    fs::rename(&temp, &current).map_err(|e| error_for("renaming", &temp, &e))?;
+    Ok(edit)
+}
@@ -2,15 +2,22 @@
 //!
 //! Iteration is one of the most important parts of leveldb. This module provides
 //! Iterators to iterate over key, values and pairs of both.
+//!
+//! `from` and `to` bound an iterator inclusively, in the order it visits
+//! keys: forward iterators visit the keys from `from` up to `to`, reverse
+//! iterators those from `from` down to `to`.
 use leveldb_sys::{leveldb_iterator_t, leveldb_iter_seek_to_first, leveldb_iter_destroy,
                   leveldb_iter_seek_to_last, leveldb_create_iterator, leveldb_iter_valid,
                   leveldb_iter_next, leveldb_iter_prev, leveldb_iter_key, leveldb_iter_value,
-                  leveldb_readoptions_destroy, leveldb_iter_seek};
+                  leveldb_iter_seek};
 use libc::{size_t, c_char};
 use std::iter;
 use super::Database;
-use super::options::{ReadOptions, c_readoptions};
+use super::comparator::KeyOrder;
+use super::options::ReadOptions;
 use super::key::{Key, from_u8};
+use super::operands::{self, Operands};
+use std::cmp::Ordering;
 use std::slice::from_raw_parts;
 use std::marker::PhantomData;
 
@@ -35,9 +42,12 @@ pub struct Iterator<'a, K: Key + 'a> {
     // but needs to hold the reference for lifetime tracking
     #[allow(dead_code)]
     database: PhantomData<&'a Database<K>>,
+    order: &'a KeyOrder,
     iter: RawIterator,
+    folding: Option<Folding<'a>>,
     from: Option<&'a K>,
     to: Option<&'a K>,
+    prefix: &'a [u8],
 }
 
 /// An iterator over the leveldb keyspace  that browses the keys backwards.
@@ -49,9 +59,28 @@ pub struct RevIterator<'a, K: Key + 'a> {
     // but needs to hold the reference for lifetime tracking
     #[allow(dead_code)]
     database: PhantomData<&'a Database<K>>,
+    order: &'a KeyOrder,
     iter: RawIterator,
+    folding: Option<Folding<'a>>,
     from: Option<&'a K>,
     to: Option<&'a K>,
+    prefix: &'a [u8],
+}
+
+/// Reads the operands of keys in databases with a merge operator, from
+/// the same state as the iterator.
+struct Folding<'a> {
+    operands: &'a Operands,
+    iter: RawIterator,
+}
+
+impl<'a> Folding<'a> {
+    /// The folded value of the entry `iter` is positioned at.
+    unsafe fn value(&self, iter: *mut leveldb_iterator_t) -> Vec<u8> {
+        let key = raw_key(iter);
+        let entries = operands::entries(self.iter.ptr, key);
+        self.operands.fold(key, &entries)
+    }
 }
 
 /// An iterator over the leveldb keyspace.
@@ -127,42 +156,51 @@ pub trait LevelDBIterator<'a, K: Key> {
     fn from_key(&self) -> Option<&K>;
     fn to_key(&self) -> Option<&K>;
 
+    /// Only visit keys starting with `prefix`, returning them without it.
+    ///
+    /// Keys passed to `from`, `to` and `seek` are taken without the
+    /// prefix as well.
+    ///
+    /// # Panics
+    ///
+    /// The default implementation, for iterators without prefixes, panics
+    /// if `prefix` is not empty.
+    fn prefix(self, prefix: &'a [u8]) -> Self where Self: Sized {
+        assert!(prefix.is_empty(), "iterator does not support prefixes");
+        self
+    }
+
+    /// The prefix set by `prefix`, empty by default.
+    fn key_prefix(&self) -> &[u8] {
+        &[]
+    }
+
     fn valid(&self) -> bool {
-        unsafe { leveldb_iter_valid(self.raw_iterator()) != 0 }
+        unsafe {
+            leveldb_iter_valid(self.raw_iterator()) != 0 &&
+            raw_key(self.raw_iterator()).starts_with(self.key_prefix())
+        }
     }
 
     #[doc(hidden)]
     unsafe fn advance_raw(&mut self);
 
-    fn advance(&mut self) -> bool {
-        unsafe {
-            if !self.start() {
+    /// Position the iterator at the first key to visit from `key` on.
+    #[doc(hidden)]
+    fn seek_from(&self, key: &K) {
+        self.seek(key)
+    }
 
-                self.advance_raw();
-            } else {
-                if let Some(k) = self.from_key() {
-                    self.seek(k)
-                }
-                self.started();
-            }
-        }
-        self.valid()
+    fn advance(&mut self) -> bool {
+        advance(self)
     }
 
     fn key(&self) -> K {
-        unsafe {
-            let length: size_t = 0;
-            let value = leveldb_iter_key(self.raw_iterator(), &length) as *const u8;
-            from_u8(from_raw_parts(value, length as usize))
-        }
+        unsafe { from_u8(&raw_key(self.raw_iterator())[self.key_prefix().len()..]) }
     }
 
     fn value(&self) -> Vec<u8> {
-        unsafe {
-            let length: size_t = 0;
-            let value = leveldb_iter_value(self.raw_iterator(), &length) as *const u8;
-            from_raw_parts(value, length as usize).to_vec()
-        }
+        unsafe { raw_value(self.raw_iterator()) }
     }
 
     fn entry(&self) -> (K, Vec<u8>) {
@@ -170,44 +208,161 @@ pub trait LevelDBIterator<'a, K: Key> {
     }
 
     fn seek_to_first(&self) {
-        unsafe { leveldb_iter_seek_to_first(self.raw_iterator()) }
+        unsafe {
+            if self.key_prefix().is_empty() {
+                leveldb_iter_seek_to_first(self.raw_iterator())
+            } else {
+                seek_raw(self.raw_iterator(), self.key_prefix())
+            }
+        }
     }
 
     fn seek_to_last(&self) {
-        if let Some(k) = self.to_key() {
-            self.seek(k);
-        } else {
-            unsafe {
-                leveldb_iter_seek_to_last(self.raw_iterator());
-            }
-        }
+        seek_to_last(self)
     }
 
     fn seek(&self, key: &K) {
         unsafe {
             key.as_slice(|k| {
-                leveldb_iter_seek(self.raw_iterator(),
-                                  k.as_ptr() as *mut c_char,
-                                  k.len() as size_t);
+                if self.key_prefix().is_empty() {
+                    seek_raw(self.raw_iterator(), k)
+                } else {
+                    seek_raw(self.raw_iterator(), &[self.key_prefix(), k].concat())
+                }
             })
         }
     }
 }
 
+fn advance<'a, K: Key, I: LevelDBIterator<'a, K> + ?Sized>(iter: &mut I) -> bool {
+    unsafe {
+        if !iter.start() {
+
+            iter.advance_raw();
+        } else {
+            if let Some(k) = iter.from_key() {
+                iter.seek_from(k)
+            }
+            iter.started();
+        }
+    }
+    iter.valid()
+}
+
+unsafe fn raw_key<'b>(iter: *mut leveldb_iterator_t) -> &'b [u8] {
+    let length: size_t = 0;
+    let key = leveldb_iter_key(iter, &length) as *const u8;
+    from_raw_parts(key, length as usize)
+}
+
+unsafe fn seek_raw(iter: *mut leveldb_iterator_t, key: &[u8]) {
+    leveldb_iter_seek(iter, key.as_ptr() as *mut c_char, key.len() as size_t);
+}
+
+/// Position `iter` at the last key starting with `prefix`.
+unsafe fn seek_to_end(iter: *mut leveldb_iterator_t, prefix: &[u8]) {
+    // the first key after all keys starting with the prefix
+    let mut limit = prefix.to_vec();
+    while limit.last() == Some(&0xff) {
+        limit.pop();
+    }
+    match limit.pop() {
+        Some(last) => {
+            limit.push(last + 1);
+            seek_raw(iter, &limit);
+            if leveldb_iter_valid(iter) != 0 {
+                leveldb_iter_prev(iter);
+            } else {
+                leveldb_iter_seek_to_last(iter);
+            }
+        }
+        None => leveldb_iter_seek_to_last(iter),
+    }
+}
+
+unsafe fn raw_value(iter: *mut leveldb_iterator_t) -> Vec<u8> {
+    let length: size_t = 0;
+    let value = leveldb_iter_value(iter, &length) as *const u8;
+    from_raw_parts(value, length as usize).to_vec()
+}
+
+fn seek_to_last<'a, K: Key, I: LevelDBIterator<'a, K> + ?Sized>(iter: &I) {
+    if let Some(k) = iter.to_key() {
+        seek_before(iter, k);
+    } else {
+        unsafe { seek_to_end(iter.raw_iterator(), iter.key_prefix()) }
+    }
+}
+
+/// Position `iter` at the last key at or before `key`.
+fn seek_before<'a, K: Key, I: LevelDBIterator<'a, K> + ?Sized>(iter: &I, key: &K) {
+    let ptr = iter.raw_iterator();
+    let full = key.as_slice(|k| [iter.key_prefix(), k].concat());
+    unsafe {
+        seek_raw(ptr, &full);
+        if leveldb_iter_valid(ptr) == 0 {
+            leveldb_iter_seek_to_last(ptr);
+        } else if raw_key(ptr) != &full[..] {
+            leveldb_iter_prev(ptr);
+        }
+    }
+}
+
+/// Whether `iter` is positioned at a key that is not `beyond` `bound`.
+fn within<K: Key>(iter: *mut leveldb_iterator_t,
+                  order: &KeyOrder,
+                  prefix: &[u8],
+                  bound: Option<&K>,
+                  beyond: Ordering)
+                  -> bool {
+    let bound = match bound {
+        Some(bound) => bound,
+        None => return true,
+    };
+    let key = unsafe { raw_key(iter) };
+    let ordering = bound.as_slice(|b| {
+        if prefix.is_empty() {
+            order.compare(key, b)
+        } else {
+            order.compare(key, &[prefix, b].concat())
+        }
+    });
+    ordering != beyond
+}
 
 impl<'a, K: Key> Iterator<'a, K> {
     fn new(database: &'a Database<K>, options: ReadOptions<'a, K>) -> Iterator<'a, K> {
         unsafe {
-            let c_readoptions = c_readoptions(&options);
-            let ptr = leveldb_create_iterator(database.database.ptr, c_readoptions);
-            leveldb_readoptions_destroy(c_readoptions);
+            let db = database.database.ptr;
+            let (ptr, folding) = match database.operands {
+                // both iterators read from one snapshot, which leveldb
+                // only needs while creating them
+                Some(ref operands) => {
+                    database.with_snapshot(&options, |readoptions| {
+                        let folding = Folding {
+                            operands,
+                            iter: RawIterator { ptr: leveldb_create_iterator(db, readoptions.raw_ptr()) },
+                        };
+                        (leveldb_create_iterator(db, readoptions.raw_ptr()), Some(folding))
+                    })
+                }
+                None => {
+                    let ptr = database.with_readoptions(&options, |readoptions| {
+                        leveldb_create_iterator(db, readoptions.raw_ptr())
+                    });
+                    (ptr, None)
+                }
+            };
             leveldb_iter_seek_to_first(ptr);
             Iterator {
                 start: true,
+                order: &database.order,
                 iter: RawIterator { ptr: ptr },
+                folding,
                 database: PhantomData,
                 from: None,
                 to: None,
+                prefix: &[],
             }
         }
     }
@@ -215,13 +370,46 @@ impl<'a, K: Key> Iterator<'a, K> {
     /// return the last element of the iterator
     pub fn last(self) -> Option<(K, Vec<u8>)> {
         self.seek_to_last();
-        Some((self.key(), self.value()))
+        if self.at_last() {
+            Some((self.key(), self.value()))
+        } else {
+            None
+        }
+    }
+
+    /// Whether `seek_to_last` found an entry within `from`.
+    fn at_last(&self) -> bool {
+        self.valid() && within(self.iter.ptr, self.order, self.prefix, self.from, Ordering::Less)
     }
 }
 
 impl<'a, K: Key> LevelDBIterator<'a, K> for Iterator<'a,K> {
     type RevIter = RevIterator<'a,K>;
 
+    fn advance(&mut self) -> bool {
+        advance(self);
+        if self.folding.is_some() {
+            unsafe { operands::skip_reserved(self.iter.ptr, false) };
+        }
+        self.valid() && within(self.iter.ptr, self.order, self.prefix, self.to, Ordering::Greater)
+    }
+
+    fn value(&self) -> Vec<u8> {
+        unsafe {
+            match self.folding {
+                Some(ref folding) => folding.value(self.iter.ptr),
+                None => raw_value(self.iter.ptr),
+            }
+        }
+    }
+
+    fn seek_to_last(&self) {
+        seek_to_last(self);
+        if self.folding.is_some() {
+            unsafe { operands::skip_reserved(self.iter.ptr, true) };
+        }
+    }
+
     #[inline]
     fn raw_iterator(&self) -> *mut leveldb_iterator_t {
         self.iter.ptr
@@ -245,14 +433,17 @@ impl<'a, K: Key> LevelDBIterator<'a, K> for Iterator<'a,K> {
     #[inline]
     fn reverse(self) -> Self::RevIter {
         if self.start {
-            unsafe { leveldb_iter_seek_to_last(self.iter.ptr); }
+            unsafe { seek_to_end(self.iter.ptr, self.prefix) };
         }
         RevIterator {
             start: self.start,
             database: self.database,
+            order: self.order,
             iter: self.iter,
+            folding: self.folding,
             from: self.from,
             to: self.to,
+            prefix: self.prefix,
         }
     }
 
@@ -273,11 +464,45 @@ impl<'a, K: Key> LevelDBIterator<'a, K> for Iterator<'a,K> {
     fn to_key(&self) -> Option<&K> {
         self.to
     }
+
+    fn prefix(mut self, prefix: &'a [u8]) -> Self {
+        self.prefix = prefix;
+        self.seek_to_first();
+        self
+    }
+
+    fn key_prefix(&self) -> &[u8] {
+        self.prefix
+    }
 }
 
 impl<'a, K: Key> LevelDBIterator<'a, K> for RevIterator<'a,K> {
     type RevIter = Iterator<'a,K>;
 
+    fn advance(&mut self) -> bool {
+        advance(self);
+        if self.folding.is_some() {
+            unsafe { operands::skip_reserved(self.iter.ptr, true) };
+        }
+        self.valid() && within(self.iter.ptr, self.order, self.prefix, self.to, Ordering::Less)
+    }
+
+    fn value(&self) -> Vec<u8> {
+        unsafe {
+            match self.folding {
+                Some(ref folding) => folding.value(self.iter.ptr),
+                None => raw_value(self.iter.ptr),
+            }
+        }
+    }
+
+    fn seek_to_last(&self) {
+        seek_to_last(self);
+        if self.folding.is_some() {
+            unsafe { operands::skip_reserved(self.iter.ptr, true) };
+        }
+    }
+
     #[inline]
     fn raw_iterator(&self) -> *mut leveldb_iterator_t {
         self.iter.ptr
@@ -298,17 +523,24 @@ impl<'a, K: Key> LevelDBIterator<'a, K> for RevIterator<'a,K> {
         leveldb_iter_prev(self.raw_iterator());
     }
 
+    fn seek_from(&self, key: &K) {
+        seek_before(self, key)
+    }
+
     #[inline]
     fn reverse(self) -> Self::RevIter {
         if self.start {
-            unsafe { leveldb_iter_seek_to_first(self.iter.ptr); }
+            self.seek_to_first();
         }
         Iterator {
             start: self.start,
             database: self.database,
+            order: self.order,
             iter: self.iter,
+            folding: self.folding,
             from: self.from,
             to: self.to,
+            prefix: self.prefix,
         }
     }
 
@@ -329,6 +561,16 @@ impl<'a, K: Key> LevelDBIterator<'a, K> for RevIterator<'a,K> {
     fn to_key(&self) -> Option<&K> {
         self.to
     }
+
+    fn prefix(mut self, prefix: &'a [u8]) -> Self {
+        self.prefix = prefix;
+        unsafe { seek_to_end(self.iter.ptr, prefix) };
+        self
+    }
+
+    fn key_prefix(&self) -> &[u8] {
+        self.prefix
+    }
 }
 
 impl<'a,K: Key> KeyIterator<'a,K> {
@@ -339,7 +581,11 @@ impl<'a,K: Key> KeyIterator<'a,K> {
     /// return the last element of the iterator
     pub fn last(self) -> Option<K> {
         self.seek_to_last();
-        Some(self.key())
+        if self.inner.at_last() {
+            Some(self.key())
+        } else {
+            None
+        }
     }
 }
 
@@ -351,7 +597,11 @@ impl<'a,K: Key> ValueIterator<'a,K> {
     /// return the last element of the iterator
     pub fn last(self) -> Option<Vec<u8>> {
         self.seek_to_last();
-        Some(self.value())
+        if self.inner.at_last() {
+            Some(self.value())
+        } else {
+            None
+        }
     }
 }
 
@@ -359,6 +609,18 @@ macro_rules! impl_leveldb_iterator {
     ($T:ty, $RevT:ty) => {
         impl<'a,K: Key> LevelDBIterator<'a, K> for $T {
             type RevIter = $RevT;
+
+            fn advance(&mut self) -> bool {
+                self.inner.advance()
+            }
+
+            fn value(&self) -> Vec<u8> {
+                self.inner.value()
+            }
+
+            fn seek_to_last(&self) {
+                self.inner.seek_to_last()
+            }
         
             #[inline]
             fn raw_iterator(&self) -> *mut leveldb_iterator_t {
@@ -402,6 +664,15 @@ macro_rules! impl_leveldb_iterator {
             fn to_key(&self) -> Option<&K> {
                 self.inner.to
             }
+
+            fn prefix(mut self, prefix: &'a [u8]) -> Self {
+                self.inner = self.inner.prefix(prefix);
+                self
+            }
+
+            fn key_prefix(&self) -> &[u8] {
+                self.inner.prefix
+            }
         }
     };
 }
@@ -0,0 +1,270 @@
+//! Keyspaces, giving column-family style namespaces within one database.
+//!
+//! A `Keyspace` transparently prefixes all keys with a four-byte id that
+//! is allocated when the keyspace is first opened. The mapping from names
+//! to ids is stored in a reserved metadata area at the start of the
+//! keyspace (prefix id `0`), so it survives reopening the database.
+//!
+//! As the prefixed keys are built from the key's binary representation,
+//! the database must use a key type that accepts any byte string, like
+//! `ByteKey`. Key types of a fixed width, like `i32`, panic when a
+//! prefixed key is converted back.
+use std::borrow::Borrow;
+
+use super::Database;
+use super::batch::{Batch, Writebatch, WritebatchIterator};
+use super::bytes::Bytes;
+use super::compaction::Compaction;
+use super::error::Error;
+use super::iterator::{Iterable, Iterator, KeyIterator, LevelDBIterator, ValueIterator};
+use super::key::{Key, from_u8};
+use super::kv::KV;
+use options::{ReadOptions, WriteOptions};
+
+const PREFIX_LEN: usize = 4;
+const METADATA_PREFIX: [u8; PREFIX_LEN] = [0, 0, 0, 0];
+const REGISTRY_ENTRY: &[u8] = b"keyspace:";
+const NEXT_ID: &[u8] = b"next-keyspace-id";
+/// The largest keyspace id; the prefix `\xff\xff\xff\xff` is reserved
+/// for the database's own entries.
+const MAX_ID: u32 = 0xffff_fffe;
+
+/// A namespace within a database.
+///
+/// Iterating over a keyspace through `Iterable` visits only its entries,
+/// returning keys without the keyspace prefix.
+///
+/// # Panics
+///
+/// Operations panic if the key type can't represent a prefixed key, see
+/// the module documentation.
+pub struct Keyspace<'a, K: Key + 'a> {
+    database: &'a Database<K>,
+    name: String,
+    prefix: [u8; PREFIX_LEN],
+}
+
+/// A write batch spanning several keyspaces of one database.
+///
+/// Write it atomically using `Batch::write` on the database and
+/// `KeyspaceBatch::writebatch`.
+pub struct KeyspaceBatch<K: Key> {
+    batch: Writebatch<K>,
+}
+
+fn encode_id(id: u32) -> [u8; PREFIX_LEN] {
+    [(id >> 24) as u8, (id >> 16) as u8, (id >> 8) as u8, id as u8]
+}
+
+fn decode_id(bytes: &[u8]) -> Result<u32, Error> {
+    if bytes.len() != PREFIX_LEN {
+        return Err(Error::new("corrupt keyspace registry".to_string()));
+    }
+    Ok(bytes.iter().fold(0, |acc, &b| acc << 8 | b as u32))
+}
+
+fn exhausted() -> Error {
+    Error::new("all keyspace ids are in use".to_string())
+}
+
+fn join<K: Key>(prefix: &[u8], rest: &[u8]) -> K {
+    let mut bytes = Vec::with_capacity(prefix.len() + rest.len());
+    bytes.extend_from_slice(prefix);
+    bytes.extend_from_slice(rest);
+    from_u8(&bytes)
+}
+
+fn prefix_key<K: Key>(prefix: &[u8], key: &K) -> K {
+    key.as_slice(|k| join(prefix, k))
+}
+
+impl<'a, K: Key + 'a> Keyspace<'a, K> {
+    /// Open the keyspace called `name`, registering it if it doesn't
+    /// exist yet.
+    pub fn open(database: &'a Database<K>, name: &str) -> Result<Keyspace<'a, K>, Error> {
+        let entry_key: K = join(&METADATA_PREFIX, &[REGISTRY_ENTRY, name.as_bytes()].concat());
+        let next_key: K = join(&METADATA_PREFIX, NEXT_ID);
+
+        let id = match database.get(ReadOptions::new(), &entry_key)? {
+            Some(id) => decode_id(&id)?,
+            None => {
+                let _lock = database.lock_manager().lock(&next_key)?;
+                match database.get(ReadOptions::new(), &entry_key)? {
+                    Some(id) => decode_id(&id)?,
+                    None => {
+                        let id = match database.get(ReadOptions::new(), &next_key)? {
+                            Some(next) => decode_id(&next)?,
+                            None => 1,
+                        };
+                        if id > MAX_ID {
+                            return Err(exhausted());
+                        }
+                        let next = id + 1;
+                        let mut batch = Writebatch::new();
+                        batch.put(entry_key, &encode_id(id));
+                        batch.put(next_key, &encode_id(next));
+                        database.write(WriteOptions::new(), &batch)?;
+                        id
+                    }
+                }
+            }
+        };
+
+        Ok(Keyspace {
+            database,
+            name: name.to_string(),
+            prefix: encode_id(id),
+        })
+    }
+
+    /// List the names of all registered keyspaces.
+    pub fn list(database: &Database<K>) -> Result<Vec<String>, Error> {
+        let start: K = join(&METADATA_PREFIX, REGISTRY_ENTRY);
+        let registry = [&METADATA_PREFIX[..], REGISTRY_ENTRY].concat();
+        let mut iter = database.keys_iter(ReadOptions::new()).from(&start);
+        let mut names = vec![];
+        while iter.advance() {
+            let name = iter.key().as_slice(|k| {
+                if k.starts_with(&registry) {
+                    Some(String::from_utf8_lossy(&k[registry.len()..]).into_owned())
+                } else {
+                    None
+                }
+            });
+            match name {
+                Some(name) => names.push(name),
+                None => break,
+            }
+        }
+        Ok(names)
+    }
+
+    /// The name of this keyspace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The prefix prepended to all keys of this keyspace.
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// The database this keyspace lives in.
+    pub fn database(&self) -> &'a Database<K> {
+        self.database
+    }
+
+    /// Return the key as stored in the database.
+    pub fn prefixed(&self, key: &K) -> K {
+        prefix_key(&self.prefix, key)
+    }
+
+    /// Compact all entries of this keyspace.
+    pub fn compact_all(&self) -> Result<(), Error> {
+        let id = decode_id(&self.prefix)?;
+        let start: K = join(&self.prefix, &[]);
+        let limit: K = join(&encode_id(id.checked_add(1).ok_or_else(exhausted)?), &[]);
+        self.database.compact(&start, &limit);
+        Ok(())
+    }
+}
+
+impl<'a, K: Key + 'a> KV<K> for Keyspace<'a, K> {
+    fn get<'b, BK: Borrow<K>>(&self, options: ReadOptions<'b, K>, key: BK) -> Result<Option<Vec<u8>>, Error> {
+        self.database.get(options, self.prefixed(key.borrow()))
+    }
+
+    fn get_bytes<'b, BK: Borrow<K>>(&self, options: ReadOptions<'b, K>, key: BK) -> Result<Option<Bytes>, Error> {
+        self.database.get_bytes(options, self.prefixed(key.borrow()))
+    }
+
+    fn put<BK: Borrow<K>>(&self, options: WriteOptions, key: BK, value: &[u8]) -> Result<(), Error> {
+        self.database.put(options, self.prefixed(key.borrow()), value)
+    }
+
+    fn delete<BK: Borrow<K>>(&self, options: WriteOptions, key: BK) -> Result<(), Error> {
+        self.database.delete(options, self.prefixed(key.borrow()))
+    }
+}
+
+struct Prefixer<K: Key> {
+    prefix: [u8; PREFIX_LEN],
+    batch: Writebatch<K>,
+}
+
+impl<K: Key> WritebatchIterator for Prefixer<K> {
+    type K = K;
+
+    fn put(&mut self, key: K, value: &[u8]) {
+        let key = prefix_key(&self.prefix, &key);
+        self.batch.put(key, value);
+    }
+
+    fn deleted(&mut self, key: K) {
+        let key = prefix_key(&self.prefix, &key);
+        self.batch.delete(key);
+    }
+
+    fn merge(&mut self, key: K, operand: &[u8]) {
+        let key = prefix_key(&self.prefix, &key);
+        self.batch.merge(key, operand);
+    }
+}
+
+impl<'a, K: Key + 'a> Batch<K> for Keyspace<'a, K> {
+    /// Write a batch of keys local to this keyspace.
+    fn write(&self, options: WriteOptions, batch: &Writebatch<K>) -> Result<(), Error> {
+        let prefixer = batch.iterate(Box::new(Prefixer {
+            prefix: self.prefix,
+            batch: Writebatch::new(),
+        }));
+        self.database.write(options, &prefixer.batch)
+    }
+}
+
+impl<'a, 'b, K: Key + 'b> Iterable<'b, K> for Keyspace<'a, K> {
+    fn iter(&'b self, options: ReadOptions<'b, K>) -> Iterator<'b, K> {
+        self.database.iter(options).prefix(&self.prefix)
+    }
+
+    fn keys_iter(&'b self, options: ReadOptions<'b, K>) -> KeyIterator<'b, K> {
+        self.database.keys_iter(options).prefix(&self.prefix)
+    }
+
+    fn value_iter(&'b self, options: ReadOptions<'b, K>) -> ValueIterator<'b, K> {
+        self.database.value_iter(options).prefix(&self.prefix)
+    }
+}
+
+impl<'a, 'b, K: Key + 'b> Compaction<'b, K> for Keyspace<'a, K> {
+    fn compact(&self, start: &'b K, limit: &'b K) {
+        self.database.compact(&self.prefixed(start), &self.prefixed(limit));
+    }
+}
+
+impl<K: Key> KeyspaceBatch<K> {
+    /// Create a new, empty batch.
+    pub fn new() -> KeyspaceBatch<K> {
+        KeyspaceBatch { batch: Writebatch::new() }
+    }
+
+    /// Batch a put operation into `keyspace`.
+    pub fn put(&mut self, keyspace: &Keyspace<K>, key: K, value: &[u8]) {
+        self.batch.put(keyspace.prefixed(&key), value);
+    }
+
+    /// Batch a delete operation from `keyspace`.
+    pub fn delete(&mut self, keyspace: &Keyspace<K>, key: K) {
+        self.batch.delete(keyspace.prefixed(&key));
+    }
+
+    /// Clear the batch.
+    pub fn clear(&mut self) {
+        self.batch.clear();
+    }
+
+    /// The underlying batch, holding prefixed keys.
+    pub fn writebatch(&self) -> &Writebatch<K> {
+        &self.batch
+    }
+}
@@ -2,7 +2,7 @@
 
 use super::Database;
 
-use options::{WriteOptions, ReadOptions, c_writeoptions, c_readoptions};
+use options::{WriteOptions, ReadOptions};
 use super::error::Error;
 use database::key::Key;
 use std::ptr;
@@ -10,6 +10,8 @@ use std::borrow::Borrow;
 use libc::{c_char, size_t};
 use leveldb_sys::*;
 use super::bytes::Bytes;
+use super::changes::Change;
+use super::operands::{self, Op};
 
 /// Key-Value-Access to the leveldb database, providing
 /// a basic interface.
@@ -54,26 +56,33 @@ impl<K: Key> KV<K> for Database<K> {
     /// The database will be synced to disc if `options.sync == true`. This is
     /// NOT the default.
     fn put<BK: Borrow<K>>(&self, options: WriteOptions, key: BK, value: &[u8]) -> Result<(), Error> {
-        unsafe {
-            key.borrow().as_slice(|k| {
-                let mut error = ptr::null_mut();
-                let c_writeoptions = c_writeoptions(options);
-                leveldb_put(self.database.ptr,
-                            c_writeoptions,
-                            k.as_ptr() as *mut c_char,
-                            k.len() as size_t,
-                            value.as_ptr() as *mut c_char,
-                            value.len() as size_t,
-                            &mut error);
-                leveldb_writeoptions_destroy(c_writeoptions);
+        let key = key.borrow();
+        let change = || vec![Change::Put { key: key.as_slice(|k| k.to_vec()), value: value.to_vec() }];
+        self.changes.publish(|| {
+            unsafe {
+                key.as_slice(|k| {
+                    if let Some(ref operands) = self.operands {
+                        return operands.write(self.database.ptr,
+                                              self.writeoptions(options).raw_ptr(),
+                                              &[Op::Put(k, value)]);
+                    }
+                    let mut error = ptr::null_mut();
+                    leveldb_put(self.database.ptr,
+                                self.writeoptions(options).raw_ptr(),
+                                k.as_ptr() as *mut c_char,
+                                k.len() as size_t,
+                                value.as_ptr() as *mut c_char,
+                                value.len() as size_t,
+                                &mut error);
 
-                if error == ptr::null_mut() {
-                    Ok(())
-                } else {
-                    Err(Error::new_from_char(error))
-                }
-            })
-        }
+                    if error == ptr::null_mut() {
+                        Ok(())
+                    } else {
+                        Err(Error::new_from_char(error))
+                    }
+                })
+            }
+        }, change)
     }
 
     /// delete a value from the database.
@@ -83,38 +92,49 @@ impl<K: Key> KV<K> for Database<K> {
     /// The database will be synced to disc if `options.sync == true`. This is
     /// NOT the default.
     fn delete<BK: Borrow<K>>(&self, options: WriteOptions, key: BK) -> Result<(), Error> {
-        unsafe {
-            key.borrow().as_slice(|k| {
-                let mut error = ptr::null_mut();
-                let c_writeoptions = c_writeoptions(options);
-                leveldb_delete(self.database.ptr,
-                               c_writeoptions,
-                               k.as_ptr() as *mut c_char,
-                               k.len() as size_t,
-                               &mut error);
-                leveldb_writeoptions_destroy(c_writeoptions);
-                if error == ptr::null_mut() {
-                    Ok(())
-                } else {
-                    Err(Error::new_from_char(error))
-                }
-            })
-        }
+        let key = key.borrow();
+        let change = || vec![Change::Delete { key: key.as_slice(|k| k.to_vec()) }];
+        self.changes.publish(|| {
+            unsafe {
+                key.as_slice(|k| {
+                    if let Some(ref operands) = self.operands {
+                        return operands.write(self.database.ptr,
+                                              self.writeoptions(options).raw_ptr(),
+                                              &[Op::Delete(k)]);
+                    }
+                    let mut error = ptr::null_mut();
+                    leveldb_delete(self.database.ptr,
+                                   self.writeoptions(options).raw_ptr(),
+                                   k.as_ptr() as *mut c_char,
+                                   k.len() as size_t,
+                                   &mut error);
+                    if error == ptr::null_mut() {
+                        Ok(())
+                    } else {
+                        Err(Error::new_from_char(error))
+                    }
+                })
+            }
+        }, change)
     }
 
     fn get_bytes<'a, BK: Borrow<K>>(&self, options: ReadOptions<'a, K>, key: BK) -> Result<Option<Bytes>, Error> {
-        unsafe {
+        if let Some(ref operands) = self.operands {
+            let value = self.with_snapshot(&options, |readoptions| unsafe {
+                key.borrow().as_slice(|k| operands.get(self.database.ptr, readoptions.raw_ptr(), k))
+            })?;
+            return Ok(value.map(|v| operands::to_bytes(&v)));
+        }
+        self.with_readoptions(&options, |readoptions| unsafe {
             key.borrow().as_slice(|k| {
                 let mut error = ptr::null_mut();
                 let mut length: size_t = 0;
-                let c_readoptions = c_readoptions(&options);
                 let result = leveldb_get(self.database.ptr,
-                                         c_readoptions,
+                                         readoptions.raw_ptr(),
                                          k.as_ptr() as *mut c_char,
                                          k.len() as size_t,
                                          &mut length,
                                          &mut error);
-                leveldb_readoptions_destroy(c_readoptions);
 
                 if error == ptr::null_mut() {
                     Ok(Bytes::from_raw(result as *mut u8, length))
@@ -122,10 +142,15 @@ impl<K: Key> KV<K> for Database<K> {
                     Err(Error::new_from_char(error))
                 }
             })
-        }
+        })
     }
 
     fn get<'a, BK: Borrow<K>>(&self, options: ReadOptions<'a, K>, key: BK) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(ref operands) = self.operands {
+            return self.with_snapshot(&options, |readoptions| unsafe {
+                key.borrow().as_slice(|k| operands.get(self.database.ptr, readoptions.raw_ptr(), k))
+            });
+        }
         self.get_bytes(options, key).map(|val| val.map(Into::into))
     }
 }
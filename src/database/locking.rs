@@ -0,0 +1,220 @@
+//! Pessimistic key locking for read-modify-write cycles.
+//!
+//! leveldb synchronises single writes internally, but offers no way to
+//! read a value and write back a derived one atomically. Every `Database`
+//! carries a `LockManager` that serialises such cycles per key within one
+//! process. Keys are hashed onto a fixed set of lock stripes, so unrelated
+//! keys may occasionally wait on each other.
+//!
+//! Locks held through this module are advisory: writes that don't go
+//! through the lock manager (e.g. plain `KV::put`) are not blocked.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::error::Error;
+use super::key::Key;
+use super::kv::KV;
+use options::{ReadOptions, WriteOptions};
+
+/// The number of lock stripes a database is opened with.
+pub const DEFAULT_LOCK_STRIPES: usize = 64;
+
+struct Stripe {
+    locked: Mutex<bool>,
+    released: Condvar,
+}
+
+/// A set of striped locks over the keyspace of a database.
+pub struct LockManager {
+    stripes: Vec<Stripe>,
+    timeout: Option<Duration>,
+}
+
+/// A held lock on one or more keys.
+///
+/// The locks are released when this value is dropped.
+pub struct KeyLock<'a> {
+    manager: &'a LockManager,
+    stripes: Vec<usize>,
+}
+
+impl LockManager {
+    /// Create a lock manager with `stripes` locks.
+    ///
+    /// `timeout` is used by `lock` and `lock_all`. `None` waits forever.
+    pub fn new(stripes: usize, timeout: Option<Duration>) -> LockManager {
+        assert!(stripes > 0, "a lock manager needs at least one stripe");
+        LockManager {
+            stripes: (0..stripes)
+                .map(|_| {
+                    Stripe {
+                        locked: Mutex::new(false),
+                        released: Condvar::new(),
+                    }
+                })
+                .collect(),
+            timeout,
+        }
+    }
+
+    /// The timeout applied when acquiring locks.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Lock a single key, waiting at most for the configured timeout.
+    pub fn lock<'a, K: Key>(&'a self, key: &K) -> Result<KeyLock<'a>, Error> {
+        let timeout = self.timeout;
+        self.lock_all_timeout(&[key], timeout)
+    }
+
+    /// Lock a set of keys, waiting at most for the configured timeout.
+    pub fn lock_all<'a, K: Key>(&'a self, keys: &[&K]) -> Result<KeyLock<'a>, Error> {
+        let timeout = self.timeout;
+        self.lock_all_timeout(keys, timeout)
+    }
+
+    /// Lock a set of keys, waiting at most for `timeout`.
+    ///
+    /// Stripes are always acquired in ascending order, so concurrent
+    /// multi-key locks can't deadlock each other. Keys mapping to the
+    /// same stripe are locked once. If the timeout elapses, all stripes
+    /// acquired so far are released and an error is returned.
+    ///
+    /// Locks are not reentrant: locking a key while already holding a
+    /// lock on a key of the same stripe blocks until the timeout.
+    pub fn lock_all_timeout<'a, K: Key>(&'a self,
+                                        keys: &[&K],
+                                        timeout: Option<Duration>)
+                                        -> Result<KeyLock<'a>, Error> {
+        let mut indices: Vec<usize> = keys.iter().map(|k| self.stripe_for(*k)).collect();
+        indices.sort();
+        indices.dedup();
+
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut held = KeyLock {
+            manager: self,
+            stripes: Vec::with_capacity(indices.len()),
+        };
+        for index in indices {
+            if !self.acquire(index, deadline) {
+                return Err(Error::new("timed out waiting for key lock".to_string()));
+            }
+            held.stripes.push(index);
+        }
+        Ok(held)
+    }
+
+    fn stripe_for<K: Key>(&self, key: &K) -> usize {
+        let hash = key.as_slice(|k| {
+            let mut hasher = DefaultHasher::new();
+            hasher.write(k);
+            hasher.finish()
+        });
+        (hash % self.stripes.len() as u64) as usize
+    }
+
+    fn acquire(&self, index: usize, deadline: Option<Instant>) -> bool {
+        let stripe = &self.stripes[index];
+        let mut locked = stripe.locked.lock().unwrap();
+        while *locked {
+            match deadline {
+                None => locked = stripe.released.wait(locked).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    locked = stripe.released.wait_timeout(locked, deadline - now).unwrap().0;
+                }
+            }
+        }
+        *locked = true;
+        true
+    }
+
+    fn release(&self, index: usize) {
+        let stripe = &self.stripes[index];
+        *stripe.locked.lock().unwrap() = false;
+        stripe.released.notify_one();
+    }
+}
+
+impl<'a> Drop for KeyLock<'a> {
+    fn drop(&mut self) {
+        for &index in self.stripes.iter().rev() {
+            self.manager.release(index);
+        }
+    }
+}
+
+/// Atomic read-modify-write operations on single keys.
+///
+/// Updates are serialised through the database's `LockManager`, so they
+/// are only atomic with respect to other updates going through it.
+pub trait ReadModifyWrite<K: Key> {
+    /// Replace the value of `key` by the result of `f`.
+    ///
+    /// `f` receives the current value, if any. Returning `None` deletes
+    /// the key. The new value is returned.
+    fn update<F>(&self, options: WriteOptions, key: K, f: F) -> Result<Option<Vec<u8>>, Error>
+        where F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>;
+
+    /// Set `key` to `new` if its current value equals `expected`.
+    ///
+    /// `None` stands for an absent key on both sides. Returns whether the
+    /// swap took place.
+    fn compare_and_swap(&self,
+                        options: WriteOptions,
+                        key: K,
+                        expected: Option<&[u8]>,
+                        new: Option<&[u8]>)
+                        -> Result<bool, Error>;
+}
+
+impl<K: Key> Database<K> {
+    /// The lock manager used for read-modify-write operations.
+    pub fn lock_manager(&self) -> &LockManager {
+        &self.locks
+    }
+
+    fn write_value(&self, options: WriteOptions, key: K, value: Option<&[u8]>) -> Result<(), Error> {
+        let mut batch = Writebatch::new();
+        match value {
+            Some(v) => batch.put(key, v),
+            None => batch.delete(key),
+        }
+        self.write(options, &batch)
+    }
+}
+
+impl<K: Key> ReadModifyWrite<K> for Database<K> {
+    fn update<F>(&self, options: WriteOptions, key: K, f: F) -> Result<Option<Vec<u8>>, Error>
+        where F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>
+    {
+        let _lock = self.locks.lock(&key)?;
+        let old = self.get(ReadOptions::new(), &key)?;
+        let new = f(old.as_ref().map(|v| &v[..]));
+        self.write_value(options, key, new.as_ref().map(|v| &v[..]))?;
+        Ok(new)
+    }
+
+    fn compare_and_swap(&self,
+                        options: WriteOptions,
+                        key: K,
+                        expected: Option<&[u8]>,
+                        new: Option<&[u8]>)
+                        -> Result<bool, Error> {
+        let _lock = self.locks.lock(&key)?;
+        let current = self.get(ReadOptions::new(), &key)?;
+        if current.as_ref().map(|v| &v[..]) != expected {
+            return Ok(false);
+        }
+        self.write_value(options, key, new)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,349 @@
+//! A reader and a writer for leveldb's write-ahead log files (`.log`).
+//!
+//! Log files are a sequence of 32KiB blocks. Every write batch is stored
+//! as one logical record, split into fragments that don't cross block
+//! boundaries. Each fragment has a header holding a masked CRC32C, its
+//! length and whether it is the full record or its first, a middle or the
+//! last part.
+//!
+//! The reader tolerates torn tails: an incomplete fragment at the end of
+//! the file is treated as the end of the log, and read again once more
+//! data has been appended. This allows tailing the log of a live database.
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use super::batch::WritebatchIterator;
+use super::coding::{decode_fixed32, decode_fixed64, get_length_prefixed, put_fixed32};
+use super::crc32c;
+use super::error::Error;
+use super::key::{Key, from_u8};
+
+/// The size of the blocks of a log file.
+pub const BLOCK_SIZE: usize = 32768;
+/// The length of the header of every fragment.
+pub const HEADER_LEN: usize = 7;
+
+const ZERO_TYPE: u8 = 0;
+const FULL_TYPE: u8 = 1;
+const FIRST_TYPE: u8 = 2;
+const MIDDLE_TYPE: u8 = 3;
+const LAST_TYPE: u8 = 4;
+
+const BATCH_HEADER_LEN: usize = 12;
+const DELETION: u8 = 0;
+const VALUE: u8 = 1;
+
+/// Reads logical records from a log file.
+pub struct LogReader<R: Read> {
+    reader: R,
+    block: Vec<u8>,
+    pos: usize,
+    pending: Vec<u8>,
+    fragmented: bool,
+}
+
+/// Writes logical records to a log file.
+///
+/// The MANIFEST uses the same format, so this also writes descriptors.
+pub struct LogWriter<W: Write> {
+    writer: W,
+    block_offset: usize,
+}
+
+/// A write batch decoded from a log record.
+pub struct LogBatch {
+    /// The sequence number of the first write in the batch.
+    ///
+    /// Writes in the batch are numbered consecutively from it.
+    pub sequence: u64,
+    count: u32,
+    record: Vec<u8>,
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(format!("corrupt log: {}", message))
+}
+
+/// List the log files in the database directory `dir`, oldest first.
+pub fn log_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut logs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let number = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.ends_with(".log") => name[..name.len() - 4].parse::<u64>().ok(),
+            _ => None,
+        };
+        if let Some(number) = number {
+            logs.push((number, path));
+        }
+    }
+    logs.sort();
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+impl LogReader<File> {
+    /// Open the log file at `path`.
+    pub fn open(path: &Path) -> Result<LogReader<File>, Error> {
+        let file = File::open(path).map_err(|e| Error::new(format!("reading {}: {}", path.display(), e)))?;
+        Ok(LogReader::new(file))
+    }
+}
+
+impl<R: Read> LogReader<R> {
+    /// Read a log from the start of `reader`.
+    pub fn new(reader: R) -> LogReader<R> {
+        LogReader {
+            reader,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            pos: 0,
+            pending: vec![],
+            fragmented: false,
+        }
+    }
+
+    /// Read more of the current block, returning whether anything was read.
+    fn fill(&mut self) -> Result<bool, Error> {
+        let start = self.block.len();
+        self.block.resize(BLOCK_SIZE, 0);
+        let mut len = start;
+        while len < BLOCK_SIZE {
+            match self.reader.read(&mut self.block[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.block.truncate(len);
+                    return Err(e.into());
+                }
+            }
+        }
+        self.block.truncate(len);
+        Ok(len > start)
+    }
+
+    /// Skip the rest of the current block.
+    fn skip_block(&mut self) {
+        self.pos = BLOCK_SIZE;
+    }
+
+    /// Read the next fragment, or `None` at the (possibly torn) end.
+    fn read_fragment(&mut self) -> Result<Option<(u8, Vec<u8>)>, Error> {
+        loop {
+            if self.block.len().saturating_sub(self.pos) < HEADER_LEN {
+                if self.block.len() == BLOCK_SIZE {
+                    // the remains of a block are zero padding
+                    self.block.clear();
+                    self.pos = 0;
+                } else if !self.fill()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            let header = &self.block[self.pos..self.pos + HEADER_LEN];
+            let length = (header[4] as usize) | (header[5] as usize) << 8;
+            let kind = header[6];
+            let expected = crc32c::unmask(decode_fixed32(header));
+            if kind == ZERO_TYPE && length == 0 {
+                // preallocated space that was never written
+                return Ok(None);
+            }
+            let end = self.pos + HEADER_LEN + length;
+            if end > BLOCK_SIZE {
+                self.skip_block();
+                return Err(corrupt("bad record length"));
+            }
+            if end > self.block.len() {
+                if !self.fill()? {
+                    return Ok(None);
+                }
+                continue;
+            }
+            let data = &self.block[self.pos + HEADER_LEN - 1..end];
+            if crc32c::value(data) != expected {
+                self.skip_block();
+                return Err(corrupt("checksum mismatch"));
+            }
+            let fragment = (kind, data[1..].to_vec());
+            self.pos = end;
+            return Ok(Some(fragment));
+        }
+    }
+
+    /// Read the next logical record.
+    ///
+    /// Returns `None` at the end of the log. If the log is still being
+    /// written, later calls return the records appended in the meantime.
+    /// After a corruption error, reading resumes with the next block.
+    pub fn read_record(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        loop {
+            let (kind, data) = match self.read_fragment()? {
+                Some(fragment) => fragment,
+                None => return Ok(None),
+            };
+            match kind {
+                FULL_TYPE => {
+                    self.fragmented = false;
+                    self.pending.clear();
+                    return Ok(Some(data));
+                }
+                FIRST_TYPE => {
+                    self.fragmented = true;
+                    self.pending = data;
+                }
+                MIDDLE_TYPE | LAST_TYPE if !self.fragmented => {
+                    return Err(corrupt("missing start of fragmented record"));
+                }
+                MIDDLE_TYPE => self.pending.extend_from_slice(&data),
+                LAST_TYPE => {
+                    self.fragmented = false;
+                    self.pending.extend_from_slice(&data);
+                    return Ok(Some(::std::mem::take(&mut self.pending)));
+                }
+                _ => return Err(corrupt("unknown record type")),
+            }
+        }
+    }
+
+    /// Read the next record and decode it as a write batch.
+    pub fn read_batch(&mut self) -> Result<Option<LogBatch>, Error> {
+        match self.read_record()? {
+            Some(record) => LogBatch::decode(record).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<W: Write> LogWriter<W> {
+    /// Write a log to `writer`, which must be at the start of a block.
+    pub fn new(writer: W) -> LogWriter<W> {
+        LogWriter {
+            writer,
+            block_offset: 0,
+        }
+    }
+
+    /// Append a record, split into fragments at block boundaries.
+    pub fn add_record(&mut self, mut record: &[u8]) -> Result<(), Error> {
+        let mut first = true;
+        loop {
+            let left = BLOCK_SIZE - self.block_offset;
+            if left < HEADER_LEN {
+                // too small for a header, pad the block with zeroes
+                self.writer.write_all(&[0; HEADER_LEN][..left])?;
+                self.block_offset = 0;
+            }
+            let len = record.len().min(BLOCK_SIZE - self.block_offset - HEADER_LEN);
+            let last = len == record.len();
+            let kind = match (first, last) {
+                (true, true) => FULL_TYPE,
+                (true, false) => FIRST_TYPE,
+                (false, false) => MIDDLE_TYPE,
+                (false, true) => LAST_TYPE,
+            };
+            self.emit_fragment(kind, &record[..len])?;
+            record = &record[len..];
+            first = false;
+            if last {
+                return Ok(());
+            }
+        }
+    }
+
+    fn emit_fragment(&mut self, kind: u8, data: &[u8]) -> Result<(), Error> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        put_fixed32(&mut header, crc32c::mask(crc32c::extend(crc32c::value(&[kind]), data)));
+        header.push(data.len() as u8);
+        header.push((data.len() >> 8) as u8);
+        header.push(kind);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        self.block_offset += HEADER_LEN + data.len();
+        Ok(())
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(Error::from)
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<R: Read> iter::Iterator for LogReader<R> {
+    type Item = Result<LogBatch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_batch() {
+            Ok(batch) => batch.map(Ok),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Split the next entry of a batch into its key and value, which is
+/// `None` for deletions.
+fn next_entry<'a>(src: &mut &'a [u8]) -> Result<(&'a [u8], Option<&'a [u8]>), Error> {
+    let (&tag, rest) = src.split_first().ok_or_else(|| corrupt("truncated batch"))?;
+    *src = rest;
+    let key = get_length_prefixed(src).ok_or_else(|| corrupt("bad batch key"))?;
+    match tag {
+        VALUE => {
+            let value = get_length_prefixed(src).ok_or_else(|| corrupt("bad batch value"))?;
+            Ok((key, Some(value)))
+        }
+        DELETION => Ok((key, None)),
+        _ => Err(corrupt("unknown batch entry type")),
+    }
+}
+
+impl LogBatch {
+    /// Decode a log record as a write batch, validating all entries.
+    pub fn decode(record: Vec<u8>) -> Result<LogBatch, Error> {
+        if record.len() < BATCH_HEADER_LEN {
+            return Err(corrupt("batch too short"));
+        }
+        let count = decode_fixed32(&record[8..]);
+        let mut src = &record[BATCH_HEADER_LEN..];
+        let mut found = 0;
+        while !src.is_empty() {
+            next_entry(&mut src)?;
+            found += 1;
+        }
+        if found != count {
+            return Err(corrupt("wrong batch count"));
+        }
+        Ok(LogBatch {
+            sequence: decode_fixed64(&record),
+            count,
+            record,
+        })
+    }
+
+    /// The number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Whether the batch contains no writes.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Iterate over the writes in the batch, like `Writebatch::iterate`.
+    pub fn iterate<K: Key, T: WritebatchIterator<K = K>>(&self, mut iterator: Box<T>) -> Box<T> {
+        let mut src = &self.record[BATCH_HEADER_LEN..];
+        // entries were validated when decoding
+        while let Ok((key, value)) = next_entry(&mut src) {
+            match value {
+                Some(value) => iterator.put(from_u8(key), value),
+                None => iterator.deleted(from_u8(key)),
+            }
+        }
+        iterator
+    }
+}
@@ -0,0 +1,280 @@
+//! A reader and a writer for leveldb's MANIFEST, the descriptor log of a
+//! database.
+//!
+//! The file named by `CURRENT` is a log (see `log`) of `VersionEdit`
+//! records. Each edit adds and removes table files at levels and updates
+//! the counters of the database. Replaying all edits yields the current
+//! `VersionSet`: which files live at which level and their key ranges.
+//!
+//! The database must not be open while its MANIFEST is read, since
+//! leveldb may be rewriting it.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::coding::{get_length_prefixed, get_varint64, put_length_prefixed, put_varint64};
+use super::error::Error;
+use super::log::LogReader;
+use super::table::InternalKey;
+
+/// The number of levels of a leveldb database.
+pub const NUM_LEVELS: usize = 7;
+
+const COMPARATOR: u64 = 1;
+const LOG_NUMBER: u64 = 2;
+const NEXT_FILE_NUMBER: u64 = 3;
+const LAST_SEQUENCE: u64 = 4;
+const COMPACT_POINTER: u64 = 5;
+const DELETED_FILE: u64 = 6;
+const NEW_FILE: u64 = 7;
+const PREV_LOG_NUMBER: u64 = 9;
+
+/// Describes a table file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMetaData {
+    /// The number of the file, as in `000042.ldb`.
+    pub number: u64,
+    /// The size of the file in bytes.
+    pub file_size: u64,
+    /// The smallest key in the file.
+    pub smallest: InternalKey,
+    /// The largest key in the file.
+    pub largest: InternalKey,
+}
+
+/// A change to the state of a database, as recorded in the MANIFEST.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// The name of the comparator the database was created with.
+    pub comparator: Option<String>,
+    /// The number of the current log file.
+    pub log_number: Option<u64>,
+    /// The number of the log file being compacted, if any.
+    pub prev_log_number: Option<u64>,
+    /// The number the next new file will get.
+    pub next_file_number: Option<u64>,
+    /// The sequence number of the last write.
+    pub last_sequence: Option<u64>,
+    /// Where the next compaction of a level starts, as level and key.
+    pub compact_pointers: Vec<(usize, InternalKey)>,
+    /// Files removed, as level and file number.
+    pub deleted_files: Vec<(usize, u64)>,
+    /// Files added, with their level.
+    pub new_files: Vec<(usize, FileMetaData)>,
+}
+
+/// The state of a database, reconstructed from its MANIFEST.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionSet {
+    /// The name of the comparator the database was created with.
+    pub comparator: Option<String>,
+    /// The number of the current log file.
+    pub log_number: u64,
+    /// The number of the log file being compacted, or 0.
+    pub prev_log_number: u64,
+    /// The number the next new file will get.
+    pub next_file_number: u64,
+    /// The sequence number of the last write.
+    pub last_sequence: u64,
+    /// Where the next compaction of each level starts.
+    pub compact_pointers: Vec<Option<InternalKey>>,
+    levels: Vec<Vec<FileMetaData>>,
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(format!("corrupt manifest: {}", message))
+}
+
+fn get_level(src: &mut &[u8]) -> Result<usize, Error> {
+    match get_varint64(src) {
+        Some(level) if level < NUM_LEVELS as u64 => Ok(level as usize),
+        _ => Err(corrupt("invalid level")),
+    }
+}
+
+fn get_number(src: &mut &[u8], field: &str) -> Result<u64, Error> {
+    get_varint64(src).ok_or_else(|| corrupt(&format!("invalid {}", field)))
+}
+
+fn get_internal_key(src: &mut &[u8]) -> Result<InternalKey, Error> {
+    let encoded = get_length_prefixed(src).ok_or_else(|| corrupt("invalid internal key"))?;
+    InternalKey::decode(encoded)
+}
+
+impl VersionEdit {
+    /// Decode an edit from a MANIFEST record.
+    pub fn decode(mut src: &[u8]) -> Result<VersionEdit, Error> {
+        let src = &mut src;
+        let mut edit = VersionEdit::default();
+        while !src.is_empty() {
+            match get_number(src, "tag")? {
+                COMPARATOR => {
+                    let name = get_length_prefixed(src).ok_or_else(|| corrupt("invalid comparator name"))?;
+                    edit.comparator = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                LOG_NUMBER => edit.log_number = Some(get_number(src, "log number")?),
+                PREV_LOG_NUMBER => edit.prev_log_number = Some(get_number(src, "previous log number")?),
+                NEXT_FILE_NUMBER => edit.next_file_number = Some(get_number(src, "next file number")?),
+                LAST_SEQUENCE => edit.last_sequence = Some(get_number(src, "last sequence number")?),
+                COMPACT_POINTER => {
+                    let level = get_level(src)?;
+                    edit.compact_pointers.push((level, get_internal_key(src)?));
+                }
+                DELETED_FILE => {
+                    let level = get_level(src)?;
+                    edit.deleted_files.push((level, get_number(src, "deleted file")?));
+                }
+                NEW_FILE => {
+                    let level = get_level(src)?;
+                    let file = FileMetaData {
+                        number: get_number(src, "file number")?,
+                        file_size: get_number(src, "file size")?,
+                        smallest: get_internal_key(src)?,
+                        largest: get_internal_key(src)?,
+                    };
+                    edit.new_files.push((level, file));
+                }
+                tag => return Err(corrupt(&format!("unknown tag {}", tag))),
+            }
+        }
+        Ok(edit)
+    }
+
+    /// Encode this edit as a MANIFEST record.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut dst = vec![];
+        if let Some(ref comparator) = self.comparator {
+            put_varint64(&mut dst, COMPARATOR);
+            put_length_prefixed(&mut dst, comparator.as_bytes());
+        }
+        let numbers = [(LOG_NUMBER, self.log_number),
+                       (PREV_LOG_NUMBER, self.prev_log_number),
+                       (NEXT_FILE_NUMBER, self.next_file_number),
+                       (LAST_SEQUENCE, self.last_sequence)];
+        for &(tag, number) in &numbers {
+            if let Some(number) = number {
+                put_varint64(&mut dst, tag);
+                put_varint64(&mut dst, number);
+            }
+        }
+        for &(level, ref key) in &self.compact_pointers {
+            put_varint64(&mut dst, COMPACT_POINTER);
+            put_varint64(&mut dst, level as u64);
+            put_length_prefixed(&mut dst, &key.encode());
+        }
+        for &(level, number) in &self.deleted_files {
+            put_varint64(&mut dst, DELETED_FILE);
+            put_varint64(&mut dst, level as u64);
+            put_varint64(&mut dst, number);
+        }
+        for &(level, ref file) in &self.new_files {
+            put_varint64(&mut dst, NEW_FILE);
+            put_varint64(&mut dst, level as u64);
+            put_varint64(&mut dst, file.number);
+            put_varint64(&mut dst, file.file_size);
+            put_length_prefixed(&mut dst, &file.smallest.encode());
+            put_length_prefixed(&mut dst, &file.largest.encode());
+        }
+        dst
+    }
+}
+
+/// Return the path of the MANIFEST named by the `CURRENT` file of the
+/// database directory `dir`.
+pub fn current_manifest(dir: &Path) -> Result<PathBuf, Error> {
+    let path = dir.join("CURRENT");
+    let mut current = String::new();
+    File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut current))
+        .map_err(|e| Error::new(format!("reading {}: {}", path.display(), e)))?;
+    let name = current.trim_end_matches('\n');
+    if name.is_empty() || current.len() == name.len() || name.contains('/') {
+        return Err(Error::new(format!("invalid CURRENT file in {}", dir.display())));
+    }
+    Ok(dir.join(name))
+}
+
+/// Read all edits from the MANIFEST at `path`.
+pub fn read_manifest(path: &Path) -> Result<Vec<VersionEdit>, Error> {
+    let mut reader = LogReader::open(path)?;
+    let mut edits = vec![];
+    while let Some(record) = reader.read_record()? {
+        edits.push(VersionEdit::decode(&record)?);
+    }
+    Ok(edits)
+}
+
+impl VersionSet {
+    /// Reconstruct the state of the closed database in `dir`.
+    pub fn recover(dir: &Path) -> Result<VersionSet, Error> {
+        VersionSet::from_edits(read_manifest(&current_manifest(dir)?)?)
+    }
+
+    /// Reconstruct the state of a database by applying `edits` in order.
+    pub fn from_edits<I: IntoIterator<Item = VersionEdit>>(edits: I) -> Result<VersionSet, Error> {
+        let mut comparator = None;
+        let (mut log_number, mut prev_log_number) = (None, 0);
+        let (mut next_file_number, mut last_sequence) = (None, None);
+        let mut compact_pointers = vec![None; NUM_LEVELS];
+        let mut levels = vec![vec![]; NUM_LEVELS];
+
+        for edit in edits {
+            if edit.comparator.is_some() {
+                comparator = edit.comparator;
+            }
+            log_number = edit.log_number.or(log_number);
+            prev_log_number = edit.prev_log_number.unwrap_or(prev_log_number);
+            next_file_number = edit.next_file_number.or(next_file_number);
+            last_sequence = edit.last_sequence.or(last_sequence);
+            for (level, key) in edit.compact_pointers {
+                compact_pointers[level] = Some(key);
+            }
+            for (level, number) in edit.deleted_files {
+                levels[level].retain(|f: &FileMetaData| f.number != number);
+            }
+            for (level, file) in edit.new_files {
+                levels[level].retain(|f: &FileMetaData| f.number != file.number);
+                levels[level].push(file);
+            }
+        }
+
+        // level 0 files may overlap, and are ordered by age instead
+        levels[0].sort_by_key(|f| f.number);
+        for files in &mut levels[1..] {
+            files.sort_by(|a, b| a.smallest.user_key.cmp(&b.smallest.user_key));
+        }
+        Ok(VersionSet {
+            comparator,
+            log_number: log_number.ok_or_else(|| corrupt("no log number"))?,
+            prev_log_number,
+            next_file_number: next_file_number.ok_or_else(|| corrupt("no next file number"))?,
+            last_sequence: last_sequence.ok_or_else(|| corrupt("no last sequence number"))?,
+            compact_pointers,
+            levels,
+        })
+    }
+
+    /// The files at every level.
+    ///
+    /// Files at level 0 are ordered by file number. Files at higher levels
+    /// are ordered by their smallest key, assuming bytewise ordering.
+    pub fn levels(&self) -> &[Vec<FileMetaData>] {
+        &self.levels
+    }
+
+    /// The files at `level`.
+    pub fn files_at_level(&self, level: usize) -> &[FileMetaData] {
+        &self.levels[level]
+    }
+
+    /// The path of the table file `file` in the database directory `dir`.
+    ///
+    /// Newer versions of leveldb name table files `.ldb`, older ones `.sst`.
+    pub fn table_path(dir: &Path, file: &FileMetaData) -> PathBuf {
+        let path = dir.join(format!("{:06}.ldb", file.number));
+        if path.exists() {
+            return path;
+        }
+        dir.join(format!("{:06}.sst", file.number))
+    }
+}
@@ -0,0 +1,117 @@
+//! Merge operators, emulated on top of leveldb.
+//!
+//! leveldb has no native merge support. A database opened with
+//! `Options::merge_operator` instead keeps a list of entries for every key
+//! in a reserved area of the database, starting at the key
+//! `"\xff\xff\xff\xffleveldb.merge\x00"`. Merge operands are appended
+//! to that list, while puts and deletes replace it. `KV::get` and the
+//! database's iterators fold the operands into the value with the
+//! operator.
+//!
+//! A merge onto a list of `MAX_ENTRIES` entries folds them into a single
+//! value as it is written, so reads fold few operands however often a key
+//! is merged. `Merge::fold_range` folds the lists of a range ahead of
+//! time and compacts it, reclaiming the space of the replaced entries.
+//!
+//! The layout requires the default comparator, and all writes to the
+//! database must go through a handle with a merge operator.
+use std::borrow::Borrow;
+
+use leveldb_sys::{leveldb_create_iterator, leveldb_iter_destroy};
+
+use super::Database;
+use super::changes::Change;
+use super::error::Error;
+use super::key::{Key, from_u8};
+use super::operands::{self, Op};
+use options::{ReadOptions, WriteOptions};
+
+/// The most entries kept for a key. A merge onto as many entries folds
+/// them, along with the new operand, into one.
+pub const MAX_ENTRIES: usize = 16;
+
+/// A merge operator combines a value with a list of operands.
+pub trait MergeOperator {
+    /// Fold `operands` into `existing`, in the order they were recorded.
+    ///
+    /// `existing` is `None` if the key had no value before the first
+    /// operand was recorded.
+    fn full_merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8>;
+}
+
+/// Recording merge operands.
+pub trait Merge<K: Key> {
+    /// Record a merge operand for `key`.
+    ///
+    /// Fails unless the database was opened with a merge operator.
+    fn merge<BK: Borrow<K>>(&self, options: WriteOptions, key: BK, operand: &[u8]) -> Result<(), Error>;
+
+    /// Fold the operands of all keys between `from` and `to` (inclusive)
+    /// into their values, then compact that range.
+    ///
+    /// Returns the number of keys folded.
+    fn fold_range(&self, options: WriteOptions, from: &K, to: &K) -> Result<usize, Error>;
+}
+
+fn no_operator() -> Error {
+    Error::new("the database has no merge operator".to_string())
+}
+
+impl<K: Key> Database<K> {
+    /// Fold the entries of `key`, holding its lock.
+    fn fold_key(&self, options: WriteOptions, key: &[u8]) -> Result<(), Error> {
+        let operands = self.operands.as_ref().ok_or_else(no_operator)?;
+        let _lock = self.lock_manager().lock(&from_u8::<K>(key))?;
+        self.with_snapshot(&ReadOptions::new(), |readoptions| unsafe {
+            let exists = operands::exists(self.database.ptr, readoptions.raw_ptr(), key)?;
+            let iter = leveldb_create_iterator(self.database.ptr, readoptions.raw_ptr());
+            let entries = operands::entries(iter, key);
+            leveldb_iter_destroy(iter);
+            operands.compact_entries(self.database.ptr,
+                                     self.writeoptions(options).raw_ptr(),
+                                     key,
+                                     exists,
+                                     &entries)
+        })
+    }
+}
+
+impl<K: Key> Merge<K> for Database<K> {
+    fn merge<BK: Borrow<K>>(&self, options: WriteOptions, key: BK, operand: &[u8]) -> Result<(), Error> {
+        let operands = self.operands.as_ref().ok_or_else(no_operator)?;
+        let key = key.borrow();
+        let change = || {
+            vec![Change::Merge {
+                     key: key.as_slice(|k| k.to_vec()),
+                     operand: operand.to_vec(),
+                 }]
+        };
+        self.changes.publish(|| unsafe {
+            key.as_slice(|k| {
+                operands.write(self.database.ptr,
+                               self.writeoptions(options).raw_ptr(),
+                               &[Op::Merge(k, operand)])
+            })
+        }, change)
+    }
+
+    fn fold_range(&self, options: WriteOptions, from: &K, to: &K) -> Result<usize, Error> {
+        if self.operands.is_none() {
+            return Err(no_operator());
+        }
+        let from = from.as_slice(|k| k.to_vec());
+        let to = to.as_slice(|k| k.to_vec());
+        let mut folded = 0;
+        self.with_snapshot(&ReadOptions::new(), |readoptions| unsafe {
+            let iter = leveldb_create_iterator(self.database.ptr, readoptions.raw_ptr());
+            let result = operands::foldable(iter, &from, &to, |key| {
+                folded += 1;
+                self.fold_key(options, key)
+            });
+            leveldb_iter_destroy(iter);
+            result
+        })?;
+        unsafe { operands::compact_range(self.database.ptr, &from, &to) };
+        Ok(folded)
+    }
+}
@@ -0,0 +1,282 @@
+//! Hierarchical range hashes for comparing replicas.
+//!
+//! A `MerkleTree` hashes the entries of a database or snapshot into
+//! buckets by key prefix. Level `i` of the tree holds one node per
+//! distinct `i`-byte key prefix, down to the configured depth; keys shorter
+//! than the depth additionally get a node of their own. Every node carries
+//! the hash and number of the entries below it.
+//!
+//! Two trees of the same depth are compared top-down, descending only into
+//! nodes whose hashes differ, which yields the key ranges that need to be
+//! repaired. Trees can be encoded to exchange them between processes.
+//!
+//! Hashes are 128-bit FNV-1a, which detects accidental divergence but is
+//! not meant to withstand deliberate collisions.
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::coding::{get_length_prefixed, get_varint64, put_length_prefixed, put_varint64};
+use super::error::Error;
+use super::iterator::Iterable;
+use super::key::Key;
+use options::ReadOptions;
+
+/// The depth trees are built with by default.
+pub const DEFAULT_DEPTH: usize = 2;
+
+const FNV_OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+const FNV_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+/// The hash and size of a subtree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeHash {
+    /// The hash of all entries in the subtree, in key order.
+    pub hash: u128,
+    /// The number of entries in the subtree.
+    pub count: u64,
+}
+
+/// A range of keys, from `start` (inclusive) to `end` (exclusive).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyRange {
+    /// The first key of the range.
+    pub start: Vec<u8>,
+    /// The end of the range, or `None` if it extends to the last key.
+    pub end: Option<Vec<u8>>,
+}
+
+/// Range hashes over the keyspace of a database or snapshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleTree {
+    depth: usize,
+    levels: Vec<BTreeMap<Vec<u8>, NodeHash>>,
+    exact: BTreeMap<Vec<u8>, NodeHash>,
+}
+
+fn fnv(mut hash: u128, data: &[u8]) -> u128 {
+    for &b in data {
+        hash ^= b as u128;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+impl NodeHash {
+    fn empty() -> NodeHash {
+        NodeHash {
+            hash: FNV_OFFSET,
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, entry: u128) {
+        self.hash = fnv(self.hash, &entry.to_le_bytes());
+        self.count += 1;
+    }
+}
+
+impl KeyRange {
+    /// The range of all keys starting with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> KeyRange {
+        let mut end = prefix.to_vec();
+        while let Some(last) = end.pop() {
+            if last < 0xff {
+                end.push(last + 1);
+                return KeyRange {
+                    start: prefix.to_vec(),
+                    end: Some(end),
+                };
+            }
+        }
+        KeyRange {
+            start: prefix.to_vec(),
+            end: None,
+        }
+    }
+
+    /// The range holding only `key`.
+    pub fn single(key: &[u8]) -> KeyRange {
+        let mut end = key.to_vec();
+        end.push(0);
+        KeyRange {
+            start: key.to_vec(),
+            end: Some(end),
+        }
+    }
+
+    /// Whether `key` lies within the range.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= &self.start[..] && !matches!(self.end, Some(ref end) if key >= &end[..])
+    }
+}
+
+impl MerkleTree {
+    /// Create an empty tree of `depth` levels below the root.
+    pub fn new(depth: usize) -> MerkleTree {
+        let mut levels = vec![BTreeMap::new(); depth + 1];
+        levels[0].insert(vec![], NodeHash::empty());
+        MerkleTree {
+            depth,
+            levels,
+            exact: BTreeMap::new(),
+        }
+    }
+
+    /// Hash all entries of a database or snapshot.
+    pub fn build<'a, K, S>(source: &'a S, depth: usize) -> MerkleTree
+        where K: Key + 'a,
+              S: Iterable<'a, K>
+    {
+        let mut options = ReadOptions::new();
+        options.fill_cache = false;
+        let mut tree = MerkleTree::new(depth);
+        for (key, value) in source.iter(options) {
+            tree.insert(&key.as_slice(|k| k.to_vec()), &value);
+        }
+        tree
+    }
+
+    /// Add an entry to the tree.
+    ///
+    /// Trees are only comparable if entries are inserted in the same
+    /// order, e.g. the order of the database's comparator.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        let mut entry = fnv(FNV_OFFSET, &(key.len() as u64).to_le_bytes());
+        entry = fnv(entry, key);
+        entry = fnv(entry, &(value.len() as u64).to_le_bytes());
+        entry = fnv(entry, value);
+
+        let levels = self.depth.min(key.len());
+        for (i, level) in self.levels.iter_mut().enumerate().take(levels + 1) {
+            level.entry(key[..i].to_vec()).or_insert_with(NodeHash::empty).add(entry);
+        }
+        if key.len() < self.depth {
+            self.exact.entry(key.to_vec()).or_insert_with(NodeHash::empty).add(entry);
+        }
+    }
+
+    /// The depth of the tree.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The hash over all entries.
+    pub fn root(&self) -> NodeHash {
+        self.levels[0][&vec![]]
+    }
+
+    /// The nodes at `level`, keyed by their prefix.
+    pub fn level(&self, level: usize) -> &BTreeMap<Vec<u8>, NodeHash> {
+        &self.levels[level]
+    }
+
+    /// The children of the node `prefix` at `level`, keyed by their prefix
+    /// and whether they are a subtree. The key equal to `prefix` itself is
+    /// not a subtree, and sorts before all others.
+    fn children(&self, level: usize, prefix: &[u8]) -> BTreeMap<(Vec<u8>, bool), NodeHash> {
+        let mut children = BTreeMap::new();
+        if let Some(hash) = self.exact.get(prefix) {
+            children.insert((prefix.to_vec(), false), *hash);
+        }
+        let next = self.levels[level + 1]
+            .range(prefix.to_vec()..)
+            .take_while(|(child, _)| child.starts_with(prefix));
+        for (child, hash) in next {
+            children.insert((child.clone(), true), *hash);
+        }
+        children
+    }
+
+    /// Return the key ranges in which this tree and `other` differ, in key
+    /// order.
+    ///
+    /// Adjacent ranges are merged. Both trees must have the same depth.
+    pub fn diff(&self, other: &MerkleTree) -> Result<Vec<KeyRange>, Error> {
+        if self.depth != other.depth {
+            return Err(Error::new(format!("cannot compare trees of depth {} and {}", self.depth, other.depth)));
+        }
+        let mut ranges = vec![];
+        if self.root() != other.root() {
+            self.diff_node(other, 0, &[], &mut ranges);
+        }
+        Ok(ranges)
+    }
+
+    fn diff_node(&self, other: &MerkleTree, level: usize, prefix: &[u8], ranges: &mut Vec<KeyRange>) {
+        if level == self.depth {
+            push_range(ranges, KeyRange::prefix(prefix));
+            return;
+        }
+        let mine = self.children(level, prefix);
+        let theirs = other.children(level, prefix);
+        let children: BTreeSet<&(Vec<u8>, bool)> = mine.keys().chain(theirs.keys()).collect();
+        for child in children {
+            if mine.get(child) == theirs.get(child) {
+                continue;
+            }
+            let (ref child, subtree) = *child;
+            if subtree {
+                self.diff_node(other, level + 1, child, ranges);
+            } else {
+                push_range(ranges, KeyRange::single(child));
+            }
+        }
+    }
+
+    /// Encode the tree.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        put_varint64(&mut buf, self.depth as u64);
+        let maps = self.levels.iter().chain(Some(&self.exact));
+        for map in maps {
+            put_varint64(&mut buf, map.len() as u64);
+            for (prefix, node) in map {
+                put_length_prefixed(&mut buf, prefix);
+                buf.extend_from_slice(&node.hash.to_le_bytes());
+                put_varint64(&mut buf, node.count);
+            }
+        }
+        buf
+    }
+
+    /// Decode a tree encoded by `encode`.
+    pub fn decode(mut src: &[u8]) -> Result<MerkleTree, Error> {
+        let corrupt = || Error::new("corrupt merkle tree".to_string());
+        let src = &mut src;
+        let depth = get_varint64(src).filter(|&d| d < 256).ok_or_else(corrupt)? as usize;
+        let mut maps = vec![];
+        for _ in 0..depth + 2 {
+            let mut map = BTreeMap::new();
+            for _ in 0..get_varint64(src).ok_or_else(corrupt)? {
+                let prefix = get_length_prefixed(src).ok_or_else(corrupt)?.to_vec();
+                if src.len() < 16 {
+                    return Err(corrupt());
+                }
+                let mut hash = [0; 16];
+                hash.copy_from_slice(&src[..16]);
+                *src = &src[16..];
+                let count = get_varint64(src).ok_or_else(corrupt)?;
+                map.insert(prefix, NodeHash { hash: u128::from_le_bytes(hash), count });
+            }
+            maps.push(map);
+        }
+        let exact = maps.pop().ok_or_else(corrupt)?;
+        if !src.is_empty() || !maps[0].contains_key(&vec![]) {
+            return Err(corrupt());
+        }
+        Ok(MerkleTree {
+            depth,
+            levels: maps,
+            exact,
+        })
+    }
+}
+
+fn push_range(ranges: &mut Vec<KeyRange>, range: KeyRange) {
+    if let Some(last) = ranges.last_mut() {
+        if last.end.as_ref() == Some(&range.start) {
+            last.end = range.end;
+            return;
+        }
+    }
+    ranges.push(range);
+}
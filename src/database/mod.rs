@@ -4,15 +4,20 @@ extern crate db_key as key;
 
 use leveldb_sys::*;
 
-use self::options::{Options, c_options};
+use self::options::{Options, PreparedReadOptions, PreparedWriteOptions, ReadOptions, WriteOptions,
+                    READ_FLAGS, c_options, read_flags};
 use self::error::Error;
 use std::ffi::CString;
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use std::ptr;
-use comparator::{Comparator, create_comparator};
+use comparator::{Comparator, KeyOrder, create_comparator_with_order};
 use self::key::Key;
+use self::locking::{LockManager, DEFAULT_LOCK_STRIPES};
+use self::changes::ChangeFeed;
+use self::operands::Operands;
+use self::snapshots::Snapshots;
 
 use std::marker::PhantomData;
 use libc::c_char;
@@ -23,11 +28,38 @@ pub mod iterator;
 pub mod comparator;
 pub mod snapshots;
 pub mod cache;
+pub mod filter_policy;
 pub mod kv;
 pub mod batch;
 pub mod management;
 pub mod compaction;
 pub mod bytes;
+pub mod locking;
+pub mod merge;
+pub mod byte_key;
+pub mod keyspace;
+pub mod ttl;
+pub mod index;
+pub mod properties;
+pub mod dump;
+pub mod backup;
+pub mod table;
+pub mod log;
+pub mod manifest;
+pub mod changes;
+pub mod replication;
+pub mod merkle;
+pub mod diff;
+pub mod range_delete;
+pub mod bulk;
+pub mod multi_get;
+pub mod ingest;
+pub mod read_only;
+mod coding;
+mod operands;
+mod staging;
+mod crc32c;
+mod snappy;
 
 #[allow(missing_docs)]
 struct RawDB {
@@ -70,14 +102,26 @@ impl Drop for RawComparator {
 /// internally.
 pub struct Database<K: Key> {
     database: RawDB,
+    path: PathBuf,
     // this holds a reference passed into leveldb
     // it is never read from Rust, but must be kept around
     #[allow(dead_code)]
     comparator: Option<RawComparator>,
+    // compares keys like the comparator, for iterator bounds
+    order: KeyOrder,
     // these hold multiple references that are used by the leveldb library
     // and should survive as long as the database lives
     #[allow(dead_code)]
     options: Options,
+    locks: LockManager,
+    changes: ChangeFeed,
+    // prepared options, reused by every call
+    writeoptions: PreparedWriteOptions,
+    sync_writeoptions: PreparedWriteOptions,
+    // indexed by `read_flags`
+    readoptions: Vec<PreparedReadOptions>,
+    // set if the database was opened with a merge operator
+    operands: Option<Operands>,
     marker: PhantomData<K>,
 }
 
@@ -86,18 +130,66 @@ unsafe impl<K: Key> Send for Database<K> {}
 
 impl<K: Key> Database<K> {
     fn new(database: *mut leveldb_t,
+           path: &Path,
            options: Options,
-           comparator: Option<*mut leveldb_comparator_t>)
-           -> Database<K> {
-        let raw_comp = match comparator {
-            Some(p) => Some(RawComparator { ptr: p }),
-            None => None,
+           comparator: Option<(*mut leveldb_comparator_t, KeyOrder)>)
+           -> Result<Database<K>, Error> {
+        let (raw_comp, order) = match comparator {
+            Some((p, order)) => (Some(RawComparator { ptr: p }), order),
+            None => (None, KeyOrder::Bytewise),
         };
-        Database {
+        let locks = LockManager::new(DEFAULT_LOCK_STRIPES, options.lock_timeout);
+        let mut sync = WriteOptions::new();
+        sync.sync = true;
+        let operands = options.merge_operator
+            .as_ref()
+            .map(|operator| unsafe { Operands::open(database, operator.clone()) });
+        Ok(Database {
             database: RawDB { ptr: database },
+            path: path.to_path_buf(),
             comparator: raw_comp,
+            order: order,
             options: options,
+            locks: locks,
+            changes: ChangeFeed::new(),
+            writeoptions: PreparedWriteOptions::new(WriteOptions::new()),
+            sync_writeoptions: PreparedWriteOptions::new(sync),
+            readoptions: (0..READ_FLAGS)
+                .map(|flags| unsafe { PreparedReadOptions::new(flags, ptr::null_mut()) })
+                .collect(),
+            operands: operands.transpose()?,
             marker: PhantomData,
+        })
+    }
+
+    /// The prepared write options matching `options`.
+    fn writeoptions(&self, options: WriteOptions) -> &PreparedWriteOptions {
+        if options.sync {
+            &self.sync_writeoptions
+        } else {
+            &self.writeoptions
+        }
+    }
+
+    /// Call `f` with prepared read options matching `options`.
+    fn with_readoptions<T, F>(&self, options: &ReadOptions<K>, f: F) -> T
+        where F: FnOnce(&PreparedReadOptions) -> T
+    {
+        match options.snapshot {
+            Some(snapshot) => snapshot.with_readoptions(read_flags(options), f),
+            None => f(&self.readoptions[read_flags(options)]),
+        }
+    }
+
+    /// Call `f` with prepared read options matching `options`, taking a
+    /// snapshot if `options` names none, so several reads see the same
+    /// state.
+    fn with_snapshot<T, F>(&self, options: &ReadOptions<K>, f: F) -> T
+        where F: FnOnce(&PreparedReadOptions) -> T
+    {
+        match options.snapshot {
+            Some(snapshot) => snapshot.with_readoptions(read_flags(options), f),
+            None => self.snapshot().with_readoptions(read_flags(options), f),
         }
     }
 
@@ -116,7 +208,7 @@ impl<K: Key> Database<K> {
             leveldb_options_destroy(c_options);
 
             if error == ptr::null_mut() {
-                Ok(Database::new(db, options, None))
+                Database::new(db, name, options, None)
             } else {
                 Err(Error::new_from_char(error))
             }
@@ -131,12 +223,17 @@ impl<K: Key> Database<K> {
     /// The comparator must implement a total ordering over the keyspace.
     ///
     /// For keys that implement Ord, consider the `OrdComparator`.
+    ///
+    /// Databases with a merge operator must use the default comparator.
     pub fn open_with_comparator<C: Comparator<K = K>>(name: &Path,
                                                       options: Options,
                                                       comparator: C)
                                                       -> Result<Database<K>, Error> {
+        if options.merge_operator.is_some() {
+            return Err(Error::new("a merge operator requires the default comparator".to_string()));
+        }
         let mut error = ptr::null_mut();
-        let comp_ptr = create_comparator(Box::new(comparator));
+        let (comp_ptr, order) = create_comparator_with_order(Box::new(comparator));
         unsafe {
             let c_string = CString::new(name.to_str().unwrap()).unwrap();
             let c_options = c_options(&options, Some(comp_ptr));
@@ -146,10 +243,15 @@ impl<K: Key> Database<K> {
             leveldb_options_destroy(c_options);
 
             if error == ptr::null_mut() {
-                Ok(Database::new(db, options, Some(comp_ptr)))
+                Database::new(db, name, options, Some((comp_ptr, order)))
             } else {
                 Err(Error::new_from_char(error))
             }
         }
     }
+
+    /// The directory the database was opened from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 }
@@ -0,0 +1,144 @@
+//! Batched point lookups.
+//!
+//! `MultiGet::multi_get` looks up many keys at once. All lookups read from
+//! the same snapshot and share one set of read options. Keys are looked up
+//! in sorted order, which touches each table block once. For larger
+//! batches on databases using the default, bytewise ordering, a single
+//! iterator is used, stepping forward to nearby keys instead of seeking.
+use std::ptr;
+use std::slice::from_raw_parts;
+
+use leveldb_sys::*;
+use libc::{c_char, size_t};
+
+use super::Database;
+use super::bytes::Bytes;
+use super::error::Error;
+use super::key::Key;
+use super::snapshots::Snapshots;
+use options::ReadOptions;
+
+/// Batches with at least this many keys are read with an iterator.
+const ITERATOR_THRESHOLD: usize = 16;
+/// How many entries the iterator steps over before seeking instead.
+const MAX_STEPS: usize = 4;
+
+/// The result of looking up a key.
+pub type GetResult = Result<Option<Vec<u8>>, Error>;
+
+/// Lookup of many keys at once.
+pub trait MultiGet<K: Key> {
+    /// Look up all `keys` in one snapshot.
+    ///
+    /// If `options` names no snapshot, one is taken for the duration of
+    /// the call. The results are in the order of `keys`.
+    fn multi_get<'a>(&'a self, options: ReadOptions<'a, K>, keys: &[K]) -> Vec<GetResult>;
+}
+
+unsafe fn iter_key<'a>(iter: *mut leveldb_iterator_t) -> &'a [u8] {
+    let length: size_t = 0;
+    let key = leveldb_iter_key(iter, &length) as *const u8;
+    from_raw_parts(key, length as usize)
+}
+
+unsafe fn iter_value(iter: *mut leveldb_iterator_t) -> Vec<u8> {
+    let length: size_t = 0;
+    let value = leveldb_iter_value(iter, &length) as *const u8;
+    from_raw_parts(value, length as usize).to_vec()
+}
+
+impl<K: Key> Database<K> {
+    unsafe fn get_raw(&self, c_readoptions: *mut leveldb_readoptions_t, key: &[u8]) -> GetResult {
+        let mut error = ptr::null_mut();
+        let mut length: size_t = 0;
+        let result = leveldb_get(self.database.ptr,
+                                 c_readoptions,
+                                 key.as_ptr() as *mut c_char,
+                                 key.len() as size_t,
+                                 &mut length,
+                                 &mut error);
+        if error.is_null() {
+            Ok(Bytes::from_raw(result as *mut u8, length).map(Into::into))
+        } else {
+            Err(Error::new_from_char(error))
+        }
+    }
+
+    /// Look up the sorted `keys` with a single iterator, storing the value
+    /// of `keys[n].0` at `results[keys[n].1]`.
+    ///
+    /// Returns false if the iterator failed.
+    unsafe fn seek_all(&self,
+                       c_readoptions: *mut leveldb_readoptions_t,
+                       keys: &[(Vec<u8>, usize)],
+                       results: &mut [Option<GetResult>])
+                       -> bool {
+        let iter = leveldb_create_iterator(self.database.ptr, c_readoptions);
+        let mut positioned = false;
+        for &(ref key, i) in keys {
+            let mut steps = 0;
+            while positioned && steps < MAX_STEPS && leveldb_iter_valid(iter) != 0 &&
+                  iter_key(iter) < &key[..] {
+                leveldb_iter_next(iter);
+                steps += 1;
+            }
+            if !positioned || leveldb_iter_valid(iter) == 0 || iter_key(iter) < &key[..] {
+                leveldb_iter_seek(iter, key.as_ptr() as *mut c_char, key.len() as size_t);
+                positioned = true;
+            }
+            let found = leveldb_iter_valid(iter) != 0 && iter_key(iter) == &key[..];
+            results[i] = Some(Ok(if found { Some(iter_value(iter)) } else { None }));
+        }
+        let mut error: *mut c_char = ptr::null_mut();
+        leveldb_iter_get_error(iter, &mut error as *mut _ as *const *const c_char);
+        leveldb_iter_destroy(iter);
+        if error.is_null() {
+            true
+        } else {
+            leveldb_free(error as *mut _);
+            false
+        }
+    }
+
+    fn multi_get_at(&self, options: &ReadOptions<K>, keys: &[K]) -> Vec<GetResult> {
+        let mut sorted: Vec<(Vec<u8>, usize)> = keys.iter()
+            .enumerate()
+            .map(|(i, key)| (key.as_slice(|k| k.to_vec()), i))
+            .collect();
+        sorted.sort();
+        let mut results: Vec<Option<GetResult>> = keys.iter().map(|_| None).collect();
+        self.with_readoptions(options, |readoptions| unsafe {
+            let c_readoptions = readoptions.raw_ptr();
+            if let Some(ref operands) = self.operands {
+                for &(ref key, i) in &sorted {
+                    results[i] = Some(operands.get(self.database.ptr, c_readoptions, key));
+                }
+                return;
+            }
+            // with a custom comparator, iterator order differs from byte order
+            let iterated = self.comparator.is_none() && keys.len() >= ITERATOR_THRESHOLD &&
+                           self.seek_all(c_readoptions, &sorted, &mut results);
+            if !iterated {
+                for &(ref key, i) in &sorted {
+                    results[i] = Some(self.get_raw(c_readoptions, key));
+                }
+            }
+        });
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl<K: Key> MultiGet<K> for Database<K> {
+    fn multi_get<'a>(&'a self, options: ReadOptions<'a, K>, keys: &[K]) -> Vec<GetResult> {
+        if options.snapshot.is_some() {
+            return self.multi_get_at(&options, keys);
+        }
+        let snapshot = self.snapshot();
+        let options = ReadOptions {
+            verify_checksums: options.verify_checksums,
+            fill_cache: options.fill_cache,
+            snapshot: Some(&snapshot),
+        };
+        self.multi_get_at(&options, keys)
+    }
+}
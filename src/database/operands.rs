@@ -0,0 +1,469 @@
+//! The storage layout of databases with a merge operator.
+//!
+//! Every key of such a database is stored twice. The key itself holds an
+//! empty marker, so iteration finds it in key order. Its data lives in the
+//! operand area, a reserved range of keys starting with `PREFIX`, as a
+//! list of entries ordered by a sequence number:
+//!
+//! `PREFIX ENTRY escaped-key sequence -> tag data`
+//!
+//! A merge appends an `OPERAND` entry. A put replaces the entries of the
+//! key with a `BASE` entry, and a delete removes them. The value of a key
+//! is its last `BASE` entry (or none), folded with the operands that
+//! follow. Folding replaces the entries of a key with a single `BASE`
+//! entry; a merge folds them as it writes once a key has `MAX_ENTRIES`.
+//!
+//! Keys are escaped so the operand area is in the same order as the keys
+//! themselves: `0x00` is written as `0x00 0xff`, and the key is terminated
+//! by `0x00 0x01`. The last sequence number handed out is stored at
+//! `PREFIX COUNTER`, and written along with every entry.
+use std::collections::HashMap;
+use std::ptr;
+use std::slice::from_raw_parts;
+use std::sync::{Arc, Mutex};
+
+use leveldb_sys::*;
+use libc::{self, c_char, size_t};
+
+use super::bytes::Bytes;
+use super::coding::{decode_fixed64, put_fixed64};
+use super::error::Error;
+use super::merge::{MergeOperator, MAX_ENTRIES};
+
+/// The start of the operand area. Keys starting with it are reserved.
+pub const PREFIX: &[u8] = b"\xff\xff\xff\xffleveldb.merge\x00";
+/// The first key after the operand area.
+pub const PREFIX_END: &[u8] = b"\xff\xff\xff\xffleveldb.merge\x01";
+
+const COUNTER: u8 = 0;
+const ENTRY: u8 = 1;
+
+const BASE: u8 = 0;
+const OPERAND: u8 = 1;
+const DELETE: u8 = 2;
+
+/// A single write to a database with a merge operator.
+pub enum Op<'k> {
+    Put(&'k [u8], &'k [u8]),
+    Delete(&'k [u8]),
+    Merge(&'k [u8], &'k [u8]),
+}
+
+/// An entry of the operand area.
+pub struct Entry {
+    /// The operand key holding the entry.
+    pub raw_key: Vec<u8>,
+    tag: u8,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    /// The value the entry is stored with.
+    fn value(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(self.data.len() + 1);
+        value.push(self.tag);
+        value.extend_from_slice(&self.data);
+        value
+    }
+}
+
+/// The merge state of an open database.
+pub struct Operands {
+    operator: Arc<dyn MergeOperator + Send + Sync>,
+    // the last sequence number used; held while writing, so entries are
+    // written in the order of their numbers
+    sequence: Mutex<u64>,
+}
+
+/// The position in the operand area where the entries of `key` start.
+pub fn key_prefix(key: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(PREFIX.len() + key.len() + 3);
+    raw.extend_from_slice(PREFIX);
+    raw.push(ENTRY);
+    for &byte in key {
+        raw.push(byte);
+        if byte == 0 {
+            raw.push(0xff);
+        }
+    }
+    raw.extend_from_slice(&[0, 1]);
+    raw
+}
+
+fn entry_key(key: &[u8], sequence: u64) -> Vec<u8> {
+    let mut raw = key_prefix(key);
+    raw.extend_from_slice(&sequence.to_be_bytes());
+    raw
+}
+
+fn counter_key() -> Vec<u8> {
+    let mut raw = PREFIX.to_vec();
+    raw.push(COUNTER);
+    raw
+}
+
+/// Split an operand key into the key it belongs to and its sequence number.
+pub fn decode_entry_key(raw: &[u8]) -> Option<(Vec<u8>, u64)> {
+    if !raw.starts_with(PREFIX) || raw.get(PREFIX.len()) != Some(&ENTRY) {
+        return None;
+    }
+    let mut rest = &raw[PREFIX.len() + 1..];
+    let mut key = vec![];
+    loop {
+        match rest {
+            [0, 0xff, tail @ ..] => {
+                key.push(0);
+                rest = tail;
+            }
+            [0, 1, tail @ ..] => {
+                rest = tail;
+                break;
+            }
+            [0, ..] | [] => return None,
+            [byte, tail @ ..] => {
+                key.push(*byte);
+                rest = tail;
+            }
+        }
+    }
+    if rest.len() != 8 {
+        return None;
+    }
+    let mut sequence = [0; 8];
+    sequence.copy_from_slice(rest);
+    Some((key, u64::from_be_bytes(sequence)))
+}
+
+/// Whether `key` lies in the operand area.
+pub fn is_reserved(key: &[u8]) -> bool {
+    key.starts_with(PREFIX)
+}
+
+unsafe fn iter_key<'a>(iter: *mut leveldb_iterator_t) -> &'a [u8] {
+    let length: size_t = 0;
+    let key = leveldb_iter_key(iter, &length) as *const u8;
+    from_raw_parts(key, length as usize)
+}
+
+unsafe fn iter_value<'a>(iter: *mut leveldb_iterator_t) -> &'a [u8] {
+    let length: size_t = 0;
+    let value = leveldb_iter_value(iter, &length) as *const u8;
+    from_raw_parts(value, length as usize)
+}
+
+unsafe fn seek(iter: *mut leveldb_iterator_t, key: &[u8]) {
+    leveldb_iter_seek(iter, key.as_ptr() as *mut c_char, key.len() as size_t);
+}
+
+/// Move `iter` off the operand area, in the direction of iteration.
+pub unsafe fn skip_reserved(iter: *mut leveldb_iterator_t, reverse: bool) {
+    if leveldb_iter_valid(iter) == 0 || !is_reserved(iter_key(iter)) {
+        return;
+    }
+    if reverse {
+        seek(iter, PREFIX);
+        if leveldb_iter_valid(iter) != 0 {
+            leveldb_iter_prev(iter);
+        } else {
+            leveldb_iter_seek_to_last(iter);
+        }
+    } else {
+        seek(iter, PREFIX_END);
+    }
+}
+
+/// Read the value at `key` without interpreting it.
+unsafe fn get_raw(database: *mut leveldb_t,
+                  readoptions: *mut leveldb_readoptions_t,
+                  key: &[u8])
+                  -> Result<Option<Bytes>, Error> {
+    let mut error = ptr::null_mut();
+    let mut length: size_t = 0;
+    let result = leveldb_get(database,
+                             readoptions,
+                             key.as_ptr() as *mut c_char,
+                             key.len() as size_t,
+                             &mut length,
+                             &mut error);
+    if error.is_null() {
+        Ok(Bytes::from_raw(result as *mut u8, length))
+    } else {
+        Err(Error::new_from_char(error))
+    }
+}
+
+/// Whether `key` exists, reading its marker.
+pub unsafe fn exists(database: *mut leveldb_t,
+                     readoptions: *mut leveldb_readoptions_t,
+                     key: &[u8])
+                     -> Result<bool, Error> {
+    get_raw(database, readoptions, key).map(|marker| marker.is_some())
+}
+
+/// Copy `value` into memory that leveldb can free.
+pub fn to_bytes(value: &[u8]) -> Bytes {
+    unsafe {
+        let ptr = libc::malloc(value.len().max(1)) as *mut u8;
+        assert!(!ptr.is_null(), "out of memory");
+        ptr::copy_nonoverlapping(value.as_ptr(), ptr, value.len());
+        Bytes::from_raw_unchecked(ptr, value.len())
+    }
+}
+
+/// Read the entries of `key` from an iterator over the database.
+pub unsafe fn entries(iter: *mut leveldb_iterator_t, key: &[u8]) -> Vec<Entry> {
+    let prefix = key_prefix(key);
+    let mut entries = vec![];
+    seek(iter, &prefix);
+    while leveldb_iter_valid(iter) != 0 {
+        let raw_key = iter_key(iter);
+        if !raw_key.starts_with(&prefix) || raw_key.len() != prefix.len() + 8 {
+            break;
+        }
+        if let Some((&tag, data)) = iter_value(iter).split_first() {
+            entries.push(Entry {
+                raw_key: raw_key.to_vec(),
+                tag: tag,
+                data: data.to_vec(),
+            });
+        }
+        leveldb_iter_next(iter);
+    }
+    entries
+}
+
+impl Operands {
+    /// Load the merge state of an opened database.
+    pub unsafe fn open(database: *mut leveldb_t,
+                       operator: Arc<dyn MergeOperator + Send + Sync>)
+                       -> Result<Operands, Error> {
+        let readoptions = leveldb_readoptions_create();
+        let counter = get_raw(database, readoptions, &counter_key());
+        leveldb_readoptions_destroy(readoptions);
+        let sequence = match counter? {
+            Some(ref raw) if raw.len() == 8 => decode_fixed64(raw),
+            Some(_) => return Err(Error::new("corrupt merge sequence number".to_string())),
+            None => 0,
+        };
+        Ok(Operands {
+            operator,
+            sequence: Mutex::new(sequence),
+        })
+    }
+
+    /// Fold `entries` of `key` into its value.
+    pub fn fold(&self, key: &[u8], entries: &[Entry]) -> Vec<u8> {
+        let mut base = None;
+        let mut operands = vec![];
+        for entry in entries {
+            match entry.tag {
+                BASE => {
+                    base = Some(&entry.data[..]);
+                    operands.clear();
+                }
+                OPERAND => operands.push(&entry.data[..]),
+                _ => {
+                    base = None;
+                    operands.clear();
+                }
+            }
+        }
+        if operands.is_empty() {
+            base.map(|b| b.to_vec()).unwrap_or_default()
+        } else {
+            self.operator.full_merge(key, base, &operands)
+        }
+    }
+
+    /// Read the folded value of `key`.
+    ///
+    /// `readoptions` must name a snapshot, so the key and its entries are
+    /// read from the same state.
+    pub unsafe fn get(&self,
+                      database: *mut leveldb_t,
+                      readoptions: *mut leveldb_readoptions_t,
+                      key: &[u8])
+                      -> Result<Option<Vec<u8>>, Error> {
+        if !exists(database, readoptions, key)? {
+            return Ok(None);
+        }
+        let iter = leveldb_create_iterator(database, readoptions);
+        let entries = entries(iter, key);
+        leveldb_iter_destroy(iter);
+        Ok(Some(self.fold(key, &entries)))
+    }
+
+    /// Replace `entries` of `key` by a single entry holding their folded
+    /// value, or remove them if the key no longer exists.
+    ///
+    /// The folded value is stored under the last entry's operand key, so
+    /// entries written meanwhile still apply after it.
+    pub unsafe fn compact_entries(&self,
+                                  database: *mut leveldb_t,
+                                  writeoptions: *mut leveldb_writeoptions_t,
+                                  key: &[u8],
+                                  exists: bool,
+                                  entries: &[Entry])
+                                  -> Result<(), Error> {
+        let batch = leveldb_writebatch_create();
+        let (last, older) = match entries.split_last() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        for entry in older {
+            batch_delete(batch, &entry.raw_key);
+        }
+        if exists {
+            let mut value = vec![BASE];
+            value.extend_from_slice(&self.fold(key, entries));
+            batch_put(batch, &last.raw_key, &value);
+        } else {
+            batch_delete(batch, &last.raw_key);
+        }
+        let result = write_batch(database, writeoptions, batch);
+        leveldb_writebatch_destroy(batch);
+        result
+    }
+
+    /// Perform `ops` atomically.
+    ///
+    /// Puts and deletes remove the earlier entries of their key, and a
+    /// merge onto `MAX_ENTRIES` entries folds them, so no key holds more.
+    pub unsafe fn write(&self,
+                        database: *mut leveldb_t,
+                        writeoptions: *mut leveldb_writeoptions_t,
+                        ops: &[Op])
+                        -> Result<(), Error> {
+        let mut sequence = self.sequence.lock().unwrap();
+        let mut next = *sequence;
+        let batch = leveldb_writebatch_create();
+        // no other write changes entries while the sequence is held
+        let readoptions = leveldb_readoptions_create();
+        let iter = leveldb_create_iterator(database, readoptions);
+        // the entries of the keys written, as of the ops so far
+        let mut chains: HashMap<&[u8], Vec<Entry>> = HashMap::new();
+        for op in ops {
+            next += 1;
+            let (key, tag, data): (&[u8], u8, &[u8]) = match *op {
+                Op::Put(key, value) => (key, BASE, value),
+                Op::Delete(key) => (key, DELETE, &[]),
+                Op::Merge(key, operand) => (key, OPERAND, operand),
+            };
+            if tag == DELETE {
+                batch_delete(batch, key);
+            } else {
+                batch_put(batch, key, &[]);
+            }
+            let chain = chains.entry(key).or_insert_with(|| entries(iter, key));
+            let mut entry = Entry {
+                raw_key: entry_key(key, next),
+                tag: tag,
+                data: data.to_vec(),
+            };
+            if tag == OPERAND && chain.len() >= MAX_ENTRIES {
+                chain.push(entry);
+                let data = self.fold(key, chain);
+                entry = Entry {
+                    raw_key: chain.pop().unwrap().raw_key,
+                    tag: BASE,
+                    data: data,
+                };
+            }
+            if entry.tag != OPERAND {
+                for old in chain.drain(..) {
+                    batch_delete(batch, &old.raw_key);
+                }
+            }
+            if entry.tag != DELETE {
+                batch_put(batch, &entry.raw_key, &entry.value());
+                chain.push(entry);
+            }
+        }
+        leveldb_iter_destroy(iter);
+        leveldb_readoptions_destroy(readoptions);
+        let mut counter = vec![];
+        put_fixed64(&mut counter, next);
+        batch_put(batch, &counter_key(), &counter);
+        let result = write_batch(database, writeoptions, batch);
+        leveldb_writebatch_destroy(batch);
+        if result.is_ok() {
+            *sequence = next;
+        }
+        result
+    }
+}
+
+unsafe fn batch_delete(batch: *mut leveldb_writebatch_t, key: &[u8]) {
+    leveldb_writebatch_delete(batch, key.as_ptr() as *const c_char, key.len() as size_t);
+}
+
+unsafe fn batch_put(batch: *mut leveldb_writebatch_t, key: &[u8], value: &[u8]) {
+    leveldb_writebatch_put(batch,
+                           key.as_ptr() as *const c_char,
+                           key.len() as size_t,
+                           value.as_ptr() as *const c_char,
+                           value.len() as size_t);
+}
+
+unsafe fn write_batch(database: *mut leveldb_t,
+                      writeoptions: *mut leveldb_writeoptions_t,
+                      batch: *mut leveldb_writebatch_t)
+                      -> Result<(), Error> {
+    let mut error = ptr::null_mut();
+    leveldb_write(database, writeoptions, batch, &mut error);
+    if error.is_null() {
+        Ok(())
+    } else {
+        Err(Error::new_from_char(error))
+    }
+}
+
+/// Call `f` with every key between `from` and `to` (inclusive) whose
+/// entries can be folded, reading from `iter`.
+pub unsafe fn foldable<F>(iter: *mut leveldb_iterator_t, from: &[u8], to: &[u8], mut f: F) -> Result<(), Error>
+    where F: FnMut(&[u8]) -> Result<(), Error>
+{
+    seek(iter, &key_prefix(from));
+    let mut current: Option<(Vec<u8>, usize, u8)> = None;
+    loop {
+        let next = if leveldb_iter_valid(iter) != 0 {
+            decode_entry_key(iter_key(iter)).filter(|(key, _)| &key[..] <= to)
+        } else {
+            None
+        };
+        let same = match (&current, &next) {
+            (&Some((ref key, _, _)), &Some((ref next, _))) => key == next,
+            _ => false,
+        };
+        if !same {
+            if let Some((key, count, tag)) = current.take() {
+                // a single base entry is folded already
+                if count > 1 || tag != BASE {
+                    f(&key)?;
+                }
+            }
+        }
+        let (key, _) = match next {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+        let tag = iter_value(iter).first().cloned().unwrap_or(DELETE);
+        current = match current {
+            Some((key, count, _)) => Some((key, count + 1, tag)),
+            None => Some((key, 1, tag)),
+        };
+        leveldb_iter_next(iter);
+    }
+}
+
+/// Compact the entries of the keys between `from` and `to` (inclusive).
+pub unsafe fn compact_range(database: *mut leveldb_t, from: &[u8], to: &[u8]) {
+    let start = key_prefix(from);
+    let mut limit = key_prefix(to);
+    limit.extend_from_slice(&[0xff; 8]);
+    leveldb_compact_range(database,
+                          start.as_ptr() as *const c_char,
+                          start.len() as size_t,
+                          limit.as_ptr() as *const c_char,
+                          limit.len() as size_t);
+}
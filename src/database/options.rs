@@ -4,12 +4,20 @@
 //! * `Options`: used when opening a database
 //! * `ReadOptions`: used when reading from leveldb
 //! * `WriteOptions`: used when writng to leveldb
+//!
+//! Databases and snapshots keep the leveldb representation of these
+//! options prepared, so it is reused across calls instead of being created
+//! for every operation.
 use leveldb_sys::*;
 
 use libc::size_t;
+use std::sync::Arc;
+use std::time::Duration;
 use database::snapshots::Snapshot;
 use database::key::Key;
 use database::cache::Cache;
+use database::filter_policy::FilterPolicy;
+use database::merge::MergeOperator;
 
 /// Options to consider when opening a new or pre-existing database.
 ///
@@ -56,6 +64,26 @@ pub struct Options {
     ///
     /// default: None
     pub cache: Option<Cache>,
+    /// A filter policy, used to skip reading table files that do not
+    /// contain a key.
+    ///
+    /// default: None
+    pub filter_policy: Option<FilterPolicy>,
+    /// How long read-modify-write operations wait for a key lock.
+    ///
+    /// This is not passed to leveldb, but used by the database's
+    /// `LockManager`.
+    ///
+    /// default: None (wait forever)
+    pub lock_timeout: Option<Duration>,
+    /// A merge operator, enabling `Merge::merge`.
+    ///
+    /// Values are then stored in the layout described in the `merge`
+    /// module, so a database must always be opened with a merge operator,
+    /// or always without. Requires the default comparator.
+    ///
+    /// default: None
+    pub merge_operator: Option<Arc<dyn MergeOperator + Send + Sync>>,
 }
 
 impl Options {
@@ -71,6 +99,9 @@ impl Options {
             block_restart_interval: None,
             compression: Compression::No,
             cache: None,
+            filter_policy: None,
+            lock_timeout: None,
+            merge_operator: None,
         }
     }
 }
@@ -150,6 +181,9 @@ pub unsafe fn c_options(options: &Options,
     if let Some(ref cache) = options.cache {
         leveldb_options_set_cache(c_options, cache.raw_ptr());
     }
+    if let Some(ref policy) = options.filter_policy {
+        leveldb_options_set_filter_policy(c_options, policy.raw_ptr());
+    }
     c_options
 }
 
@@ -173,3 +207,73 @@ pub unsafe fn c_readoptions<'a, K>(options: &ReadOptions<'a, K>) -> *mut leveldb
     }
     c_readoptions
 }
+
+/// Write options converted to leveldb's representation, for reuse.
+pub(crate) struct PreparedWriteOptions {
+    ptr: *mut leveldb_writeoptions_t,
+}
+
+// leveldb only reads the options, so they can be shared between threads
+unsafe impl Send for PreparedWriteOptions {}
+unsafe impl Sync for PreparedWriteOptions {}
+
+impl PreparedWriteOptions {
+    /// Prepare `options`.
+    pub(crate) fn new(options: WriteOptions) -> PreparedWriteOptions {
+        PreparedWriteOptions { ptr: unsafe { c_writeoptions(options) } }
+    }
+
+    #[inline]
+    pub(crate) fn raw_ptr(&self) -> *mut leveldb_writeoptions_t {
+        self.ptr
+    }
+}
+
+impl Drop for PreparedWriteOptions {
+    fn drop(&mut self) {
+        unsafe { leveldb_writeoptions_destroy(self.ptr) }
+    }
+}
+
+/// The number of combinations of the flags in `ReadOptions`.
+pub(crate) const READ_FLAGS: usize = 4;
+
+/// The flags of `options`, as an index below `READ_FLAGS`.
+pub(crate) fn read_flags<K: Key>(options: &ReadOptions<K>) -> usize {
+    (options.verify_checksums as usize) << 1 | options.fill_cache as usize
+}
+
+/// Read options converted to leveldb's representation, for reuse.
+pub(crate) struct PreparedReadOptions {
+    ptr: *mut leveldb_readoptions_t,
+}
+
+unsafe impl Send for PreparedReadOptions {}
+unsafe impl Sync for PreparedReadOptions {}
+
+impl PreparedReadOptions {
+    /// Prepare options with the given `read_flags`, reading from
+    /// `snapshot` unless it is null.
+    ///
+    /// The prepared options must not outlive the snapshot.
+    pub(crate) unsafe fn new(flags: usize, snapshot: *mut leveldb_snapshot_t) -> PreparedReadOptions {
+        let c_readoptions = leveldb_readoptions_create();
+        leveldb_readoptions_set_verify_checksums(c_readoptions, (flags >> 1 & 1) as u8);
+        leveldb_readoptions_set_fill_cache(c_readoptions, (flags & 1) as u8);
+        if !snapshot.is_null() {
+            leveldb_readoptions_set_snapshot(c_readoptions, snapshot);
+        }
+        PreparedReadOptions { ptr: c_readoptions }
+    }
+
+    #[inline]
+    pub(crate) fn raw_ptr(&self) -> *mut leveldb_readoptions_t {
+        self.ptr
+    }
+}
+
+impl Drop for PreparedReadOptions {
+    fn drop(&mut self) {
+        unsafe { leveldb_readoptions_destroy(self.ptr) }
+    }
+}
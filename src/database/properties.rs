@@ -0,0 +1,41 @@
+//! Access to the properties leveldb exposes about a database.
+//!
+//! Known properties include:
+//!
+//! * `leveldb.num-files-at-level<N>`: the number of files at level N
+//! * `leveldb.stats`: multi-line statistics about the internal operation
+//! * `leveldb.sstables`: a description of all table files
+//! * `leveldb.approximate-memory-usage`: memory used by the database
+use leveldb_sys::leveldb_property_value;
+use libc::{c_char, c_void};
+use std::ffi::{CStr, CString};
+
+use super::Database;
+use super::key::Key;
+
+/// Structs implementing `Properties` can report leveldb properties.
+pub trait Properties {
+    /// Return the value of the property `name`, or `None` if the
+    /// property is unknown.
+    fn property(&self, name: &str) -> Option<String>;
+}
+
+impl<K: Key> Properties for Database<K> {
+    fn property(&self, name: &str) -> Option<String> {
+        let c_name = match CString::new(name) {
+            Ok(c_name) => c_name,
+            Err(_) => return None,
+        };
+        unsafe {
+            let value = leveldb_property_value(self.database.ptr,
+                                               c_name.as_bytes_with_nul().as_ptr() as *const c_char);
+            if value.is_null() {
+                None
+            } else {
+                let string = CStr::from_ptr(value).to_string_lossy().into_owned();
+                ::leveldb_sys::leveldb_free(value as *mut c_void);
+                Some(string)
+            }
+        }
+    }
+}
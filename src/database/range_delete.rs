@@ -0,0 +1,126 @@
+//! Deleting ranges of keys.
+//!
+//! leveldb has no native range deletion. `RangeDelete` scans the keys of
+//! the range without reading their values, deletes them in batches of
+//! bounded size and finally compacts the range, so the space held by the
+//! deleted entries is reclaimed.
+//!
+//! Range bounds are compared by the binary value of keys, so the helpers
+//! assume the database uses the default, bytewise comparator.
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::compaction::Compaction;
+use super::error::Error;
+use super::iterator::{Iterable, LevelDBIterator};
+use super::key::Key;
+use super::snapshots::Snapshot;
+use options::{ReadOptions, WriteOptions};
+
+/// The number of key bytes deleted per batch by default.
+pub const DEFAULT_BATCH_BYTES: usize = 1 << 20;
+
+/// Options for deleting ranges of keys.
+pub struct RangeDeleteOptions<'a, K: Key + 'a> {
+    /// The write options used for every batch.
+    pub write: WriteOptions,
+    /// Delete the keys present in this snapshot, rather than those present
+    /// when the scan starts.
+    ///
+    /// default: None
+    pub snapshot: Option<&'a Snapshot<'a, K>>,
+    /// The number of key bytes after which a batch is written.
+    ///
+    /// default: `DEFAULT_BATCH_BYTES`
+    pub max_batch_bytes: usize,
+    /// Whether to compact the range after deleting it.
+    ///
+    /// default: true
+    pub compact: bool,
+}
+
+impl<'a, K: Key + 'a> RangeDeleteOptions<'a, K> {
+    /// Return a `RangeDeleteOptions` struct with the default values.
+    pub fn new() -> RangeDeleteOptions<'a, K> {
+        RangeDeleteOptions {
+            write: WriteOptions::new(),
+            snapshot: None,
+            max_batch_bytes: DEFAULT_BATCH_BYTES,
+            compact: true,
+        }
+    }
+}
+
+/// Deletion of all keys in a range.
+pub trait RangeDelete<K: Key> {
+    /// Delete all keys from `from` (inclusive) up to `to` (exclusive).
+    ///
+    /// Returns the number of keys deleted.
+    fn delete_range<'a>(&'a self, options: RangeDeleteOptions<'a, K>, from: &K, to: &K) -> Result<usize, Error>;
+
+    /// Delete all keys starting with `prefix`.
+    ///
+    /// Returns the number of keys deleted.
+    fn delete_prefix<'a>(&'a self, options: RangeDeleteOptions<'a, K>, prefix: &[u8]) -> Result<usize, Error>;
+}
+
+impl<K: Key> Database<K> {
+    /// Delete the keys from `from` onwards for which `in_range` holds,
+    /// stopping at the first key for which it does not.
+    fn delete_while<'a, F>(&'a self, options: RangeDeleteOptions<'a, K>, from: &K, in_range: F) -> Result<usize, Error>
+        where F: Fn(&[u8]) -> bool
+    {
+        let mut read = ReadOptions::new();
+        read.fill_cache = false;
+        read.snapshot = options.snapshot;
+        let keys = self.keys_iter(read);
+        keys.seek(from);
+
+        let mut batch = Writebatch::new();
+        let (mut pending, mut batch_bytes) = (0, 0);
+        let mut deleted = 0;
+        let mut first = None;
+        let mut last = None;
+        for key in keys {
+            let bytes = key.as_slice(|k| k.to_vec());
+            if !in_range(&bytes) {
+                break;
+            }
+            batch_bytes += bytes.len();
+            if first.is_none() {
+                first = Some(bytes.clone());
+            }
+            last = Some(bytes);
+            batch.delete(key);
+            pending += 1;
+            if batch_bytes >= options.max_batch_bytes {
+                self.write(options.write, &batch)?;
+                batch.clear();
+                deleted += pending;
+                pending = 0;
+                batch_bytes = 0;
+            }
+        }
+        if pending > 0 {
+            self.write(options.write, &batch)?;
+            deleted += pending;
+        }
+
+        if options.compact {
+            if let (Some(first), Some(last)) = (first, last) {
+                self.compact(&K::from_u8(&first), &K::from_u8(&last));
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+impl<K: Key> RangeDelete<K> for Database<K> {
+    fn delete_range<'a>(&'a self, options: RangeDeleteOptions<'a, K>, from: &K, to: &K) -> Result<usize, Error> {
+        let end = to.as_slice(|t| t.to_vec());
+        self.delete_while(options, from, |k| k < &end[..])
+    }
+
+    fn delete_prefix<'a>(&'a self, options: RangeDeleteOptions<'a, K>, prefix: &[u8]) -> Result<usize, Error> {
+        self.delete_while(options, &K::from_u8(prefix), |k| k.starts_with(prefix))
+    }
+}
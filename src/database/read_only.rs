@@ -0,0 +1,173 @@
+//! Read-only access to a database that may be open in another process.
+//!
+//! leveldb takes an exclusive lock on the directory of an open database,
+//! so a second process can't open it, not even for reading. A
+//! `ReadOnlyDatabase` instead opens a private copy, with table files
+//! hard-linked into a staging directory where possible. By default, the
+//! staging directory is created next to the database, on the same
+//! filesystem. The copy is removed again when the `ReadOnlyDatabase` is
+//! dropped.
+//!
+//! The result is a point-in-time view: writes to the original database
+//! after opening are not visible. Writes to the view itself fail.
+//!
+//! If the original database changes while it is copied, for example
+//! because a compaction deleted a table file, copying starts over.
+use std::borrow::Borrow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Database;
+use super::bytes::Bytes;
+use super::comparator::Comparator;
+use super::error::Error;
+use super::iterator::{Iterable, Iterator, KeyIterator, ValueIterator};
+use super::key::Key;
+use super::kv::KV;
+use super::staging::{stage, StagingDir};
+use options::{Options, ReadOptions, WriteOptions};
+
+static STAGING: AtomicUsize = AtomicUsize::new(0);
+
+/// Options for opening a database with `ReadOnlyDatabase`.
+pub struct ReadOnlyOptions {
+    /// The directory to create the staging directory in. It should be on
+    /// the same filesystem as the database, so table files can be linked
+    /// instead of copied.
+    ///
+    /// default: None, the directory containing the database
+    pub staging_dir: Option<PathBuf>,
+    /// How often to start copying over when the database changes while
+    /// it is copied.
+    ///
+    /// default: 10
+    pub max_attempts: usize,
+}
+
+/// A point-in-time, read-only view of a database.
+///
+/// Reads work through `KV` and `Iterable`; `KV::put` and `KV::delete`
+/// return an error.
+pub struct ReadOnlyDatabase<K: Key> {
+    database: Database<K>,
+    // declared after the database, so it is closed before the staging
+    // directory is removed
+    #[allow(dead_code)]
+    staging: StagingDir,
+}
+
+fn error_for(action: &str, path: &Path, error: &io::Error) -> Error {
+    Error::new(format!("{} {}: {}", action, path.display(), error))
+}
+
+fn write_error() -> Error {
+    Error::new("database is open read-only".to_string())
+}
+
+impl ReadOnlyOptions {
+    /// Create default options for opening a database read-only.
+    pub fn new() -> ReadOnlyOptions {
+        ReadOnlyOptions {
+            staging_dir: None,
+            max_attempts: 10,
+        }
+    }
+}
+
+impl<K: Key> ReadOnlyDatabase<K> {
+    /// Open the database at `name` read-only.
+    ///
+    /// `options` are used to open the copy; `create_if_missing` is
+    /// ignored.
+    pub fn open(name: &Path, options: Options, read_only: ReadOnlyOptions) -> Result<ReadOnlyDatabase<K>, Error> {
+        ReadOnlyDatabase::open_staged(name, options, read_only, Database::open)
+    }
+
+    /// Open the database at `name` read-only, with a custom comparator.
+    ///
+    /// The comparator must be the one the database was created with.
+    pub fn open_with_comparator<C: Comparator<K = K>>(name: &Path,
+                                                      options: Options,
+                                                      read_only: ReadOnlyOptions,
+                                                      comparator: C)
+                                                      -> Result<ReadOnlyDatabase<K>, Error> {
+        ReadOnlyDatabase::open_staged(name,
+                                      options,
+                                      read_only,
+                                      |path, options| Database::open_with_comparator(path, options, comparator))
+    }
+
+    fn open_staged<F>(name: &Path,
+                      mut options: Options,
+                      read_only: ReadOnlyOptions,
+                      open: F)
+                      -> Result<ReadOnlyDatabase<K>, Error>
+        where F: FnOnce(&Path, Options) -> Result<Database<K>, Error>
+    {
+        let base = match read_only.staging_dir {
+            Some(dir) => dir,
+            None => name.parent().unwrap_or(name).to_path_buf(),
+        };
+        let db_name = name.file_name().map_or("leveldb".into(), |n| n.to_string_lossy());
+        let id = STAGING.fetch_add(1, Ordering::SeqCst);
+        let staging = StagingDir { path: base.join(format!("{}.read-only-{}-{}", db_name, process::id(), id)) };
+        let path = staging.path.clone();
+        let mut attempts = 0;
+        loop {
+            if path.exists() {
+                fs::remove_dir_all(&path).map_err(|e| error_for("removing", &path, &e))?;
+            }
+            fs::create_dir_all(&path).map_err(|e| error_for("creating", &path, &e))?;
+            if stage(name, &path)? {
+                break;
+            }
+            attempts += 1;
+            if attempts >= read_only.max_attempts {
+                return Err(Error::new(format!("{} kept changing while it was copied", name.display())));
+            }
+        }
+        options.create_if_missing = false;
+        options.error_if_exists = false;
+        Ok(ReadOnlyDatabase {
+            database: open(&path, options)?,
+            staging,
+        })
+    }
+}
+
+impl<K: Key> KV<K> for ReadOnlyDatabase<K> {
+    fn get<'a, BK: Borrow<K>>(&self, options: ReadOptions<'a, K>, key: BK) -> Result<Option<Vec<u8>>, Error> {
+        self.database.get(options, key)
+    }
+
+    fn get_bytes<'a, BK: Borrow<K>>(&self, options: ReadOptions<'a, K>, key: BK) -> Result<Option<Bytes>, Error> {
+        self.database.get_bytes(options, key)
+    }
+
+    /// Always fails, the database is read-only.
+    fn put<BK: Borrow<K>>(&self, _options: WriteOptions, _key: BK, _value: &[u8]) -> Result<(), Error> {
+        Err(write_error())
+    }
+
+    /// Always fails, the database is read-only.
+    fn delete<BK: Borrow<K>>(&self, _options: WriteOptions, _key: BK) -> Result<(), Error> {
+        Err(write_error())
+    }
+}
+
+impl<'a, K: Key + 'a> Iterable<'a, K> for ReadOnlyDatabase<K> {
+    fn iter(&'a self, options: ReadOptions<'a, K>) -> Iterator<'a, K> {
+        self.database.iter(options)
+    }
+
+    fn keys_iter(&'a self, options: ReadOptions<'a, K>) -> KeyIterator<'a, K> {
+        self.database.keys_iter(options)
+    }
+
+    fn value_iter(&'a self, options: ReadOptions<'a, K>) -> ValueIterator<'a, K> {
+        self.database.value_iter(options)
+    }
+}
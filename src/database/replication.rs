@@ -0,0 +1,647 @@
+//! Primary/follower replication for warm standbys.
+//!
+//! A `Primary` subscribes to the change feed (see `changes`) of a database
+//! handle and keeps the most recent committed writes in a bounded
+//! replication log. Followers connect over a `Transport` and tell the
+//! primary the last write they applied. The primary then streams the
+//! following writes from its log. If a follower has fallen so far behind
+//! that the log no longer holds the writes it needs, the primary sends a
+//! consistent snapshot of the whole database first.
+//!
+//! Sequence numbers are scoped to a primary's epoch, which changes every
+//! time a `Primary` is created. A follower from an earlier epoch always
+//! catches up from a snapshot.
+//!
+//! A follower stores its position under the reserved key
+//! `"\xff\xff\xff\xffleveldb.replication"` of its database, written in the
+//! same batch as every write it applies, so it resumes where it stopped
+//! after a restart. The key type of the follower's database must be able
+//! to represent that key, like `ByteKey`, and the key is visible to the
+//! database's iterators; skip it when reading a follower's data.
+//!
+//! Snapshots are staged in a separate database next to the follower's.
+//! Once complete, the keys that differ replace the follower's data in
+//! batches of bounded size. The first of them removes the stored position
+//! and the last stores the new one, so a follower interrupted in between
+//! catches up from a snapshot again after restarting.
+//!
+//! Two transports are provided: `ChannelTransport` for followers in the
+//! same process, and `StreamTransport` for any byte stream, such as a
+//! `TcpStream` or `UnixStream`.
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::changes::{Change, SlowSubscriberPolicy};
+use super::coding::{decode_fixed32, decode_fixed64, get_length_prefixed, get_varint64,
+                    put_fixed32, put_fixed64, put_length_prefixed, put_varint64};
+use super::crc32c;
+use super::error::Error;
+use super::iterator::Iterable;
+use super::key::{Key, from_u8};
+use super::kv::KV;
+use super::management;
+use super::staging::StagingDir;
+use options::{Options, ReadOptions, WriteOptions};
+
+/// The number of writes a primary keeps for followers by default.
+pub const DEFAULT_LOG_CAPACITY: usize = 10000;
+
+/// The largest message `StreamTransport` accepts.
+pub const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+const SNAPSHOT_BATCH: usize = 1000;
+const POSITION_KEY: &[u8] = b"\xff\xff\xff\xffleveldb.replication";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const SUBSCRIBE: u8 = 1;
+const BATCH: u8 = 2;
+const SNAPSHOT_BEGIN: u8 = 3;
+const SNAPSHOT_ENTRY: u8 = 4;
+const SNAPSHOT_END: u8 = 5;
+
+/// A message exchanged between primary and follower.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// Sent by a follower to start replication after the write `after`
+    /// of `epoch`.
+    Subscribe {
+        /// The epoch of the primary the follower last replicated from.
+        epoch: u64,
+        /// The sequence number of the last write the follower applied.
+        after: u64,
+    },
+    /// A committed write.
+    Batch {
+        /// The sequence number of the write.
+        sequence: u64,
+        /// The mutations of the write.
+        changes: Vec<Change>,
+    },
+    /// Starts a snapshot, replacing all data of the follower.
+    SnapshotBegin {
+        /// The epoch of the primary.
+        epoch: u64,
+        /// The sequence number of the last write in the snapshot.
+        sequence: u64,
+    },
+    /// An entry of a snapshot.
+    SnapshotEntry {
+        /// The key of the entry.
+        key: Vec<u8>,
+        /// The value of the entry.
+        value: Vec<u8>,
+    },
+    /// Completes a snapshot.
+    SnapshotEnd,
+}
+
+/// A bidirectional connection between a primary and a follower.
+pub trait Transport {
+    /// Send a message.
+    fn send(&mut self, message: &Message) -> Result<(), Error>;
+
+    /// Wait for the next message, returning `None` once the other side
+    /// closed the connection.
+    fn recv(&mut self) -> Result<Option<Message>, Error>;
+}
+
+/// A transport between threads of the same process.
+pub struct ChannelTransport {
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+}
+
+/// A transport over a byte stream, e.g. a `TcpStream` or `UnixStream`.
+///
+/// Messages are framed by their length and a CRC32C. Messages larger than
+/// `MAX_MESSAGE_SIZE` are rejected.
+pub struct StreamTransport<S: Read + Write> {
+    stream: S,
+}
+
+struct LogState {
+    entries: VecDeque<(u64, Vec<Change>)>,
+    // the sequence number of the first entry, or the next one if empty
+    first: u64,
+    stopped: bool,
+}
+
+struct ReplicationLog {
+    state: Mutex<LogState>,
+    appended: Condvar,
+}
+
+/// The primary side of replication.
+///
+/// Recording stops when the primary is shut down or dropped, which also
+/// ends all connections being served.
+pub struct Primary<K: Key + 'static> {
+    database: Arc<Database<K>>,
+    log: Arc<ReplicationLog>,
+    epoch: u64,
+    recorder: Option<JoinHandle<()>>,
+}
+
+/// The follower side of replication, applying writes to a database.
+///
+/// The replication position is stored in the database, so a follower
+/// created for a database that was replicated before resumes from there.
+/// Iterating the database returns the position key as well, see the
+/// module documentation.
+pub struct Follower<'a, K: Key + 'a> {
+    database: &'a Database<K>,
+    epoch: u64,
+    applied: u64,
+    snapshot: Option<StagedSnapshot<K>>,
+}
+
+/// A snapshot being received, staged in a database of its own.
+struct StagedSnapshot<K: Key> {
+    epoch: u64,
+    sequence: u64,
+    database: Database<K>,
+    batch: Writebatch<K>,
+    pending: usize,
+    // declared after the database, so it is closed before the staging
+    // directory is removed
+    #[allow(dead_code)]
+    staging: StagingDir,
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(format!("invalid replication message: {}", message))
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        match *self {
+            Message::Subscribe { epoch, after } => {
+                buf.push(SUBSCRIBE);
+                put_varint64(&mut buf, epoch);
+                put_varint64(&mut buf, after);
+            }
+            Message::Batch { sequence, ref changes } => {
+                buf.push(BATCH);
+                put_varint64(&mut buf, sequence);
+                put_varint64(&mut buf, changes.len() as u64);
+                for change in changes {
+                    match *change {
+                        Change::Put { ref key, ref value } => {
+                            buf.push(1);
+                            put_length_prefixed(&mut buf, key);
+                            put_length_prefixed(&mut buf, value);
+                        }
+                        Change::Delete { ref key } => {
+                            buf.push(0);
+                            put_length_prefixed(&mut buf, key);
+                        }
+                        Change::Merge { ref key, ref operand } => {
+                            buf.push(2);
+                            put_length_prefixed(&mut buf, key);
+                            put_length_prefixed(&mut buf, operand);
+                        }
+                    }
+                }
+            }
+            Message::SnapshotBegin { epoch, sequence } => {
+                buf.push(SNAPSHOT_BEGIN);
+                put_varint64(&mut buf, epoch);
+                put_varint64(&mut buf, sequence);
+            }
+            Message::SnapshotEntry { ref key, ref value } => {
+                buf.push(SNAPSHOT_ENTRY);
+                put_length_prefixed(&mut buf, key);
+                put_length_prefixed(&mut buf, value);
+            }
+            Message::SnapshotEnd => buf.push(SNAPSHOT_END),
+        }
+        buf
+    }
+
+    fn decode(mut src: &[u8]) -> Result<Message, Error> {
+        let src = &mut src;
+        let number = |src: &mut &[u8]| get_varint64(src).ok_or_else(|| corrupt("bad number"));
+        let bytes = |src: &mut &[u8]| {
+            get_length_prefixed(src).map(|b| b.to_vec()).ok_or_else(|| corrupt("bad string"))
+        };
+        let (&tag, rest) = src.split_first().ok_or_else(|| corrupt("empty"))?;
+        *src = rest;
+        let message = match tag {
+            SUBSCRIBE => Message::Subscribe { epoch: number(src)?, after: number(src)? },
+            BATCH => {
+                let sequence = number(src)?;
+                let count = number(src)?;
+                let mut changes = vec![];
+                for _ in 0..count {
+                    let (&kind, rest) = src.split_first().ok_or_else(|| corrupt("truncated batch"))?;
+                    *src = rest;
+                    changes.push(match kind {
+                        1 => Change::Put { key: bytes(src)?, value: bytes(src)? },
+                        0 => Change::Delete { key: bytes(src)? },
+                        2 => Change::Merge { key: bytes(src)?, operand: bytes(src)? },
+                        _ => return Err(corrupt("unknown change type")),
+                    });
+                }
+                Message::Batch { sequence, changes }
+            }
+            SNAPSHOT_BEGIN => Message::SnapshotBegin { epoch: number(src)?, sequence: number(src)? },
+            SNAPSHOT_ENTRY => Message::SnapshotEntry { key: bytes(src)?, value: bytes(src)? },
+            SNAPSHOT_END => Message::SnapshotEnd,
+            _ => return Err(corrupt("unknown message type")),
+        };
+        if !src.is_empty() {
+            return Err(corrupt("trailing bytes"));
+        }
+        Ok(message)
+    }
+}
+
+impl ChannelTransport {
+    /// Create two connected transports.
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (a_sender, a_receiver) = channel();
+        let (b_sender, b_receiver) = channel();
+        (ChannelTransport { sender: a_sender, receiver: b_receiver },
+         ChannelTransport { sender: b_sender, receiver: a_receiver })
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, message: &Message) -> Result<(), Error> {
+        self.sender.send(message.clone()).map_err(|_| Error::new("transport closed".to_string()))
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, Error> {
+        Ok(self.receiver.recv().ok())
+    }
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    /// Exchange messages over `stream`.
+    pub fn new(stream: S) -> StreamTransport<S> {
+        StreamTransport { stream }
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write> Transport for StreamTransport<S> {
+    fn send(&mut self, message: &Message) -> Result<(), Error> {
+        let payload = message.encode();
+        if payload.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::new(format!("message of {} bytes is too large", payload.len())));
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 8);
+        put_fixed32(&mut frame, payload.len() as u32);
+        put_fixed32(&mut frame, crc32c::value(&payload));
+        frame.extend_from_slice(&payload);
+        self.stream.write_all(&frame)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Option<Message>, Error> {
+        let mut header = [0; 8];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let len = decode_fixed32(&header) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(corrupt("message too large"));
+        }
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload)?;
+        if crc32c::value(&payload) != decode_fixed32(&header[4..]) {
+            return Err(corrupt("checksum mismatch"));
+        }
+        Message::decode(&payload).map(Some)
+    }
+}
+
+impl<K: Key + 'static> Primary<K> {
+    /// Start recording the writes through `database`, keeping up to
+    /// `capacity` of them for followers.
+    pub fn new(database: Arc<Database<K>>, capacity: usize) -> Primary<K> {
+        let subscription = database.subscribe(capacity.max(1), SlowSubscriberPolicy::Block);
+        let log = Arc::new(ReplicationLog {
+            state: Mutex::new(LogState {
+                entries: VecDeque::new(),
+                first: subscription.first_sequence(),
+                stopped: false,
+            }),
+            appended: Condvar::new(),
+        });
+
+        let recorder_log = log.clone();
+        let recorder = thread::spawn(move || {
+            loop {
+                let event = subscription.recv_timeout(POLL_INTERVAL);
+                let mut state = recorder_log.state.lock().unwrap();
+                if state.stopped {
+                    return;
+                }
+                if let Some(event) = event {
+                    state.entries.push_back((event.sequence, event.changes));
+                    if state.entries.len() > capacity {
+                        state.entries.pop_front();
+                        state.first += 1;
+                    }
+                    recorder_log.appended.notify_all();
+                }
+            }
+        });
+
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Primary {
+            database,
+            log,
+            epoch: elapsed.as_secs() << 32 | elapsed.subsec_nanos() as u64,
+            recorder: Some(recorder),
+        }
+    }
+
+    /// The epoch of this primary.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Send a snapshot of the database, returning its sequence number.
+    fn send_snapshot<T: Transport>(&self, transport: &mut T) -> Result<u64, Error> {
+        let (snapshot, sequence) = self.database.snapshot_with_sequence();
+        transport.send(&Message::SnapshotBegin {
+                epoch: self.epoch,
+                sequence,
+            })?;
+        let mut options = ReadOptions::new();
+        options.fill_cache = false;
+        for (key, value) in snapshot.iter(options) {
+            transport.send(&Message::SnapshotEntry {
+                    key: key.as_slice(|k| k.to_vec()),
+                    value,
+                })?;
+        }
+        transport.send(&Message::SnapshotEnd)?;
+        Ok(sequence)
+    }
+
+    /// Serve a follower until it disconnects or the primary is dropped.
+    ///
+    /// The follower must start by sending `Message::Subscribe`.
+    pub fn serve<T: Transport>(&self, mut transport: T) -> Result<(), Error> {
+        let mut next = match transport.recv()? {
+            Some(Message::Subscribe { epoch, after }) if epoch == self.epoch => after + 1,
+            Some(Message::Subscribe { .. }) => 0,
+            Some(_) => return Err(corrupt("expected subscribe")),
+            None => return Ok(()),
+        };
+
+        loop {
+            let pending: Vec<(u64, Vec<Change>)> = {
+                let mut state = self.log.state.lock().unwrap();
+                // after a snapshot, the log may not have caught up to it yet
+                while !state.stopped && next >= state.first + state.entries.len() as u64 {
+                    state = self.log.appended.wait(state).unwrap();
+                }
+                if state.stopped {
+                    return Ok(());
+                }
+                let skip = next.saturating_sub(state.first) as usize;
+                if next < state.first {
+                    vec![]
+                } else {
+                    state.entries.iter().skip(skip).cloned().collect()
+                }
+            };
+            if pending.is_empty() {
+                // the log no longer holds the writes the follower needs
+                next = self.send_snapshot(&mut transport)? + 1;
+                continue;
+            }
+            for (sequence, changes) in pending {
+                transport.send(&Message::Batch { sequence, changes })?;
+                next = sequence + 1;
+            }
+        }
+    }
+
+    /// Stop recording writes and end all connections being served.
+    pub fn shutdown(&self) {
+        self.log.state.lock().unwrap().stopped = true;
+        self.log.appended.notify_all();
+    }
+
+    /// Serve a follower on a background thread.
+    pub fn spawn_serve<T>(primary: &Arc<Primary<K>>, transport: T) -> JoinHandle<Result<(), Error>>
+        where T: Transport + Send + 'static
+    {
+        let primary = primary.clone();
+        thread::spawn(move || primary.serve(transport))
+    }
+}
+
+impl<K: Key + 'static> Drop for Primary<K> {
+    fn drop(&mut self) {
+        self.shutdown();
+        if let Some(recorder) = self.recorder.take() {
+            let _ = recorder.join();
+        }
+    }
+}
+
+fn position_key<K: Key>() -> K {
+    from_u8(POSITION_KEY)
+}
+
+/// Whether keys of type `K` can represent the position key. Key types of
+/// a fixed width panic in `from_u8`.
+fn holds_position_key<K: Key>() -> bool {
+    match panic::catch_unwind(position_key::<K>) {
+        Ok(key) => key.as_slice(|k| k == POSITION_KEY),
+        Err(_) => false,
+    }
+}
+
+fn encode_position(epoch: u64, applied: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(16);
+    put_fixed64(&mut buf, epoch);
+    put_fixed64(&mut buf, applied);
+    buf
+}
+
+impl<K: Key> StagedSnapshot<K> {
+    /// Create an empty staging database next to `database`.
+    fn create(database: &Database<K>, epoch: u64, sequence: u64) -> Result<StagedSnapshot<K>, Error> {
+        let mut name = database.path().as_os_str().to_owned();
+        name.push(".snapshot");
+        let path = PathBuf::from(name);
+        if path.exists() {
+            management::destroy(&path, Options::new())?;
+        }
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        options.error_if_exists = true;
+        Ok(StagedSnapshot {
+            epoch,
+            sequence,
+            database: Database::open(&path, options)?,
+            batch: Writebatch::new(),
+            pending: 0,
+            staging: StagingDir { path },
+        })
+    }
+
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.batch.put(from_u8(key), value);
+        self.pending += 1;
+        if self.pending >= SNAPSHOT_BATCH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.database.write(WriteOptions::new(), &self.batch)?;
+        self.batch.clear();
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Replace the contents of `target` by the snapshot, writing the keys
+    /// that differ in batches of `SNAPSHOT_BATCH`.
+    ///
+    /// The first batch deletes the stored position and the last one
+    /// writes the new position, so `target` is behind until it is written.
+    fn replace(mut self, target: &Database<K>) -> Result<(), Error> {
+        self.flush()?;
+        let mut batch = Writebatch::new();
+        batch.delete(position_key());
+        let mut pending = 1;
+        for key in target.keys_iter(ReadOptions::new()) {
+            if key.as_slice(|k| k != POSITION_KEY) && self.database.get(ReadOptions::new(), &key)?.is_none() {
+                batch.delete(key);
+                pending += 1;
+            }
+            if pending >= SNAPSHOT_BATCH {
+                target.write(WriteOptions::new(), &batch)?;
+                batch.clear();
+                pending = 0;
+            }
+        }
+        for (key, value) in self.database.iter(ReadOptions::new()) {
+            if target.get(ReadOptions::new(), &key)?.as_ref() != Some(&value) {
+                batch.put(key, &value);
+                pending += 1;
+            }
+            if pending >= SNAPSHOT_BATCH {
+                target.write(WriteOptions::new(), &batch)?;
+                batch.clear();
+                pending = 0;
+            }
+        }
+        batch.put(position_key(), &encode_position(self.epoch, self.sequence));
+        let mut options = WriteOptions::new();
+        options.sync = true;
+        target.write(options, &batch)
+    }
+}
+
+impl<'a, K: Key + 'a> Follower<'a, K> {
+    /// Replicate into `database`, resuming from the position stored in it.
+    ///
+    /// Fails if the key type of `database` can't represent the key the
+    /// position is stored under.
+    pub fn new(database: &'a Database<K>) -> Result<Follower<'a, K>, Error> {
+        if !holds_position_key::<K>() {
+            return Err(Error::new("key type can't hold the replication position".to_string()));
+        }
+        let (epoch, applied) = match database.get(ReadOptions::new(), position_key::<K>())? {
+            Some(ref position) if position.len() == 16 => {
+                (decode_fixed64(position), decode_fixed64(&position[8..]))
+            }
+            Some(_) => return Err(corrupt("stored position")),
+            None => (0, 0),
+        };
+        Ok(Follower {
+            database,
+            epoch,
+            applied,
+            snapshot: None,
+        })
+    }
+
+    /// The epoch of the primary replicated from, and the sequence number
+    /// of the last write applied.
+    pub fn position(&self) -> (u64, u64) {
+        (self.epoch, self.applied)
+    }
+
+    /// Subscribe to a primary and apply its writes until it disconnects.
+    pub fn run<T: Transport>(&mut self, transport: &mut T) -> Result<(), Error> {
+        transport.send(&Message::Subscribe {
+                epoch: self.epoch,
+                after: self.applied,
+            })?;
+        while let Some(message) = transport.recv()? {
+            self.apply(message)?;
+        }
+        Ok(())
+    }
+
+    /// Apply a single message received from the primary.
+    pub fn apply(&mut self, message: Message) -> Result<(), Error> {
+        match message {
+            Message::Batch { sequence, changes } => {
+                if self.snapshot.is_some() || sequence != self.applied + 1 {
+                    return Err(Error::new(format!("unexpected write {} after {}", sequence, self.applied)));
+                }
+                let mut batch = Writebatch::new();
+                for change in changes {
+                    match change {
+                        Change::Put { key, value } => batch.put(from_u8(&key), &value),
+                        Change::Delete { key } => batch.delete(from_u8(&key)),
+                        Change::Merge { key, operand } => batch.merge(from_u8(&key), &operand),
+                    }
+                }
+                batch.put(position_key(), &encode_position(self.epoch, sequence));
+                self.database.write(WriteOptions::new(), &batch)?;
+                self.applied = sequence;
+            }
+            Message::SnapshotBegin { epoch, sequence } => {
+                // drop an unfinished snapshot before staging the next one
+                self.snapshot = None;
+                self.snapshot = Some(StagedSnapshot::create(self.database, epoch, sequence)?);
+            }
+            Message::SnapshotEntry { key, value } => {
+                self.snapshot
+                    .as_mut()
+                    .ok_or_else(|| corrupt("snapshot entry outside of snapshot"))?
+                    .put(&key, &value)?;
+            }
+            Message::SnapshotEnd => {
+                let snapshot = self.snapshot
+                    .take()
+                    .ok_or_else(|| corrupt("snapshot end outside of snapshot"))?;
+                let (epoch, sequence) = (snapshot.epoch, snapshot.sequence);
+                snapshot.replace(self.database)?;
+                self.epoch = epoch;
+                self.applied = sequence;
+            }
+            Message::Subscribe { .. } => return Err(corrupt("unexpected subscribe")),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+//! A compressor and decompressor for the raw snappy format, used for
+//! table blocks.
+use super::coding::{decode_fixed32, put_varint64};
+use super::error::Error;
+
+/// Input is compressed in fragments of this size, so copy offsets fit into
+/// two bytes.
+const FRAGMENT_SIZE: usize = 1 << 16;
+const HASH_BITS: u32 = 14;
+
+fn corrupt() -> Error {
+    Error::new("corrupt snappy block".to_string())
+}
+
+fn get_varint32(src: &mut &[u8]) -> Option<u32> {
+    let mut result = 0u32;
+    for shift in 0..5 {
+        let (&byte, rest) = src.split_first()?;
+        *src = rest;
+        result |= ((byte & 0x7f) as u32) << (7 * shift);
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+fn take<'a>(src: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if src.len() < len {
+        return Err(corrupt());
+    }
+    let (taken, rest) = src.split_at(len);
+    *src = rest;
+    Ok(taken)
+}
+
+fn little_endian(bytes: &[u8]) -> usize {
+    bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize)
+}
+
+/// Decompress a raw (unframed) snappy buffer.
+pub fn decompress(mut src: &[u8]) -> Result<Vec<u8>, Error> {
+    let len = get_varint32(&mut src).ok_or_else(corrupt)? as usize;
+    // don't trust the header for the allocation, snappy expands at most
+    // by a factor of ~21
+    let mut dst = Vec::with_capacity(len.min(src.len().saturating_mul(24)));
+    while !src.is_empty() {
+        let tag = take(&mut src, 1)?[0];
+        let (length, offset) = match tag & 3 {
+            0 => {
+                let mut length = (tag >> 2) as usize;
+                if length >= 60 {
+                    length = little_endian(take(&mut src, length - 59)?);
+                }
+                dst.extend_from_slice(take(&mut src, length + 1)?);
+                continue;
+            }
+            1 => (4 + ((tag >> 2) & 7) as usize, ((tag >> 5) as usize) << 8 | take(&mut src, 1)?[0] as usize),
+            2 => ((tag >> 2) as usize + 1, little_endian(take(&mut src, 2)?)),
+            _ => ((tag >> 2) as usize + 1, little_endian(take(&mut src, 4)?)),
+        };
+        if offset == 0 || offset > dst.len() || dst.len() + length > len {
+            return Err(corrupt());
+        }
+        // copies may overlap their own output, so go byte by byte
+        let start = dst.len() - offset;
+        for i in 0..length {
+            let byte = dst[start + i];
+            dst.push(byte);
+        }
+    }
+    if dst.len() != len {
+        return Err(corrupt());
+    }
+    Ok(dst)
+}
+
+fn emit_literal(dst: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        dst.push((n as u8) << 2);
+    } else {
+        let bytes = (64 - (n as u64).leading_zeros() as usize).div_ceil(8);
+        dst.push(((59 + bytes) as u8) << 2);
+        dst.extend_from_slice(&(n as u32).to_le_bytes()[..bytes]);
+    }
+    dst.extend_from_slice(literal);
+}
+
+fn emit_copy_upto64(dst: &mut Vec<u8>, offset: usize, len: usize) {
+    if len < 12 && offset < 2048 {
+        dst.push(1 | ((len - 4) as u8) << 2 | ((offset >> 8) as u8) << 5);
+        dst.push(offset as u8);
+    } else {
+        dst.push(2 | ((len - 1) as u8) << 2);
+        dst.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+}
+
+fn emit_copy(dst: &mut Vec<u8>, offset: usize, mut len: usize) {
+    // keep the last copy at least 4 bytes long
+    while len >= 68 {
+        emit_copy_upto64(dst, offset, 64);
+        len -= 64;
+    }
+    if len > 64 {
+        emit_copy_upto64(dst, offset, 60);
+        len -= 60;
+    }
+    emit_copy_upto64(dst, offset, len);
+}
+
+fn compress_fragment(dst: &mut Vec<u8>, src: &[u8], table: &mut [usize]) {
+    for entry in table.iter_mut() {
+        *entry = 0;
+    }
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos + 4 <= src.len() {
+        let hash = (decode_fixed32(&src[pos..]).wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize;
+        // positions are stored plus one, so zero means empty
+        let candidate = table[hash];
+        table[hash] = pos + 1;
+        if candidate == 0 || src[candidate - 1..candidate + 3] != src[pos..pos + 4] {
+            pos += 1;
+            continue;
+        }
+        let start = candidate - 1;
+        let mut len = 4;
+        while pos + len < src.len() && src[start + len] == src[pos + len] {
+            len += 1;
+        }
+        emit_literal(dst, &src[literal_start..pos]);
+        emit_copy(dst, pos - start, len);
+        pos += len;
+        literal_start = pos;
+    }
+    emit_literal(dst, &src[literal_start..]);
+}
+
+/// Compress `src` into a raw (unframed) snappy buffer.
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(src.len() / 2 + 8);
+    put_varint64(&mut dst, src.len() as u64);
+    let mut table = vec![0; 1 << HASH_BITS];
+    for fragment in src.chunks(FRAGMENT_SIZE) {
+        compress_fragment(&mut dst, fragment, &mut table);
+    }
+    dst
+}
@@ -10,10 +10,12 @@ use database::Database;
 use database::kv::KV;
 
 use database::error::Error;
-use database::options::ReadOptions;
+use database::options::{PreparedReadOptions, ReadOptions, READ_FLAGS};
 use database::iterator::{Iterable, Iterator, KeyIterator, ValueIterator};
+use database::multi_get::{GetResult, MultiGet};
 
 use std::borrow::Borrow;
+use std::cell::RefCell;
 
 #[allow(missing_docs)]
 struct RawSnapshot {
@@ -32,6 +34,9 @@ impl Drop for RawSnapshot {
 /// Represents a database at a certain point in time,
 /// and allows for all read operations (get and iteration).
 pub struct Snapshot<'a, K: Key + 'a> {
+    // prepared on first use, indexed by `read_flags`, and dropped before
+    // the snapshot they read from
+    readoptions: [RefCell<Option<PreparedReadOptions>>; READ_FLAGS],
     raw: RawSnapshot,
     database: &'a Database<K>,
 }
@@ -54,6 +59,7 @@ impl<K: Key> Snapshots<K> for Database<K> {
             ptr: snap,
         };
         Snapshot {
+            readoptions: Default::default(),
             raw: raw,
             database: self,
         }
@@ -77,6 +83,19 @@ impl<'a, K: Key> Snapshot<'a, K> {
     pub fn raw_ptr(&self) -> *mut leveldb_snapshot_t {
         self.raw.ptr
     }
+
+    /// Call `f` with prepared options reading from this snapshot with the
+    /// given `read_flags`.
+    pub(crate) fn with_readoptions<T, F>(&self, flags: usize, f: F) -> T
+        where F: FnOnce(&PreparedReadOptions) -> T
+    {
+        let cell = &self.readoptions[flags];
+        if cell.borrow().is_none() {
+            *cell.borrow_mut() = Some(unsafe { PreparedReadOptions::new(flags, self.raw.ptr) });
+        }
+        let readoptions = cell.borrow();
+        f(readoptions.as_ref().unwrap())
+    }
 }
 
 impl<'a, K: Key + 'a> Iterable<'a, K> for Snapshot<'a, K> {
@@ -93,3 +112,10 @@ impl<'a, K: Key + 'a> Iterable<'a, K> for Snapshot<'a, K> {
         self.database.value_iter(options)
     }
 }
+
+impl<'a, K: Key + 'a> MultiGet<K> for Snapshot<'a, K> {
+    fn multi_get<'b>(&'b self, mut options: ReadOptions<'b, K>, keys: &[K]) -> Vec<GetResult> {
+        options.snapshot = Some(self);
+        self.database.multi_get(options, keys)
+    }
+}
@@ -0,0 +1,103 @@
+//! Copying the files of a live database.
+//!
+//! The table files, which leveldb never changes once written, are
+//! hard-linked (or copied) into a staging directory, along with copies of
+//! `CURRENT`, the MANIFEST and the logs that still hold data. The copy
+//! can be opened like any database. Logs are copied while writers may
+//! append to them; leveldb drops a torn record at the end of a log, so the
+//! copy holds a prefix of the writes.
+//!
+//! A flush or compaction that finishes while the files are copied records
+//! its edit in the MANIFEST before deleting the files it replaced. Staging
+//! fails if the MANIFEST changed by the time all files are copied, since
+//! a log or table the copied MANIFEST refers to may be gone.
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::log::log_files;
+use super::manifest::{current_manifest, read_manifest, VersionSet};
+
+/// A staging directory, removed when dropped.
+pub struct StagingDir {
+    pub path: PathBuf,
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn error_for(action: &str, path: &Path, error: &io::Error) -> Error {
+    Error::new(format!("{} {}: {}", action, path.display(), error))
+}
+
+/// Link or copy `from` to `to`, returning false if `from` is gone.
+fn stage_file(from: &Path, to: &Path, link: bool) -> Result<bool, Error> {
+    if link && fs::hard_link(from, to).is_ok() {
+        return Ok(true);
+    }
+    match fs::copy(from, to) {
+        Ok(_) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(error_for("copying", from, &e)),
+    }
+}
+
+fn same_len(path: &Path, len: u64) -> bool {
+    fs::metadata(path).map(|m| m.len() == len).unwrap_or(false)
+}
+
+/// Whether `manifest` is still the MANIFEST of the database in `dir`,
+/// `len` bytes long and naming the same logs as `version`.
+fn unchanged(dir: &Path, manifest: &Path, len: u64, version: &VersionSet) -> Result<bool, Error> {
+    if current_manifest(dir)? != manifest || !same_len(manifest, len) {
+        return Ok(false);
+    }
+    // a record being appended right now may be torn
+    Ok(match read_manifest(manifest).and_then(VersionSet::from_edits) {
+        Ok(live) => live.log_number == version.log_number && live.prev_log_number == version.prev_log_number,
+        Err(_) => false,
+    })
+}
+
+/// Copy the current state of the database in `dir` into `staging`.
+///
+/// Returns false if the database changed while copying.
+pub fn stage(dir: &Path, staging: &Path) -> Result<bool, Error> {
+    let manifest = current_manifest(dir)?;
+    let name = manifest.file_name().unwrap().to_owned();
+    let copy = staging.join(&name);
+    if !stage_file(&manifest, &copy, false)? || current_manifest(dir)? != manifest {
+        return Ok(false);
+    }
+    let len = fs::metadata(&copy).map_err(|e| error_for("reading", &copy, &e))?.len();
+    let current = staging.join("CURRENT");
+    File::create(&current)
+        .and_then(|mut f| writeln!(f, "{}", name.to_string_lossy()))
+        .map_err(|e| error_for("writing", &current, &e))?;
+
+    let version = match read_manifest(&copy).and_then(VersionSet::from_edits) {
+        Ok(version) => version,
+        // the copy caught a record while it was appended
+        Err(_) if !same_len(&manifest, len) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    for file in version.levels().iter().flat_map(|files| files.iter()) {
+        let table = VersionSet::table_path(dir, file);
+        if !stage_file(&table, &staging.join(table.file_name().unwrap()), true)? {
+            return Ok(false);
+        }
+    }
+    // logs older than the MANIFEST's log number are already in tables
+    for log in log_files(dir)? {
+        let number = log.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok());
+        if matches!(number, Some(n) if n >= version.log_number || n == version.prev_log_number) &&
+           !stage_file(&log, &staging.join(log.file_name().unwrap()), false)? {
+            return Ok(false);
+        }
+    }
+    unchanged(dir, &manifest, len, &version)
+}
@@ -0,0 +1,805 @@
+//! A reader and a writer for leveldb table files (`.ldb` and `.sst`).
+//!
+//! Tables are parsed and built entirely in Rust, without opening the
+//! database they belong to, which makes them usable for offline analysis
+//! and for preparing tables to ingest (see `ingest`). A table file
+//! consists of:
+//!
+//! * data blocks, holding prefix-compressed entries and restart points
+//! * an optional filter block, holding a bloom filter per 2KiB of data
+//! * a metaindex block, pointing to the filter block
+//! * an index block, pointing to the data blocks
+//! * a fixed-size footer, pointing to the metaindex and index blocks
+//!
+//! Every block is followed by its compression type and a masked CRC32C,
+//! which are verified on read. Keys in a table are internal keys: the
+//! user key followed by the sequence number and type of the write.
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::iter;
+use std::mem;
+use std::path::Path;
+
+use leveldb_sys::Compression;
+
+use super::coding::{decode_fixed32, decode_fixed64, get_varint64, put_fixed32, put_fixed64, put_varint64};
+use super::crc32c;
+use super::error::Error;
+use super::snappy;
+
+/// The magic number at the end of every table file.
+pub const TABLE_MAGIC: u64 = 0xdb47_7524_8b80_fb57;
+/// The length of a table footer.
+pub const FOOTER_LEN: usize = 48;
+/// The name of leveldb's builtin bloom filter policy.
+pub const BLOOM_FILTER_POLICY: &str = "leveldb.BuiltinBloomFilter2";
+
+const BLOCK_TRAILER_LEN: usize = 5;
+const NO_COMPRESSION: u8 = 0;
+const SNAPPY_COMPRESSION: u8 = 1;
+const BLOOM_SEED: u32 = 0xbc9f_1d34;
+const FILTER_BASE_LG: u8 = 11;
+
+type Entry = (Vec<u8>, Vec<u8>);
+
+/// The type of a write stored under an internal key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    /// The key was deleted.
+    Deletion,
+    /// The key was set to a value.
+    Value,
+}
+
+/// A key as stored in table files: a user key with the sequence number
+/// and type of the write.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InternalKey {
+    /// The key as written by the user.
+    pub user_key: Vec<u8>,
+    /// The sequence number of the write.
+    pub sequence: u64,
+    /// Whether the write stored a value or deleted the key.
+    pub value_type: ValueType,
+}
+
+/// The location of a block within a table file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockHandle {
+    /// The offset of the block in the file.
+    pub offset: u64,
+    /// The size of the block, excluding its trailer.
+    pub size: u64,
+}
+
+/// Options for writing tables with `TableBuilder`.
+pub struct TableOptions {
+    /// The approximate size of the uncompressed data in each data block.
+    ///
+    /// default: 4096
+    pub block_size: usize,
+    /// The number of keys between restart points in data blocks.
+    ///
+    /// default: 16
+    pub block_restart_interval: usize,
+    /// How to compress blocks. Blocks that don't shrink by at least an
+    /// eighth are stored uncompressed.
+    ///
+    /// default: Compression::Snappy
+    pub compression: Compression,
+    /// The bits per key of a bloom filter to write, as used by
+    /// `FilterPolicy::bloom`. Tables without a filter are still read
+    /// correctly by databases that use one.
+    ///
+    /// default: None
+    pub bloom_bits_per_key: Option<usize>,
+}
+
+/// Writes a table file.
+///
+/// Entries must be added in table order: by user key in bytewise order,
+/// and for the same user key, newest sequence number first. Tables written
+/// this way can only be used by databases with the default comparator.
+pub struct TableBuilder<W: Write> {
+    writer: W,
+    options: TableOptions,
+    offset: u64,
+    data: BlockBuilder,
+    index: BlockBuilder,
+    filter: Option<FilterBuilder>,
+    pending_index: Option<(Vec<u8>, BlockHandle)>,
+    last_key: Option<InternalKey>,
+    num_entries: u64,
+    finished: bool,
+}
+
+struct BlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+struct FilterBuilder {
+    bits_per_key: usize,
+    keys: Vec<Vec<u8>>,
+    filters: Vec<u8>,
+    offsets: Vec<u32>,
+}
+
+/// A table file loaded into memory.
+pub struct Table {
+    data: Vec<u8>,
+    index: Block,
+    filter: Option<(String, Vec<u8>)>,
+}
+
+/// An iterator over the entries of a table, in table order.
+///
+/// Iteration stops after the first error.
+pub struct TableIterator<'a> {
+    table: &'a Table,
+    index: BlockIter,
+    data: Option<BlockIter>,
+    failed: bool,
+}
+
+#[derive(Clone)]
+struct Block {
+    contents: Vec<u8>,
+    restarts: usize,
+    num_restarts: usize,
+}
+
+struct BlockIter {
+    block: Block,
+    pos: usize,
+    key: Vec<u8>,
+    peeked: Option<Entry>,
+}
+
+fn corrupt(message: &str) -> Error {
+    Error::new(format!("corrupt table: {}", message))
+}
+
+impl InternalKey {
+    /// Decode an internal key.
+    pub fn decode(encoded: &[u8]) -> Result<InternalKey, Error> {
+        if encoded.len() < 8 {
+            return Err(corrupt("internal key too short"));
+        }
+        let (user_key, trailer) = encoded.split_at(encoded.len() - 8);
+        let trailer = decode_fixed64(trailer);
+        let value_type = match trailer & 0xff {
+            0 => ValueType::Deletion,
+            1 => ValueType::Value,
+            _ => return Err(corrupt("unknown value type")),
+        };
+        Ok(InternalKey {
+            user_key: user_key.to_vec(),
+            sequence: trailer >> 8,
+            value_type,
+        })
+    }
+
+    /// Encode this key as stored in table files.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = self.user_key.clone();
+        let value_type = match self.value_type {
+            ValueType::Deletion => 0,
+            ValueType::Value => 1,
+        };
+        put_fixed64(&mut encoded, self.sequence << 8 | value_type);
+        encoded
+    }
+}
+
+impl BlockHandle {
+    fn encode_to(&self, dst: &mut Vec<u8>) {
+        put_varint64(dst, self.offset);
+        put_varint64(dst, self.size);
+    }
+
+    fn decode(src: &mut &[u8]) -> Result<BlockHandle, Error> {
+        match (get_varint64(src), get_varint64(src)) {
+            (Some(offset), Some(size)) => Ok(BlockHandle { offset, size }),
+            _ => Err(corrupt("invalid block handle")),
+        }
+    }
+}
+
+impl Block {
+    fn new(contents: Vec<u8>) -> Result<Block, Error> {
+        if contents.len() < 4 {
+            return Err(corrupt("block too short"));
+        }
+        let num_restarts = decode_fixed32(&contents[contents.len() - 4..]) as usize;
+        let restarts = num_restarts.checked_mul(4)
+            .and_then(|len| (contents.len() - 4).checked_sub(len))
+            .ok_or_else(|| corrupt("invalid restart array"))?;
+        Ok(Block {
+            contents,
+            restarts,
+            num_restarts,
+        })
+    }
+
+    fn restart_point(&self, index: usize) -> usize {
+        decode_fixed32(&self.contents[self.restarts + 4 * index..]) as usize
+    }
+}
+
+impl BlockIter {
+    fn new(block: Block) -> BlockIter {
+        BlockIter {
+            block,
+            pos: 0,
+            key: vec![],
+            peeked: None,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Entry>, Error> {
+        if let Some(entry) = self.peeked.take() {
+            return Ok(Some(entry));
+        }
+        if self.pos >= self.block.restarts {
+            return Ok(None);
+        }
+        let mut src = &self.block.contents[self.pos..self.block.restarts];
+        let (shared, non_shared, value_len) =
+            match (get_varint64(&mut src), get_varint64(&mut src), get_varint64(&mut src)) {
+                (Some(s), Some(n), Some(v)) => (s as usize, n as usize, v as usize),
+                _ => return Err(corrupt("invalid block entry")),
+            };
+        if shared > self.key.len() || non_shared.saturating_add(value_len) > src.len() {
+            return Err(corrupt("invalid block entry"));
+        }
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&src[..non_shared]);
+        let value = src[non_shared..non_shared + value_len].to_vec();
+        self.pos = self.block.restarts - src.len() + non_shared + value_len;
+        Ok(Some((self.key.clone(), value)))
+    }
+
+    /// Position the iterator at the first entry for which `before` is false.
+    ///
+    /// Uses binary search over the restart points, then scans linearly.
+    fn seek<F: Fn(&[u8]) -> bool>(&mut self, before: F) -> Result<(), Error> {
+        self.peeked = None;
+        self.key.clear();
+        self.pos = self.block.restarts;
+        if self.block.num_restarts == 0 {
+            return Ok(());
+        }
+        let (mut left, mut right) = (0, self.block.num_restarts - 1);
+        while left < right {
+            let mid = right - (right - left) / 2;
+            self.pos = self.block.restart_point(mid);
+            self.key.clear();
+            match self.next()? {
+                Some((ref key, _)) if before(key) => left = mid,
+                _ => right = mid - 1,
+            }
+        }
+        self.pos = self.block.restart_point(left);
+        self.key.clear();
+        while let Some(entry) = self.next()? {
+            if !before(&entry.0) {
+                self.peeked = Some(entry);
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bloom_hash(data: &[u8]) -> u32 {
+    const M: u32 = 0xc6a4_a793;
+    let mut h = BLOOM_SEED ^ (data.len() as u32).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(decode_fixed32(chunk)).wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &b) in rest.iter().enumerate() {
+            h = h.wrapping_add((b as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+fn bloom_may_match(filter: &[u8], key: &[u8]) -> bool {
+    if filter.len() < 2 {
+        return false;
+    }
+    let bits = (filter.len() - 1) * 8;
+    let probes = filter[filter.len() - 1];
+    if probes > 30 {
+        // reserved for new encodings, treat as a match
+        return true;
+    }
+    let mut h = bloom_hash(key);
+    let delta = h.rotate_left(15);
+    for _ in 0..probes {
+        let bit = h as usize % bits;
+        if filter[bit / 8] & (1 << (bit % 8)) == 0 {
+            return false;
+        }
+        h = h.wrapping_add(delta);
+    }
+    true
+}
+
+fn bloom_create(keys: &[Vec<u8>], bits_per_key: usize) -> Vec<u8> {
+    // round up, and use a minimum size to keep the false positive rate low
+    // for small sets
+    let bytes = cmp::max(keys.len() * bits_per_key, 64).div_ceil(8);
+    let bits = bytes * 8;
+    // 0.69 is approximately ln(2), which minimizes the false positive rate
+    let probes = ((bits_per_key as f64 * 0.69) as usize).clamp(1, 30);
+    let mut filter = vec![0; bytes];
+    for key in keys {
+        let mut h = bloom_hash(key);
+        let delta = h.rotate_left(15);
+        for _ in 0..probes {
+            let bit = h as usize % bits;
+            filter[bit / 8] |= 1 << (bit % 8);
+            h = h.wrapping_add(delta);
+        }
+    }
+    filter.push(probes as u8);
+    filter
+}
+
+impl BlockBuilder {
+    fn new(restart_interval: usize) -> BlockBuilder {
+        BlockBuilder {
+            buffer: vec![],
+            restarts: vec![0],
+            restart_interval: cmp::max(restart_interval, 1),
+            counter: 0,
+            last_key: vec![],
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    fn size_estimate(&self) -> usize {
+        self.buffer.len() + 4 * self.restarts.len() + 4
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut shared = 0;
+        if self.counter < self.restart_interval {
+            shared = key.iter().zip(&self.last_key).take_while(|&(a, b)| a == b).count();
+        } else {
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        put_varint64(&mut self.buffer, shared as u64);
+        put_varint64(&mut self.buffer, (key.len() - shared) as u64);
+        put_varint64(&mut self.buffer, value.len() as u64);
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.counter += 1;
+    }
+
+    /// Return the contents of the block and reset the builder.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut contents = mem::take(&mut self.buffer);
+        for &restart in &self.restarts {
+            put_fixed32(&mut contents, restart);
+        }
+        put_fixed32(&mut contents, self.restarts.len() as u32);
+        self.restarts = vec![0];
+        self.counter = 0;
+        self.last_key.clear();
+        contents
+    }
+}
+
+impl FilterBuilder {
+    fn new(bits_per_key: usize) -> FilterBuilder {
+        FilterBuilder {
+            bits_per_key,
+            keys: vec![],
+            filters: vec![],
+            offsets: vec![],
+        }
+    }
+
+    /// Start the filters covering the data block at `offset`.
+    fn start_block(&mut self, offset: u64) {
+        let index = (offset >> FILTER_BASE_LG) as usize;
+        while index > self.offsets.len() {
+            self.generate();
+        }
+    }
+
+    fn add(&mut self, user_key: &[u8]) {
+        self.keys.push(user_key.to_vec());
+    }
+
+    fn generate(&mut self) {
+        self.offsets.push(self.filters.len() as u32);
+        if !self.keys.is_empty() {
+            let filter = bloom_create(&self.keys, self.bits_per_key);
+            self.filters.extend_from_slice(&filter);
+            self.keys.clear();
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if !self.keys.is_empty() {
+            self.generate();
+        }
+        let mut contents = self.filters;
+        let array = contents.len() as u32;
+        for &offset in &self.offsets {
+            put_fixed32(&mut contents, offset);
+        }
+        put_fixed32(&mut contents, array);
+        contents.push(FILTER_BASE_LG);
+        contents
+    }
+}
+
+fn compare_internal(a: &InternalKey, b: &InternalKey) -> cmp::Ordering {
+    // newer writes of a key come first, and of two writes with the same
+    // sequence number, values before deletions
+    a.user_key.cmp(&b.user_key)
+        .then(b.sequence.cmp(&a.sequence))
+        .then((b.value_type == ValueType::Value).cmp(&(a.value_type == ValueType::Value)))
+}
+
+impl TableOptions {
+    /// Create options for writing tables like leveldb does by default.
+    pub fn new() -> TableOptions {
+        TableOptions {
+            block_size: 4096,
+            block_restart_interval: 16,
+            compression: Compression::Snappy,
+            bloom_bits_per_key: None,
+        }
+    }
+}
+
+impl<W: Write> TableBuilder<W> {
+    /// Create a builder writing a table to `writer`.
+    pub fn new(writer: W, options: TableOptions) -> TableBuilder<W> {
+        let mut filter = options.bloom_bits_per_key.map(FilterBuilder::new);
+        if let Some(ref mut filter) = filter {
+            filter.start_block(0);
+        }
+        TableBuilder {
+            writer,
+            data: BlockBuilder::new(options.block_restart_interval),
+            index: BlockBuilder::new(1),
+            options,
+            offset: 0,
+            filter,
+            pending_index: None,
+            last_key: None,
+            num_entries: 0,
+            finished: false,
+        }
+    }
+
+    /// The number of entries added so far.
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    /// The number of bytes written so far.
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// Add an entry to the table.
+    ///
+    /// Fails if `key` does not sort after the previously added key.
+    pub fn add(&mut self, key: &InternalKey, value: &[u8]) -> Result<(), Error> {
+        if self.finished {
+            return Err(Error::new("table already finished".to_string()));
+        }
+        if let Some(ref last) = self.last_key {
+            if compare_internal(last, key) != cmp::Ordering::Less {
+                return Err(Error::new(format!("table keys out of order: {:?} after {:?}", key, last)));
+            }
+        }
+        if let Some((last_key, handle)) = self.pending_index.take() {
+            self.add_index_entry(&last_key, &handle);
+        }
+        if let Some(ref mut filter) = self.filter {
+            filter.add(&key.user_key);
+        }
+        self.data.add(&key.encode(), value);
+        self.last_key = Some(key.clone());
+        self.num_entries += 1;
+        if self.data.size_estimate() >= self.options.block_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Point the index at a data block, keyed by the last key in it.
+    fn add_index_entry(&mut self, last_key: &[u8], handle: &BlockHandle) {
+        let mut encoded = vec![];
+        handle.encode_to(&mut encoded);
+        self.index.add(last_key, &encoded);
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.data.is_empty() {
+            return Ok(());
+        }
+        let last_key = self.data.last_key.clone();
+        let contents = self.data.finish();
+        let handle = self.write_block(&contents, self.options.compression)?;
+        self.pending_index = Some((last_key, handle));
+        if let Some(ref mut filter) = self.filter {
+            filter.start_block(self.offset);
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, contents: &[u8], compression: Compression) -> Result<BlockHandle, Error> {
+        let compressed = match compression {
+            Compression::Snappy => Some(snappy::compress(contents)),
+            Compression::No => None,
+        };
+        let (contents, kind) = match compressed {
+            Some(ref compressed) if compressed.len() < contents.len() - contents.len() / 8 => {
+                (&compressed[..], SNAPPY_COMPRESSION)
+            }
+            _ => (contents, NO_COMPRESSION),
+        };
+        let crc = crc32c::extend(crc32c::value(contents), &[kind]);
+        let mut trailer = vec![kind];
+        put_fixed32(&mut trailer, crc32c::mask(crc));
+        self.writer.write_all(contents)?;
+        self.writer.write_all(&trailer)?;
+        let handle = BlockHandle {
+            offset: self.offset,
+            size: contents.len() as u64,
+        };
+        self.offset += (contents.len() + BLOCK_TRAILER_LEN) as u64;
+        Ok(handle)
+    }
+
+    /// Write the remaining blocks and the footer, and flush the writer.
+    ///
+    /// Returns the size of the table file.
+    pub fn finish(&mut self) -> Result<u64, Error> {
+        if self.finished {
+            return Err(Error::new("table already finished".to_string()));
+        }
+        self.finished = true;
+        self.flush()?;
+
+        let mut metaindex = BlockBuilder::new(1);
+        if let Some(filter) = self.filter.take() {
+            let handle = self.write_block(&filter.finish(), Compression::No)?;
+            let mut encoded = vec![];
+            handle.encode_to(&mut encoded);
+            metaindex.add(format!("filter.{}", BLOOM_FILTER_POLICY).as_bytes(), &encoded);
+        }
+        let metaindex = self.write_block(&metaindex.finish(), self.options.compression)?;
+
+        if let Some((last_key, handle)) = self.pending_index.take() {
+            self.add_index_entry(&last_key, &handle);
+        }
+        let contents = self.index.finish();
+        let index = self.write_block(&contents, self.options.compression)?;
+
+        let mut footer = vec![];
+        metaindex.encode_to(&mut footer);
+        index.encode_to(&mut footer);
+        footer.resize(FOOTER_LEN - 8, 0);
+        put_fixed64(&mut footer, TABLE_MAGIC);
+        self.writer.write_all(&footer)?;
+        self.writer.flush()?;
+        self.offset += FOOTER_LEN as u64;
+        Ok(self.offset)
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Read the contents of a block from a table file, verifying its checksum
+/// and decompressing it.
+fn read_block(data: &[u8], handle: &BlockHandle) -> Result<Vec<u8>, Error> {
+    let start = handle.offset as usize;
+    let end = handle.offset
+        .checked_add(handle.size)
+        .map(|end| end as usize)
+        .filter(|&end| end.saturating_add(BLOCK_TRAILER_LEN) <= data.len() - FOOTER_LEN)
+        .ok_or_else(|| corrupt("block handle out of range"))?;
+    let contents = &data[start..end];
+    let compression = data[end];
+    let expected = crc32c::unmask(decode_fixed32(&data[end + 1..]));
+    if crc32c::extend(crc32c::value(contents), &[compression]) != expected {
+        return Err(corrupt("block checksum mismatch"));
+    }
+    match compression {
+        NO_COMPRESSION => Ok(contents.to_vec()),
+        SNAPPY_COMPRESSION => snappy::decompress(contents),
+        _ => Err(corrupt("unknown compression type")),
+    }
+}
+
+impl Table {
+    /// Read the table file at `path`.
+    pub fn open(path: &Path) -> Result<Table, Error> {
+        let mut data = vec![];
+        File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .map_err(|e| Error::new(format!("reading {}: {}", path.display(), e)))?;
+        Table::from_bytes(data)
+    }
+
+    /// Parse a table from the contents of a table file.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Table, Error> {
+        if data.len() < FOOTER_LEN {
+            return Err(corrupt("file too short"));
+        }
+        let footer = &data[data.len() - FOOTER_LEN..];
+        if decode_fixed64(&footer[FOOTER_LEN - 8..]) != TABLE_MAGIC {
+            return Err(corrupt("bad magic number"));
+        }
+        let mut handles = &footer[..FOOTER_LEN - 8];
+        let metaindex = BlockHandle::decode(&mut handles)?;
+        let index = BlockHandle::decode(&mut handles)?;
+
+        let index = Block::new(read_block(&data, &index)?)?;
+
+        let mut filter = None;
+        let mut meta = BlockIter::new(Block::new(read_block(&data, &metaindex)?)?);
+        while let Some((key, value)) = meta.next()? {
+            if key.starts_with(b"filter.") {
+                let handle = BlockHandle::decode(&mut &value[..])?;
+                let policy = String::from_utf8_lossy(&key[7..]).into_owned();
+                filter = Some((policy, read_block(&data, &handle)?));
+            }
+        }
+        Ok(Table {
+            data,
+            index,
+            filter,
+        })
+    }
+
+    /// The name of the filter policy the table was written with, if any.
+    pub fn filter_policy(&self) -> Option<&str> {
+        self.filter.as_ref().map(|f| &f.0[..])
+    }
+
+    /// The locations of all data blocks, in table order.
+    pub fn data_blocks(&self) -> Result<Vec<BlockHandle>, Error> {
+        let mut index = BlockIter::new(self.index.clone());
+        let mut blocks = vec![];
+        while let Some((_, value)) = index.next()? {
+            blocks.push(BlockHandle::decode(&mut &value[..])?);
+        }
+        Ok(blocks)
+    }
+
+    /// Whether the filter of `block` may contain `user_key`.
+    ///
+    /// Returns `true` if the table has no filter, or a filter of a policy
+    /// other than leveldb's builtin bloom filter.
+    pub fn key_may_match(&self, block: &BlockHandle, user_key: &[u8]) -> bool {
+        let (policy, filter) = match self.filter {
+            Some((ref policy, ref filter)) => (policy, filter),
+            None => return true,
+        };
+        let len = filter.len();
+        if policy != BLOOM_FILTER_POLICY || len < 5 {
+            return true;
+        }
+        let base_lg = filter[len - 1] as u32;
+        let offsets = decode_fixed32(&filter[len - 5..]) as usize;
+        if offsets > len - 5 || base_lg >= 64 {
+            return true;
+        }
+        let index = (block.offset >> base_lg) as usize;
+        if index >= (len - 5 - offsets) / 4 {
+            return true;
+        }
+        let start = decode_fixed32(&filter[offsets + 4 * index..]) as usize;
+        let limit = decode_fixed32(&filter[offsets + 4 * index + 4..]) as usize;
+        if start == limit {
+            return false;
+        }
+        if start > limit || limit > offsets {
+            return true;
+        }
+        bloom_may_match(&filter[start..limit], user_key)
+    }
+
+    /// Iterate over all entries of the table.
+    pub fn iter(&self) -> TableIterator<'_> {
+        TableIterator {
+            table: self,
+            index: BlockIter::new(self.index.clone()),
+            data: None,
+            failed: false,
+        }
+    }
+}
+
+fn user_key_before(target: &[u8]) -> impl Fn(&[u8]) -> bool + '_ {
+    move |key| {
+        let user_key = &key[..key.len().saturating_sub(8)];
+        user_key.cmp(target) == cmp::Ordering::Less
+    }
+}
+
+impl<'a> TableIterator<'a> {
+    /// Position the iterator at the first entry for `user_key` or after.
+    ///
+    /// This assumes that the database used the default, bytewise
+    /// comparator.
+    pub fn seek(&mut self, user_key: &[u8]) -> Result<(), Error> {
+        self.data = None;
+        let result = self.index.seek(user_key_before(user_key)).and_then(|_| {
+            if let Some((_, handle)) = self.index.next()? {
+                let handle = BlockHandle::decode(&mut &handle[..])?;
+                let mut data = BlockIter::new(Block::new(read_block(&self.table.data, &handle)?)?);
+                data.seek(user_key_before(user_key))?;
+                self.data = Some(data);
+            }
+            Ok(())
+        });
+        self.failed = result.is_err();
+        result
+    }
+
+    fn advance(&mut self) -> Result<Option<(InternalKey, Vec<u8>)>, Error> {
+        loop {
+            if let Some(ref mut data) = self.data {
+                if let Some((key, value)) = data.next()? {
+                    return Ok(Some((InternalKey::decode(&key)?, value)));
+                }
+            }
+            match self.index.next()? {
+                Some((_, handle)) => {
+                    let handle = BlockHandle::decode(&mut &handle[..])?;
+                    self.data = Some(BlockIter::new(Block::new(read_block(&self.table.data, &handle)?)?));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<'a> iter::Iterator for TableIterator<'a> {
+    type Item = Result<(InternalKey, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        match self.advance() {
+            Ok(entry) => entry.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
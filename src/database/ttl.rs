@@ -0,0 +1,262 @@
+//! Time-to-live expiration for keys.
+//!
+//! A `Ttl` handle stores an expiry timestamp in front of every value it
+//! writes. Reads through the handle hide expired entries; they are removed
+//! from disk by `Ttl::sweep`, either on demand or periodically through a
+//! background `Sweeper`.
+//!
+//! All values of a database used with `Ttl` must be written through it;
+//! reads, iteration and sweeps fail on values too short to hold the expiry.
+//! Writes hold their key's lock from the database's `LockManager`, which
+//! `Ttl::sweep` also takes before deleting a key, so a value re-written
+//! with a fresh expiry is never swept.
+use std::iter;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::Database;
+use super::batch::{Batch, Writebatch};
+use super::compaction::Compaction;
+use super::error::Error;
+use super::iterator::{Iterable, Iterator, LevelDBIterator};
+use super::key::{Key, from_u8};
+use super::kv::KV;
+use options::{ReadOptions, WriteOptions};
+
+const HEADER_LEN: usize = 8;
+const NEVER: u64 = 0;
+
+/// The number of deletes written per batch by the `Sweeper`.
+pub const DEFAULT_SWEEP_BATCH: usize = 1000;
+
+/// A database handle storing values with an expiry time.
+pub struct Ttl<'a, K: Key + 'a> {
+    database: &'a Database<K>,
+}
+
+/// An iterator over the live entries of a `Ttl` database.
+///
+/// `from`, `to` and `reverse` bound and order it like the database's
+/// iterators.
+pub struct TtlIterator<'a, K: Key + 'a, I: LevelDBIterator<'a, K> = Iterator<'a, K>> {
+    inner: I,
+    now: u64,
+    marker: PhantomData<&'a K>,
+}
+
+/// A background thread sweeping expired keys periodically.
+///
+/// The thread is stopped when the `Sweeper` is dropped.
+pub struct Sweeper {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+fn now_millis() -> u64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64
+}
+
+fn encode(expires_at: u64, value: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(HEADER_LEN + value.len());
+    for i in (0..HEADER_LEN).rev() {
+        raw.push((expires_at >> (8 * i)) as u8);
+    }
+    raw.extend_from_slice(value);
+    raw
+}
+
+fn expires_at(raw: &[u8]) -> Result<u64, Error> {
+    if raw.len() < HEADER_LEN {
+        return Err(Error::new("value without expiry header".to_string()));
+    }
+    Ok(raw[..HEADER_LEN].iter().fold(0, |acc, &b| acc << 8 | b as u64))
+}
+
+fn is_expired(raw: &[u8], now: u64) -> Result<bool, Error> {
+    let expires_at = expires_at(raw)?;
+    Ok(expires_at != NEVER && expires_at <= now)
+}
+
+impl<'a, K: Key + 'a> Ttl<'a, K> {
+    /// Wrap `database`.
+    pub fn new(database: &'a Database<K>) -> Ttl<'a, K> {
+        Ttl { database }
+    }
+
+    /// Store a value that never expires.
+    pub fn put(&self, options: WriteOptions, key: K, value: &[u8]) -> Result<(), Error> {
+        let _lock = self.database.lock_manager().lock(&key)?;
+        self.database.put(options, key, &encode(NEVER, value))
+    }
+
+    /// Store a value that expires after `ttl`.
+    pub fn put_with_ttl(&self,
+                        options: WriteOptions,
+                        key: K,
+                        value: &[u8],
+                        ttl: Duration)
+                        -> Result<(), Error> {
+        let ttl = ttl.as_secs().saturating_mul(1000).saturating_add(ttl.subsec_millis() as u64);
+        let expires_at = now_millis().saturating_add(ttl.max(1));
+        let _lock = self.database.lock_manager().lock(&key)?;
+        self.database.put(options, key, &encode(expires_at, value))
+    }
+
+    /// Delete a value.
+    pub fn delete(&self, options: WriteOptions, key: K) -> Result<(), Error> {
+        let _lock = self.database.lock_manager().lock(&key)?;
+        self.database.delete(options, key)
+    }
+
+    /// Read a value, returning `None` if it is missing or expired.
+    pub fn get(&self, options: ReadOptions<'a, K>, key: K) -> Result<Option<Vec<u8>>, Error> {
+        match self.database.get(options, key)? {
+            Some(ref raw) if !is_expired(raw, now_millis())? => Ok(Some(raw[HEADER_LEN..].to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Iterate over all live entries.
+    pub fn iter(&self, options: ReadOptions<'a, K>) -> TtlIterator<'a, K> {
+        TtlIterator {
+            inner: self.database.iter(options),
+            now: now_millis(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Delete all expired keys, writing `batch_size` deletes per batch.
+    ///
+    /// The keys of a batch are locked and re-read before deleting them, so
+    /// keys written concurrently are kept. Fails on a value without an
+    /// expiry header, after deleting the batches before it. The range spanning the deleted keys is compacted
+    /// afterwards, so the space is reclaimed. Returns the number of keys
+    /// deleted.
+    pub fn sweep(&self, options: WriteOptions, batch_size: usize) -> Result<usize, Error> {
+        let now = now_millis();
+        let mut candidates = Vec::with_capacity(batch_size);
+        let mut deleted = 0;
+        let mut range: Option<(K, K)> = None;
+
+        for (key, raw) in self.database.iter(ReadOptions::new()) {
+            if is_expired(&raw, now)? {
+                candidates.push(key);
+            }
+            if candidates.len() >= batch_size.max(1) {
+                deleted += self.delete_expired(options, &candidates, now, &mut range)?;
+                candidates.clear();
+            }
+        }
+        if !candidates.is_empty() {
+            deleted += self.delete_expired(options, &candidates, now, &mut range)?;
+        }
+        if let Some((first, last)) = range {
+            self.database.compact(&first, &last);
+        }
+        Ok(deleted)
+    }
+
+    /// Delete those of `keys` that are still expired at `now`, widening
+    /// `range` to cover them.
+    fn delete_expired(&self,
+                      options: WriteOptions,
+                      keys: &[K],
+                      now: u64,
+                      range: &mut Option<(K, K)>)
+                      -> Result<usize, Error> {
+        let refs: Vec<&K> = keys.iter().collect();
+        let _lock = self.database.lock_manager().lock_all(&refs)?;
+        let mut batch = Writebatch::new();
+        let mut deleted = 0;
+        for key in keys {
+            let expired = match self.database.get(ReadOptions::new(), key)? {
+                Some(raw) => is_expired(&raw, now)?,
+                None => false,
+            };
+            if !expired {
+                continue;
+            }
+            let last: K = key.as_slice(|k| from_u8(k));
+            let first = match range.take() {
+                Some((first, _)) => first,
+                None => key.as_slice(|k| from_u8(k)),
+            };
+            *range = Some((first, last));
+            batch.delete(key.as_slice(|k| from_u8(k)));
+            deleted += 1;
+        }
+        if deleted > 0 {
+            self.database.write(options, &batch)?;
+        }
+        Ok(deleted)
+    }
+}
+
+impl<'a, K: Key + 'a, I: LevelDBIterator<'a, K>> TtlIterator<'a, K, I> {
+    /// Start at `key`, see `LevelDBIterator::from`.
+    pub fn from(self, key: &'a K) -> Self {
+        TtlIterator { inner: self.inner.from(key), ..self }
+    }
+
+    /// Stop at `key`, see `LevelDBIterator::to`.
+    pub fn to(self, key: &'a K) -> Self {
+        TtlIterator { inner: self.inner.to(key), ..self }
+    }
+
+    /// Iterate in the opposite direction.
+    pub fn reverse(self) -> TtlIterator<'a, K, I::RevIter> {
+        TtlIterator {
+            inner: self.inner.reverse(),
+            now: self.now,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, I> iter::Iterator for TtlIterator<'a, K, I>
+    where K: Key + 'a,
+          I: LevelDBIterator<'a, K> + iter::Iterator<Item = (K, Vec<u8>)>
+{
+    type Item = Result<(K, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (key, raw) in &mut self.inner {
+            match is_expired(&raw, self.now) {
+                Ok(false) => return Some(Ok((key, raw[HEADER_LEN..].to_vec()))),
+                Ok(true) => continue,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        None
+    }
+}
+
+impl Sweeper {
+    /// Start sweeping `database` every `interval`.
+    pub fn spawn<K: Key + 'static>(database: Arc<Database<K>>, interval: Duration) -> Sweeper {
+        let (stop, stopped) = channel();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                // errors are retried on the next run
+                let _ = Ttl::new(&database).sweep(WriteOptions::new(), DEFAULT_SWEEP_BATCH);
+            }
+        });
+        Sweeper {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
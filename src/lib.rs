@@ -56,6 +56,27 @@ pub use database::kv;
 pub use database::batch;
 pub use database::management;
 pub use database::compaction;
+pub use database::locking;
+pub use database::merge;
+pub use database::byte_key;
+pub use database::keyspace;
+pub use database::ttl;
+pub use database::index;
+pub use database::properties;
+pub use database::dump;
+pub use database::backup;
+pub use database::table;
+pub use database::log;
+pub use database::manifest;
+pub use database::changes;
+pub use database::replication;
+pub use database::merkle;
+pub use database::diff;
+pub use database::range_delete;
+pub use database::bulk;
+pub use database::multi_get;
+pub use database::ingest;
+pub use database::read_only;
 
 #[allow(missing_docs)]
 pub mod database;
@@ -0,0 +1,101 @@
+use utils::{open_database,tmpdir,db_put_simple};
+use leveldb::backup::BackupEngine;
+use leveldb::compaction::Compaction;
+use leveldb::options::{WriteOptions,ReadOptions};
+use leveldb::database::kv::KV;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_backup_and_restore() {
+  let tmp = tmpdir("backup");
+  let database = &mut open_database::<i32>(tmp.path().join("db").as_path(), true);
+  for key in 0..100 {
+    db_put_simple(database, key, &[key as u8]);
+  }
+
+  let engine = BackupEngine::open(tmp.path().join("backups").as_path()).unwrap();
+  let info = engine.create_backup(database).unwrap();
+  assert_eq!(info.id, 1);
+
+  // writes after the backup are not part of it
+  db_put_simple(database, 100, &[100]);
+  database.delete(WriteOptions::new(), 0).unwrap();
+
+  let restored_path = tmp.path().join("restored");
+  engine.restore(1, &restored_path).unwrap();
+  let restored = open_database::<i32>(&restored_path, false);
+  assert_eq!(restored.get(ReadOptions::new(), 0).unwrap(), Some(vec![0]));
+  assert_eq!(restored.get(ReadOptions::new(), 99).unwrap(), Some(vec![99]));
+  assert!(restored.get(ReadOptions::new(), 100).unwrap().is_none());
+
+  assert!(engine.restore(1, &restored_path).is_err());
+}
+
+#[test]
+fn test_backup_incremental_and_retention() {
+  let tmp = tmpdir("backup_retention");
+  let database = &mut open_database::<i32>(tmp.path().join("db").as_path(), true);
+  db_put_simple(database, 1, &[1]);
+
+  let backups = tmp.path().join("backups");
+  let engine = BackupEngine::open(&backups).unwrap();
+  engine.create_backup(database).unwrap();
+  let shared = || fs::read_dir(backups.join("shared")).unwrap().count();
+  let stored = shared();
+
+  // an unchanged database produces identical files, which are stored once
+  engine.create_backup(database).unwrap();
+  assert_eq!(shared(), stored);
+
+  db_put_simple(database, 2, &[2]);
+  engine.create_backup(database).unwrap();
+  let ids: Vec<u64> = engine.list().unwrap().iter().map(|b| b.id).collect();
+  assert_eq!(ids, vec![1, 2, 3]);
+
+  assert_eq!(engine.purge_old_backups(1).unwrap(), 2);
+  let ids: Vec<u64> = engine.list().unwrap().iter().map(|b| b.id).collect();
+  assert_eq!(ids, vec![3]);
+
+  let restored_path = tmp.path().join("restored");
+  engine.restore_latest(&restored_path).unwrap();
+  let restored = open_database::<i32>(&restored_path, false);
+  assert_eq!(restored.get(ReadOptions::new(), 2).unwrap(), Some(vec![2]));
+
+  engine.delete_backup(3).unwrap();
+  assert!(engine.list().unwrap().is_empty());
+  assert_eq!(shared(), 0);
+}
+
+fn shared_size(backups: &Path) -> u64 {
+  fs::read_dir(backups.join("shared")).unwrap().map(|e| e.unwrap().metadata().unwrap().len()).sum()
+}
+
+#[test]
+fn test_backup_stores_delta() {
+  let tmp = tmpdir("backup_delta");
+  let database = &mut open_database::<i32>(tmp.path().join("db").as_path(), true);
+  for key in 0..1000 {
+    db_put_simple(database, key, &[key as u8; 1000]);
+  }
+  // move everything into table files
+  database.compact(&0, &1000);
+
+  let backups = tmp.path().join("backups");
+  let engine = BackupEngine::open(&backups).unwrap();
+  engine.create_backup(database).unwrap();
+  let full = shared_size(&backups);
+  assert!(full > 100_000);
+
+  db_put_simple(database, 500, &[1; 1000]);
+  let info = engine.create_backup(database).unwrap();
+  // the second backup lists all files, but only stores the changed ones
+  assert!(info.size > full);
+  assert!(shared_size(&backups) - full < full / 10);
+
+  let restored_path = tmp.path().join("restored");
+  engine.restore(info.id, &restored_path).unwrap();
+  let restored = open_database::<i32>(&restored_path, false);
+  assert_eq!(restored.get(ReadOptions::new(), 500).unwrap(), Some(vec![1; 1000]));
+  assert_eq!(restored.get(ReadOptions::new(), 999).unwrap(), Some(vec![231; 1000]));
+}
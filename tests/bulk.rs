@@ -0,0 +1,62 @@
+use utils::tmpdir;
+use leveldb::byte_key::ByteKey;
+use leveldb::bulk::{bulk_load_options,BulkLoad,BulkLoadOptions};
+use leveldb::options::ReadOptions;
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use std::fs;
+
+fn entry(i: usize) -> (ByteKey, Vec<u8>) {
+  (ByteKey::from(format!("{:06}", i).as_str()), format!("value {}", i).into_bytes())
+}
+
+#[test]
+fn test_bulk_load_sorted() {
+  let tmp = tmpdir("bulk_sorted");
+  let database: Database<ByteKey> = Database::open(tmp.path(), bulk_load_options()).unwrap();
+  let mut options = BulkLoadOptions::new();
+  options.sorted = true;
+  options.max_batch_bytes = 1024;
+
+  assert_eq!(database.bulk_load(options, (0..2000).map(entry)).unwrap(), 2000);
+  let loaded: Vec<_> = database.iter(ReadOptions::new()).collect();
+  assert_eq!(loaded, (0..2000).map(entry).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_bulk_load_unsorted_spills_runs() {
+  let tmp = tmpdir("bulk_unsorted");
+  let runs = tmp.path().join("runs");
+  fs::create_dir(&runs).unwrap();
+  let database: Database<ByteKey> = Database::open(tmp.path().join("db").as_path(), bulk_load_options()).unwrap();
+  let mut options = BulkLoadOptions::new();
+  options.max_run_bytes = 4096;
+  options.temp_dir = Some(runs.clone());
+
+  // a permutation of 0..5000, followed by overwrites of some keys
+  let unsorted = (0..5000).map(|i| (i * 7919) % 5000).map(entry);
+  let overwrites = (0..10).map(|i| (ByteKey::from(format!("{:06}", i * 100).as_str()), b"new".to_vec()));
+  assert_eq!(database.bulk_load(options, unsorted.chain(overwrites)).unwrap(), 5010);
+
+  let keys: Vec<_> = database.keys_iter(ReadOptions::new()).collect();
+  assert_eq!(keys, (0..5000).map(|i| entry(i).0).collect::<Vec<_>>());
+  assert_eq!(database.get(ReadOptions::new(), ByteKey::from("000300")).unwrap(), Some(b"new".to_vec()));
+  assert_eq!(database.get(ReadOptions::new(), ByteKey::from("000301")).unwrap(), Some(b"value 301".to_vec()));
+  assert_eq!(fs::read_dir(&runs).unwrap().count(), 0);
+}
+
+#[test]
+fn test_bulk_load_throttle_timeout() {
+  use std::time::Duration;
+
+  let tmp = tmpdir("bulk_throttle");
+  let database: Database<ByteKey> = Database::open(tmp.path(), bulk_load_options()).unwrap();
+  let mut options = BulkLoadOptions::new();
+  options.sorted = true;
+  options.max_batch_bytes = 1024;
+  // level 0 never holds fewer than zero files
+  options.max_level0_files = 0;
+  options.throttle_timeout = Duration::from_millis(50);
+  assert!(database.bulk_load(options, (0..2000).map(entry)).is_err());
+}
@@ -0,0 +1,151 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::changes::{Change,SlowSubscriberPolicy};
+use leveldb::options::WriteOptions;
+use leveldb::database::kv::KV;
+use leveldb::database::batch::{Batch,Writebatch};
+use std::sync::Arc;
+use std::thread;
+
+fn put(key: &str, value: &str) -> Change {
+  Change::Put { key: key.as_bytes().to_vec(), value: value.as_bytes().to_vec() }
+}
+
+#[test]
+fn test_changes_delivered_in_order() {
+  let tmp = tmpdir("changes");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  database.put(WriteOptions::new(), ByteKey::from("before"), b"x").unwrap();
+
+  let subscription = database.subscribe(16, SlowSubscriberPolicy::Block);
+  database.put(WriteOptions::new(), ByteKey::from("a"), b"1").unwrap();
+  database.delete(WriteOptions::new(), ByteKey::from("a")).unwrap();
+  let mut batch = Writebatch::new();
+  batch.put(ByteKey::from("b"), b"2");
+  batch.delete(ByteKey::from("c"));
+  database.write(WriteOptions::new(), &batch).unwrap();
+
+  let first = subscription.recv().unwrap();
+  assert_eq!(first.changes, vec![put("a", "1")]);
+  let second = subscription.recv().unwrap();
+  assert_eq!(second.sequence, first.sequence + 1);
+  assert_eq!(second.changes, vec![Change::Delete { key: b"a".to_vec() }]);
+  let third = subscription.recv().unwrap();
+  assert_eq!(third.sequence, first.sequence + 2);
+  assert_eq!(third.changes, vec![put("b", "2"), Change::Delete { key: b"c".to_vec() }]);
+  assert!(subscription.try_recv().is_none());
+
+  drop(database);
+  assert!(subscription.recv().is_none());
+}
+
+#[test]
+fn test_changes_slow_subscribers() {
+  let tmp = tmpdir("changes_slow");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let dropping = database.subscribe(2, SlowSubscriberPolicy::DropNewest);
+  let disconnecting = database.subscribe(2, SlowSubscriberPolicy::Disconnect);
+  for i in 0..5 {
+    database.put(WriteOptions::new(), ByteKey::from(format!("{}", i).as_str()), b"").unwrap();
+  }
+
+  assert_eq!(dropping.dropped(), 3);
+  assert_eq!(dropping.iter().take(2).map(|e| e.sequence).collect::<Vec<_>>(), vec![1, 2]);
+
+  assert!(disconnecting.is_disconnected());
+  assert_eq!(disconnecting.iter().count(), 2);
+}
+
+#[test]
+fn test_changes_blocking_subscriber() {
+  let tmp = tmpdir("changes_block");
+  let database = Arc::new(open_database::<ByteKey>(tmp.path(), true));
+  let subscription = database.subscribe(1, SlowSubscriberPolicy::Block);
+
+  let writer = database.clone();
+  let handle = thread::spawn(move || {
+    for i in 0..100 {
+      writer.put(WriteOptions::new(), ByteKey::from(format!("{:03}", i).as_str()), b"").unwrap();
+    }
+  });
+  let sequences: Vec<u64> = subscription.iter().take(100).map(|e| e.sequence).collect();
+  handle.join().unwrap();
+  assert_eq!(sequences, (1..101).collect::<Vec<u64>>());
+}
+
+#[test]
+fn test_changes_blocked_writer_releases_feed() {
+  use std::time::Duration;
+
+  let tmp = tmpdir("changes_blocked");
+  let database = Arc::new(open_database::<ByteKey>(tmp.path(), true));
+  let subscription = database.subscribe(1, SlowSubscriberPolicy::Block);
+
+  let writer = database.clone();
+  let handle = thread::spawn(move || {
+    for i in 0..3 {
+      writer.put(WriteOptions::new(), ByteKey::from(format!("{}", i).as_str()), b"").unwrap();
+    }
+  });
+  thread::sleep(Duration::from_millis(50));
+  // the writer is blocked on the full subscription, but the feed isn't
+  let late = database.subscribe(16, SlowSubscriberPolicy::DropNewest);
+  let (_snapshot, sequence) = database.snapshot_with_sequence();
+  assert_eq!(sequence, 2);
+  assert_eq!(late.first_sequence(), 3);
+
+  let sequences: Vec<u64> = subscription.iter().take(3).map(|e| e.sequence).collect();
+  handle.join().unwrap();
+  assert_eq!(sequences, vec![1, 2, 3]);
+  assert_eq!(late.iter().next().unwrap().sequence, 3);
+}
+
+#[test]
+fn test_changes_subscribe_during_writes() {
+  use leveldb::database::kv::KV;
+  use leveldb::options::ReadOptions;
+  use std::sync::atomic::{AtomicBool,Ordering};
+  use std::time::Duration;
+
+  let tmp = tmpdir("changes_subscribe_race");
+  let database = Arc::new(open_database::<ByteKey>(tmp.path(), true));
+  let stop = Arc::new(AtomicBool::new(false));
+  let keys: Vec<String> = (0..4).map(|w| format!("writer-{}", w)).collect();
+  let writers: Vec<_> = keys.iter().cloned().map(|key| {
+    let database = database.clone();
+    let stop = stop.clone();
+    thread::spawn(move || {
+      let mut i = 0u64;
+      while !stop.load(Ordering::Relaxed) {
+        database.put(WriteOptions::new(), ByteKey::from(key.as_str()), i.to_string().as_bytes()).unwrap();
+        i += 1;
+      }
+    })
+  }).collect();
+
+  for _ in 0..50 {
+    let subscription = database.subscribe(1 << 16, SlowSubscriberPolicy::Block);
+    let (before, sequence) = database.snapshot_with_sequence();
+    thread::sleep(Duration::from_millis(1));
+    let (after, later) = database.snapshot_with_sequence();
+    let delivered: Vec<Change> = subscription.iter()
+      .take((later + 1 - subscription.first_sequence()) as usize)
+      .filter(|e| e.sequence > sequence)
+      .flat_map(|e| e.changes)
+      .collect();
+    // every write is either in the first snapshot or delivered after it
+    for key in &keys {
+      let key = ByteKey::from(key.as_str());
+      let old = before.get(ReadOptions::new(), &key).unwrap();
+      let new = after.get(ReadOptions::new(), &key).unwrap();
+      if new != old {
+        let change = Change::Put { key: key.0.clone(), value: new.unwrap() };
+        assert!(delivered.contains(&change), "lost write {:?}", change);
+      }
+    }
+  }
+  stop.store(true, Ordering::Relaxed);
+  for writer in writers {
+    writer.join().unwrap();
+  }
+}
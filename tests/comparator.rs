@@ -4,7 +4,7 @@ mod comparator {
   use key::Key;
   use utils::{tmpdir, db_put_simple};
   use leveldb::database::{Database};
-  use leveldb::iterator::Iterable;
+  use leveldb::iterator::{Iterable,LevelDBIterator};
   use leveldb::options::{Options,ReadOptions};
   use leveldb::comparator::{Comparator,OrdComparator};
   use std::cmp::Ordering;
@@ -43,6 +43,26 @@ mod comparator {
     assert_eq!((1, vec![1]), iter.next().unwrap());
   }
 
+  #[test]
+  fn test_comparator_bounds() {
+    let comparator: ReverseComparator<i32> = ReverseComparator { marker: PhantomData };
+    let mut opts = Options::new();
+    opts.create_if_missing = true;
+    let tmp = tmpdir("reverse_comparator_bounds");
+    let database = &mut Database::open_with_comparator(tmp.path(), opts, comparator).unwrap();
+    for key in 1..6 {
+      db_put_simple(database, key, &[key as u8]);
+    }
+
+    // bounds follow the order of the comparator
+    let (from, to) = (4, 2);
+    let keys: Vec<i32> = database.keys_iter(ReadOptions::new()).from(&from).to(&to).collect();
+    assert_eq!(keys, vec![4, 3, 2]);
+    let keys: Vec<i32> = database.keys_iter(ReadOptions::new()).reverse().from(&to).to(&from).collect();
+    assert_eq!(keys, vec![2, 3, 4]);
+    assert_eq!(database.keys_iter(ReadOptions::new()).to(&to).last(), Some(2));
+  }
+
   #[test]
   fn test_ord_comparator() {
     let comparator: OrdComparator<i32> = OrdComparator::new("foo");
@@ -0,0 +1,174 @@
+//! Crash consistency: a writer runs in a child process, is killed at a
+//! random point, and the database it leaves behind is verified.
+//!
+//! The child is this test binary, re-run with `CRASH_WRITER_DIR` set so
+//! that `crash_writer_child` acts as the writer. It writes numbered
+//! batches, syncing some of them, and reports every synced batch on
+//! stdout, along with the length of the log at that point. After killing
+//! it, the database must hold every reported batch, and every batch
+//! completely or not at all, as a prefix of the sequence.
+//!
+//! Killing a process does not lose the writes it handed to the kernel:
+//! unsynced writes stay in the page cache and survive just like synced
+//! ones, so a kill alone cannot tell them apart. It only checks that
+//! batches are applied atomically and in order.
+//!
+//! Losing the unsynced writes is simulated instead, as leveldb's C API
+//! does not allow a custom `Env` to inject IO faults: the newest log is
+//! cut at a random point after the last reported synced batch, as if the
+//! writes after it never reached the disk.
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::batch::{Batch,Writebatch};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::iterator::Iterable;
+use leveldb::log::log_files;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{BufRead,BufReader};
+use std::path::{Path,PathBuf};
+use std::process::{Command,Stdio};
+use std::thread;
+use std::time::Duration;
+
+const DIR_VAR: &str = "CRASH_WRITER_DIR";
+const BATCH_KEYS: usize = 10;
+const ROUNDS: u64 = 6;
+
+struct Random(u64);
+
+impl Random {
+  fn below(&mut self, n: u64) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n
+  }
+}
+
+fn batch_key(batch: u64, key: usize) -> ByteKey {
+  ByteKey(format!("{:08}/{:02}", batch, key).into_bytes())
+}
+
+#[test]
+fn crash_writer_child() {
+  let dir = match env::var(DIR_VAR) {
+    Ok(dir) => dir,
+    // not running as the child of `test_killed_writer_*`
+    Err(_) => return,
+  };
+  let database: Database<ByteKey> = open_database(Path::new(&dir), true);
+  let mut sync = WriteOptions::new();
+  sync.sync = true;
+  for batch in 0.. {
+    let mut writes = Writebatch::new();
+    for key in 0..BATCH_KEYS {
+      writes.put(batch_key(batch, key), &[0; 100]);
+    }
+    if batch % 3 == 0 {
+      database.write(sync, &writes).unwrap();
+      // the log holding the batch, synced up to its end
+      let log = log_files(Path::new(&dir)).unwrap().pop().unwrap();
+      println!("synced {} {} {}", batch, log.metadata().unwrap().len(), log.display());
+    } else {
+      database.write(WriteOptions::new(), &writes).unwrap();
+    }
+  }
+}
+
+/// The last synced batch reported by the writer, with the log it was
+/// written to and the length of that log after it.
+struct Synced {
+  batch: u64,
+  log: PathBuf,
+  len: u64,
+}
+
+/// Run the writer in `dir` until it reported `acks` synced batches, wait
+/// `delay`, kill it and return the last synced batch it reported.
+fn kill_writer(dir: &Path, acks: u64, delay: Duration) -> Synced {
+  let mut child = Command::new(env::current_exe().unwrap())
+    .args(&["crash::crash_writer_child", "--exact", "--nocapture", "--test-threads=1"])
+    .env(DIR_VAR, dir)
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let mut synced = None;
+  let mut seen = 0;
+  // keep the pipe open until the child is killed, so its prints don't
+  // fail and end it early
+  let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+  for line in &mut lines {
+    let line = line.unwrap();
+    if let Some(report) = line.strip_prefix("synced ") {
+      let fields: Vec<&str> = report.splitn(3, ' ').collect();
+      synced = Some(Synced {
+        batch: fields[0].parse().unwrap(),
+        len: fields[1].parse().unwrap(),
+        log: PathBuf::from(fields[2]),
+      });
+      seen += 1;
+      if seen == acks {
+        break;
+      }
+    }
+  }
+  thread::sleep(delay);
+  child.kill().unwrap();
+  let status = child.wait().unwrap();
+  drop(lines);
+  assert!(seen == acks, "writer exited early with {}", status);
+  synced.unwrap()
+}
+
+/// Check that the database holds whole batches `0..n` and return `n`.
+fn verify(dir: &Path) -> u64 {
+  let database: Database<ByteKey> = open_database(dir, false);
+  let mut batches = BTreeMap::new();
+  for key in database.keys_iter(ReadOptions::new()) {
+    let key = String::from_utf8(key.0).unwrap();
+    let batch: u64 = key[..8].parse().unwrap();
+    *batches.entry(batch).or_insert(0) += 1;
+  }
+  for (expected, (batch, keys)) in batches.iter().enumerate() {
+    assert_eq!(*batch, expected as u64, "batch {} missing", expected);
+    assert_eq!(*keys, BATCH_KEYS, "batch {} is partial", batch);
+  }
+  batches.len() as u64
+}
+
+#[test]
+fn test_killed_writer_keeps_synced_batches() {
+  let mut random = Random(0x2545_f491_4f6c_dd1d);
+  for _ in 0..ROUNDS {
+    let tmp = tmpdir("crash");
+    let acks = 1 + random.below(50);
+    let delay = Duration::from_micros(random.below(2000));
+    let synced = kill_writer(tmp.path(), acks, delay).batch;
+    let batches = verify(tmp.path());
+    assert!(batches > synced, "synced batch {} lost, found {} batches", synced, batches);
+  }
+}
+
+#[test]
+fn test_killed_writer_with_torn_log() {
+  let mut random = Random(0x9e37_79b9_7f4a_7c15);
+  for _ in 0..ROUNDS {
+    let tmp = tmpdir("crash_torn");
+    let acks = 1 + random.below(50);
+    let synced = kill_writer(tmp.path(), acks, Duration::from_millis(1));
+
+    // drop a random part of the newest log's unsynced tail; a newer log
+    // than the synced batch's holds only later writes
+    let log = log_files(tmp.path()).unwrap().pop().unwrap();
+    let file = OpenOptions::new().write(true).open(&log).unwrap();
+    let len = file.metadata().unwrap().len();
+    let kept = if log == synced.log { synced.len } else { 0 };
+    file.set_len(len - random.below(len - kept + 1)).unwrap();
+
+    let batches = verify(tmp.path());
+    assert!(batches > synced.batch, "synced batch {} lost, found {} batches", synced.batch, batches);
+  }
+}
@@ -24,3 +24,36 @@ fn test_open_non_existant_database_without_create() {
   let res: Result<Database<i32>,_> = Database::open(tmp.path(), opts);
   assert!(res.is_err());
 }
+
+#[test]
+fn test_prepared_and_custom_options() {
+  use leveldb::database::kv::KV;
+  use leveldb::options::{ReadOptions,WriteOptions};
+  use leveldb::snapshots::Snapshots;
+
+  let mut opts = Options::new();
+  opts.create_if_missing = true;
+  let tmp = tmpdir("prepared_options");
+  let database: Database<i32> = Database::open(tmp.path(), opts).unwrap();
+
+  let mut sync = WriteOptions::new();
+  sync.sync = true;
+  database.put(sync, 1, &[1]).unwrap();
+  database.put(WriteOptions::new(), 2, &[2]).unwrap();
+
+  let mut verify = ReadOptions::new();
+  verify.verify_checksums = true;
+  verify.fill_cache = false;
+  assert_eq!(database.get(verify, 1).unwrap(), Some(vec![1]));
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![2]));
+
+  let snapshot = database.snapshot();
+  database.put(WriteOptions::new(), 1, &[3]).unwrap();
+  for &(verify_checksums, fill_cache) in &[(false, true), (true, false), (false, true)] {
+    let mut options = ReadOptions::new();
+    options.verify_checksums = verify_checksums;
+    options.fill_cache = fill_cache;
+    assert_eq!(snapshot.get(options, 1).unwrap(), Some(vec![1]));
+  }
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![3]));
+}
@@ -0,0 +1,65 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::diff::{self,Diff,Difference};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use leveldb::snapshots::Snapshots;
+
+#[test]
+fn test_diff_between_databases() {
+  let tmp = tmpdir("diff");
+  let a = open_database::<ByteKey>(tmp.path().join("a").as_path(), true);
+  let b = open_database::<ByteKey>(tmp.path().join("b").as_path(), true);
+  for key in &["a", "b", "c", "d"] {
+    a.put(WriteOptions::new(), ByteKey::from(*key), b"1").unwrap();
+  }
+  b.put(WriteOptions::new(), ByteKey::from("b"), b"1").unwrap();
+  b.put(WriteOptions::new(), ByteKey::from("c"), b"2").unwrap();
+  b.put(WriteOptions::new(), ByteKey::from("e"), b"1").unwrap();
+
+  let differences: Vec<_> = Diff::new(&a, &b).collect();
+  assert_eq!(differences, vec![
+    Difference::Removed(ByteKey::from("a"), b"1".to_vec()),
+    Difference::Changed(ByteKey::from("c"), b"1".to_vec(), b"2".to_vec()),
+    Difference::Removed(ByteKey::from("d"), b"1".to_vec()),
+    Difference::Added(ByteKey::from("e"), b"1".to_vec()),
+  ]);
+  assert_eq!(Diff::new(&a, &a).count(), 0);
+}
+
+#[test]
+fn test_diff_against_snapshot() {
+  let tmp = tmpdir("diff_snapshot");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  database.put(WriteOptions::new(), ByteKey::from("a"), b"1").unwrap();
+  let snapshot = database.snapshot();
+  database.put(WriteOptions::new(), ByteKey::from("b"), b"2").unwrap();
+
+  let differences: Vec<_> = Diff::new(&snapshot, &database).collect();
+  assert_eq!(differences, vec![Difference::Added(ByteKey::from("b"), b"2".to_vec())]);
+  assert_eq!(differences[0].key(), &ByteKey::from("b"));
+}
+
+#[test]
+fn test_sync_makes_databases_equal() {
+  let tmp = tmpdir("diff_sync");
+  let source = open_database::<ByteKey>(tmp.path().join("source").as_path(), true);
+  let target = open_database::<ByteKey>(tmp.path().join("target").as_path(), true);
+  for i in 0..250 {
+    let key = ByteKey::from(format!("{:04}", i).as_str());
+    source.put(WriteOptions::new(), key, format!("{}", i).as_bytes()).unwrap();
+  }
+  for i in 200..300 {
+    let key = ByteKey::from(format!("{:04}", i).as_str());
+    target.put(WriteOptions::new(), key, b"stale").unwrap();
+  }
+
+  let written = diff::sync(&source, &target, WriteOptions::new(), 16).unwrap();
+  assert_eq!(written, 300);
+  assert_eq!(Diff::new(&source, &target).count(), 0);
+  assert_eq!(target.iter(ReadOptions::new()).count(), 250);
+
+  let snapshot = source.snapshot();
+  assert_eq!(diff::sync(&snapshot, &target, WriteOptions::new(), 16).unwrap(), 0);
+}
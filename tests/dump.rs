@@ -0,0 +1,107 @@
+use utils::{open_database,tmpdir,db_put_simple};
+use leveldb::dump::{export,import,DumpHeader,DumpReader,DumpWriter,ImportOptions};
+use leveldb::ingest::BYTEWISE_COMPARATOR;
+use leveldb::options::{Options,ReadOptions};
+use leveldb::database::kv::KV;
+use leveldb::snapshots::Snapshots;
+
+fn header() -> DumpHeader {
+  DumpHeader::new(BYTEWISE_COMPARATOR, &Options::new())
+}
+
+#[test]
+fn test_dump_roundtrip() {
+  let tmp = tmpdir("dump");
+  let source = &mut open_database::<i32>(tmp.path().join("source").as_path(), true);
+  for key in 0..100 {
+    db_put_simple(source, key, &[key as u8; 10]);
+  }
+
+  let mut buf = vec![];
+  let exported = export(source, &header(), &mut buf, |_| ()).unwrap();
+  assert_eq!(exported, 100);
+
+  let reader = DumpReader::new(&buf[..]).unwrap();
+  assert_eq!(reader.header(), &header());
+
+  let target = open_database::<i32>(tmp.path().join("target").as_path(), true);
+  let mut options = ImportOptions::new();
+  options.batch_bytes = 100;
+  let mut reports = vec![];
+  let imported = import(&target, &buf[..], &options, |p| reports.push(p.records)).unwrap();
+  assert_eq!(imported, 100);
+  assert_eq!(reports.len(), 13);
+  assert_eq!(*reports.last().unwrap(), 100);
+  assert_eq!(target.get(ReadOptions::new(), 42).unwrap(), Some(vec![42; 10]));
+}
+
+#[test]
+fn test_dump_snapshot() {
+  let tmp = tmpdir("dump_snapshot");
+  let database = &mut open_database::<i32>(tmp.path(), true);
+  db_put_simple(database, 1, &[1]);
+  let snapshot = database.snapshot();
+  db_put_simple(database, 2, &[2]);
+
+  let mut buf = vec![];
+  assert_eq!(export(&snapshot, &header(), &mut buf, |_| ()).unwrap(), 1);
+}
+
+#[test]
+fn test_dump_resume() {
+  let tmp = tmpdir("dump_resume");
+  let source = &mut open_database::<i32>(tmp.path().join("source").as_path(), true);
+  for key in 0..10 {
+    db_put_simple(source, key, &[key as u8]);
+  }
+  let mut buf = vec![];
+  export(source, &header(), &mut buf, |_| ()).unwrap();
+
+  let target = open_database::<i32>(tmp.path().join("target").as_path(), true);
+  let mut options = ImportOptions::new();
+  options.resume_from = 5;
+  assert_eq!(import(&target, &buf[..], &options, |_| ()).unwrap(), 10);
+  assert_eq!(target.get(ReadOptions::new(), 4).unwrap(), None);
+  assert_eq!(target.get(ReadOptions::new(), 5).unwrap(), Some(vec![5]));
+}
+
+#[test]
+fn test_dump_corruption() {
+  let tmp = tmpdir("dump_corrupt");
+  let source = &mut open_database::<i32>(tmp.path(), true);
+  db_put_simple(source, 1, &[1]);
+  let mut buf = vec![];
+  export(source, &header(), &mut buf, |_| ()).unwrap();
+
+  let mut flipped = buf.clone();
+  let len = flipped.len();
+  flipped[len - 20] ^= 0xff;
+  assert!(DumpReader::new(&flipped[..]).unwrap().any(|e| e.is_err()));
+
+  let truncated = &buf[..buf.len() - 5];
+  assert!(DumpReader::new(truncated).unwrap().any(|e| e.is_err()));
+}
+
+#[test]
+fn test_dump_oversized_length() {
+  let mut buf = DumpWriter::new(vec![], &header()).unwrap().finish().unwrap();
+  let len = buf.len();
+  buf.truncate(len - 13);
+  // an entry claiming a 4GiB key, without the bytes to back it
+  buf.extend_from_slice(&[1, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1, 2, 3]);
+  let mut reader = DumpReader::new(&buf[..]).unwrap();
+  assert!(reader.next().unwrap().is_err());
+}
+
+#[test]
+fn test_dump_comparator_mismatch() {
+  let tmp = tmpdir("dump_comparator");
+  let database = open_database::<i32>(tmp.path(), true);
+  let mut buf = vec![];
+  DumpWriter::new(&mut buf, &DumpHeader::new("ord", &Options::new())).unwrap().finish().unwrap();
+  assert!(import(&database, &buf[..], &ImportOptions::new(), |_| ()).is_err());
+
+  let mut options = ImportOptions::new();
+  options.comparator = "ord";
+  assert_eq!(import(&database, &buf[..], &options, |_| ()).unwrap(), 0);
+}
@@ -0,0 +1,78 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::index::{Index,Indexed,IndexReport};
+use leveldb::keyspace::Keyspace;
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+
+fn key(k: &str) -> ByteKey {
+  ByteKey::from(k)
+}
+
+// values are "city,age"
+fn by_city() -> Index {
+  Index::new("city", |v: &[u8]| {
+    vec![v.split(|&b| b == b',').next().unwrap().to_vec()]
+  })
+}
+
+fn open<'a>(database: &'a leveldb::database::Database<ByteKey>) -> Indexed<'a, ByteKey> {
+  Indexed::open(database, "people", vec![by_city()]).unwrap()
+}
+
+#[test]
+fn test_index_maintenance() {
+  let tmp = tmpdir("index");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let people = open(&database);
+
+  people.put(WriteOptions::new(), key("alice"), b"berlin,30").unwrap();
+  people.put(WriteOptions::new(), key("bob"), b"paris,25").unwrap();
+  people.put(WriteOptions::new(), key("carol"), b"berlin,41").unwrap();
+  assert_eq!(people.query("city", b"berlin").unwrap(), vec![key("alice"), key("carol")]);
+
+  people.put(WriteOptions::new(), key("alice"), b"paris,31").unwrap();
+  assert_eq!(people.query("city", b"berlin").unwrap(), vec![key("carol")]);
+  assert_eq!(people.query("city", b"paris").unwrap(), vec![key("alice"), key("bob")]);
+
+  people.delete(WriteOptions::new(), key("bob")).unwrap();
+  assert_eq!(people.query("city", b"paris").unwrap(), vec![key("alice")]);
+  assert_eq!(people.get(ReadOptions::new(), key("bob")).unwrap(), None);
+  assert!(people.query("age", b"30").is_err());
+}
+
+#[test]
+fn test_index_prefix_and_range() {
+  let tmp = tmpdir("index_range");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let people = open(&database);
+  people.put(WriteOptions::new(), key("a"), b"bern,1").unwrap();
+  people.put(WriteOptions::new(), key("b"), b"berlin,2").unwrap();
+  people.put(WriteOptions::new(), key("c"), b"paris,3").unwrap();
+  people.put(WriteOptions::new(), key("d"), b"be\x00r,4").unwrap();
+
+  assert_eq!(people.query_prefix("city", b"ber").unwrap(), vec![key("b"), key("a")]);
+  assert_eq!(people.query_prefix("city", b"be").unwrap(), vec![key("d"), key("b"), key("a")]);
+  assert_eq!(people.query_range("city", b"berlin", b"paris").unwrap(), vec![key("b"), key("a")]);
+  assert_eq!(people.query("city", b"be\x00r").unwrap(), vec![key("d")]);
+}
+
+#[test]
+fn test_index_verify_and_rebuild() {
+  let tmp = tmpdir("index_rebuild");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let people = open(&database);
+  people.put(WriteOptions::new(), key("alice"), b"berlin,30").unwrap();
+  people.put(WriteOptions::new(), key("bob"), b"paris,25").unwrap();
+  assert!(people.verify().unwrap().is_consistent());
+
+  // simulate drift by writing to the primary keyspace directly
+  let primary = Keyspace::open(&database, "people").unwrap();
+  primary.put(WriteOptions::new(), key("alice"), b"rome,30").unwrap();
+  assert_eq!(people.verify().unwrap(), IndexReport { missing: 1, dangling: 1 });
+
+  assert_eq!(people.rebuild(WriteOptions::new()).unwrap(), 2);
+  assert!(people.verify().unwrap().is_consistent());
+  assert_eq!(people.query("city", b"rome").unwrap(), vec![key("alice")]);
+  assert!(people.query("city", b"berlin").unwrap().is_empty());
+}
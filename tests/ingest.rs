@@ -0,0 +1,112 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::ingest::ingest_tables;
+use leveldb::table::{InternalKey,TableBuilder,TableOptions,ValueType};
+use leveldb::manifest::{VersionSet,NUM_LEVELS};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path,PathBuf};
+
+/// Write a table at `path` setting `keys` to `value`, with sequence
+/// numbers from `sequence` on.
+fn write_table<I: Iterator<Item = u32>>(path: &Path, keys: I, value: &[u8], sequence: u64) -> PathBuf {
+  let mut options = TableOptions::new();
+  options.bloom_bits_per_key = Some(10);
+  let mut builder = TableBuilder::new(BufWriter::new(File::create(path).unwrap()), options);
+  for (i, key) in keys.enumerate() {
+    let key = InternalKey {
+      user_key: format!("key{:05}", key).into_bytes(),
+      sequence: sequence + i as u64,
+      value_type: ValueType::Value,
+    };
+    builder.add(&key, value).unwrap();
+  }
+  builder.finish().unwrap();
+  path.to_path_buf()
+}
+
+fn key(i: u32) -> ByteKey {
+  ByteKey(format!("key{:05}", i).into_bytes())
+}
+
+#[test]
+fn test_ingest_disjoint_tables() {
+  let tmp = tmpdir("ingest");
+  let tables = vec![write_table(&tmp.path().join("b.ldb"), 500..1000, b"b", 501),
+                    write_table(&tmp.path().join("a.ldb"), 0..500, b"a", 1),
+                    write_table(&tmp.path().join("empty.ldb"), 0..0, b"", 1)];
+  let db = tmp.path().join("db");
+  let edit = ingest_tables(&db, &tables).unwrap();
+  assert_eq!(edit.last_sequence, Some(1000));
+  assert_eq!(edit.new_files.len(), 2);
+  assert!(edit.new_files.iter().all(|&(level, _)| level == NUM_LEVELS - 1));
+
+  let version = VersionSet::recover(&db).unwrap();
+  assert_eq!(version.files_at_level(NUM_LEVELS - 1).len(), 2);
+  assert_eq!(version.next_file_number, 4);
+
+  {
+    let database = open_database::<ByteKey>(&db, false);
+    assert_eq!(database.get(ReadOptions::new(), key(42)).unwrap(), Some(b"a".to_vec()));
+    assert_eq!(database.get(ReadOptions::new(), key(999)).unwrap(), Some(b"b".to_vec()));
+    assert_eq!(database.get(ReadOptions::new(), key(1000)).unwrap(), None);
+    assert_eq!(database.keys_iter(ReadOptions::new()).count(), 1000);
+
+    // the ingested database takes writes after the ingested sequences
+    database.put(WriteOptions::new(), key(42), b"new").unwrap();
+  }
+  let database = open_database::<ByteKey>(&db, false);
+  assert_eq!(database.get(ReadOptions::new(), key(42)).unwrap(), Some(b"new".to_vec()));
+}
+
+#[test]
+fn test_ingest_overlapping_tables() {
+  let tmp = tmpdir("ingest_overlap");
+  let tables = vec![write_table(&tmp.path().join("new.ldb"), (0..100).filter(|i| i % 2 == 0), b"new", 1000),
+                    write_table(&tmp.path().join("old.ldb"), 0..100, b"old", 1)];
+  let db = tmp.path().join("db");
+  let edit = ingest_tables(&db, &tables).unwrap();
+  assert!(edit.new_files.iter().all(|&(level, _)| level == 0));
+
+  let database: Database<ByteKey> = open_database(&db, false);
+  for i in 0..100 {
+    let expected: &[u8] = if i % 2 == 0 { b"new" } else { b"old" };
+    assert_eq!(database.get(ReadOptions::new(), key(i)).unwrap(), Some(expected.to_vec()));
+  }
+  let values: Vec<_> = database.value_iter(ReadOptions::new()).collect();
+  assert_eq!(values.len(), 100);
+  assert_eq!(values[0], b"new".to_vec());
+  assert_eq!(values[1], b"old".to_vec());
+}
+
+#[test]
+fn test_ingest_overlapping_sequences() {
+  let tmp = tmpdir("ingest_sequences");
+  let db = tmp.path().join("db");
+  // both tables hold keys 50..100, with interleaved sequence numbers
+  let tables = vec![write_table(&tmp.path().join("a.ldb"), 0..100, b"a", 1),
+                    write_table(&tmp.path().join("b.ldb"), 50..150, b"b", 60)];
+  assert!(ingest_tables(&db, &tables).is_err());
+  assert!(!db.join("CURRENT").exists());
+
+  // sequence numbers may overlap between tables with disjoint keys
+  let tables = vec![write_table(&tmp.path().join("c.ldb"), 200..300, b"c", 1),
+                    write_table(&tmp.path().join("d.ldb"), 250..260, b"d", 1000),
+                    write_table(&tmp.path().join("e.ldb"), 400..500, b"e", 1)];
+  ingest_tables(&db, &tables).unwrap();
+  let database: Database<ByteKey> = open_database(&db, false);
+  assert_eq!(database.get(ReadOptions::new(), key(255)).unwrap(), Some(b"d".to_vec()));
+  assert_eq!(database.get(ReadOptions::new(), key(450)).unwrap(), Some(b"e".to_vec()));
+}
+
+#[test]
+fn test_ingest_refuses_existing_database() {
+  let tmp = tmpdir("ingest_existing");
+  let table = write_table(&tmp.path().join("a.ldb"), 0..10, b"a", 1);
+  drop(open_database::<ByteKey>(&tmp.path().join("db"), true));
+  assert!(ingest_tables(&tmp.path().join("db"), &[table]).is_err());
+}
@@ -71,6 +71,19 @@ fn test_iterator_from_to() {
 
   assert_eq!(iter.next().unwrap(), (2,vec![2]));
   assert_eq!(iter.last().unwrap(), (4,vec![4]));
+
+  let keys: Vec<i32> = database.keys_iter(ReadOptions::new()).from(&from).to(&to).collect();
+  assert_eq!(keys, vec![2, 3, 4]);
+  let keys: Vec<i32> = database.keys_iter(ReadOptions::new()).reverse().from(&to).to(&from).collect();
+  assert_eq!(keys, vec![4, 3, 2]);
+  // the bounds don't have to be keys of the database
+  let (below, above) = (0, 6);
+  let keys: Vec<i32> = database.keys_iter(ReadOptions::new()).reverse().from(&above).to(&below).collect();
+  assert_eq!(keys, vec![5, 4, 3, 2, 1]);
+  // ranges ending before they start are empty
+  assert_eq!(database.keys_iter(ReadOptions::new()).from(&to).to(&from).count(), 0);
+  assert_eq!(database.keys_iter(ReadOptions::new()).from(&to).to(&from).last(), None);
+  assert_eq!(database.keys_iter(ReadOptions::new()).to(&below).last(), None);
 }
 
 
@@ -0,0 +1,111 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::keyspace::{Keyspace,KeyspaceBatch};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+use leveldb::database::batch::{Batch,Writebatch};
+use leveldb::compaction::Compaction;
+use leveldb::iterator::{Iterable,LevelDBIterator};
+
+fn key(k: &str) -> ByteKey {
+  ByteKey::from(k)
+}
+
+#[test]
+fn test_keyspace_isolation() {
+  let tmp = tmpdir("keyspace");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let users = Keyspace::open(&database, "users").unwrap();
+  let orders = Keyspace::open(&database, "orders").unwrap();
+  assert!(users.prefix() != orders.prefix());
+
+  users.put(WriteOptions::new(), key("a"), &[1]).unwrap();
+  orders.put(WriteOptions::new(), key("a"), &[2]).unwrap();
+
+  assert_eq!(users.get(ReadOptions::new(), key("a")).unwrap(), Some(vec![1]));
+  assert_eq!(orders.get(ReadOptions::new(), key("a")).unwrap(), Some(vec![2]));
+  assert_eq!(database.get(ReadOptions::new(), key("a")).unwrap(), None);
+
+  users.delete(WriteOptions::new(), key("a")).unwrap();
+  assert_eq!(users.get(ReadOptions::new(), key("a")).unwrap(), None);
+  assert_eq!(orders.get(ReadOptions::new(), key("a")).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn test_keyspace_registry() {
+  let tmp = tmpdir("keyspace_registry");
+  let prefix = {
+    let database = open_database::<ByteKey>(tmp.path(), true);
+    Keyspace::open(&database, "users").unwrap();
+    let orders = Keyspace::open(&database, "orders").unwrap();
+    orders.prefix().to_vec()
+  };
+  let database = open_database::<ByteKey>(tmp.path(), false);
+  let orders = Keyspace::open(&database, "orders").unwrap();
+  assert_eq!(orders.prefix(), &prefix[..]);
+  assert_eq!(Keyspace::list(&database).unwrap(), vec!["orders".to_string(), "users".to_string()]);
+}
+
+#[test]
+fn test_keyspace_iter() {
+  let tmp = tmpdir("keyspace_iter");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let first = Keyspace::open(&database, "first").unwrap();
+  let second = Keyspace::open(&database, "second").unwrap();
+  first.put(WriteOptions::new(), key("a"), &[1]).unwrap();
+  first.put(WriteOptions::new(), key("b"), &[2]).unwrap();
+  second.put(WriteOptions::new(), key("a"), &[3]).unwrap();
+
+  let entries: Vec<_> = first.iter(ReadOptions::new()).collect();
+  assert_eq!(entries, vec![(key("a"), vec![1]), (key("b"), vec![2])]);
+
+  let from = key("b");
+  let entries: Vec<_> = first.iter(ReadOptions::new()).from(&from).collect();
+  assert_eq!(entries, vec![(key("b"), vec![2])]);
+
+  let entries: Vec<_> = first.iter(ReadOptions::new()).reverse().collect();
+  assert_eq!(entries, vec![(key("b"), vec![2]), (key("a"), vec![1])]);
+  assert_eq!(first.keys_iter(ReadOptions::new()).collect::<Vec<_>>(), vec![key("a"), key("b")]);
+  assert_eq!(second.value_iter(ReadOptions::new()).collect::<Vec<_>>(), vec![vec![3]]);
+  assert_eq!(first.keys_iter(ReadOptions::new()).last(), Some(key("b")));
+
+  let entries: Vec<_> = second.iter(ReadOptions::new()).collect();
+  assert_eq!(entries, vec![(key("a"), vec![3])]);
+}
+
+#[test]
+fn test_keyspace_batches() {
+  let tmp = tmpdir("keyspace_batch");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let first = Keyspace::open(&database, "first").unwrap();
+  let second = Keyspace::open(&database, "second").unwrap();
+
+  let mut batch = Writebatch::new();
+  batch.put(key("a"), &[1]);
+  first.write(WriteOptions::new(), &batch).unwrap();
+  assert_eq!(first.get(ReadOptions::new(), key("a")).unwrap(), Some(vec![1]));
+
+  let mut batch = KeyspaceBatch::new();
+  batch.delete(&first, key("a"));
+  batch.put(&second, key("b"), &[2]);
+  database.write(WriteOptions::new(), batch.writebatch()).unwrap();
+  assert_eq!(first.get(ReadOptions::new(), key("a")).unwrap(), None);
+  assert_eq!(second.get(ReadOptions::new(), key("b")).unwrap(), Some(vec![2]));
+
+  second.compact(&key("a"), &key("c"));
+  second.compact_all().unwrap();
+}
+
+#[test]
+fn test_keyspace_ids_exhausted() {
+  let tmp = tmpdir("keyspace_exhausted");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  let last = Keyspace::open(&database, "last").unwrap();
+  let next_id = ByteKey([&[0, 0, 0, 0][..], b"next-keyspace-id"].concat());
+  database.put(WriteOptions::new(), next_id, &[0xff, 0xff, 0xff, 0xfe]).unwrap();
+  assert_eq!(Keyspace::open(&database, "edge").unwrap().prefix(), &[0xff, 0xff, 0xff, 0xfe]);
+  // 0xffffffff would be the reserved area
+  assert!(Keyspace::open(&database, "full").is_err());
+  // existing keyspaces still open
+  assert_eq!(Keyspace::open(&database, "last").unwrap().prefix(), last.prefix());
+}
@@ -0,0 +1,97 @@
+//! Tests for the `ldb` command-line tool, run as a subprocess.
+use utils::tmpdir;
+use leveldb::dump::DumpReader;
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command,Output};
+
+fn ldb(db: &Path, args: &[&str]) -> Output {
+  Command::new(env!("CARGO_BIN_EXE_ldb"))
+    .arg(db)
+    .args(args)
+    .output()
+    .unwrap()
+}
+
+fn stdout(output: Output) -> String {
+  assert!(output.status.success(), "ldb failed: {}", String::from_utf8_lossy(&output.stderr));
+  String::from_utf8(output.stdout).unwrap()
+}
+
+fn stderr(output: Output) -> String {
+  assert!(!output.status.success());
+  String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn test_ldb_get_put_delete() {
+  let tmp = tmpdir("ldb");
+  let db = tmp.path();
+  stdout(ldb(db, &["put", "a", "1", "--create"]));
+  stdout(ldb(db, &["--sync", "put", "b", "2"]));
+  assert_eq!(stdout(ldb(db, &["get", "a"])), "1\n");
+  assert_eq!(stdout(ldb(db, &["get", "62", "--key-format", "hex"])), "2\n");
+  stdout(ldb(db, &["delete", "a"]));
+  assert_eq!(stderr(ldb(db, &["get", "a"])), "ldb: not found\n");
+}
+
+#[test]
+fn test_ldb_arguments() {
+  let tmp = tmpdir("ldb_args");
+  let db = tmp.path();
+  assert!(stderr(ldb(db, &["put", "a", "--create"])).contains("missing argument: value"));
+  assert!(stderr(ldb(db, &["scan", "--limit"])).contains("--limit needs a value"));
+  assert!(stderr(ldb(db, &["scan", "--limit", "x"])).contains("invalid --limit"));
+  assert!(stderr(ldb(db, &["scan", "--bogus"])).contains("unknown flag: --bogus"));
+  assert!(stderr(ldb(db, &["scan", "--key-format", "b64"])).contains("unknown format: b64"));
+  assert!(stderr(ldb(db, &["frobnicate"])).contains("unknown command: frobnicate"));
+}
+
+#[test]
+fn test_ldb_escaping() {
+  let tmp = tmpdir("ldb_escaping");
+  let db = tmp.path();
+  stdout(ldb(db, &["put", "k\\x00\\n", "v\\t\\\\", "--create"]));
+  assert_eq!(stdout(ldb(db, &["get", "6b000a", "--key-format", "hex", "--value-format", "hex"])), "76095c\n");
+  assert_eq!(stdout(ldb(db, &["scan"])), "k\\x00\\n\tv\\t\\\\\n");
+
+  // non-ASCII input is rejected or stored verbatim, never a panic
+  assert!(stderr(ldb(db, &["get", "aéa", "--key-format", "hex"])).contains("invalid hex"));
+  assert!(stderr(ldb(db, &["get", "\\xaé", "--key-format", "escaped"])).contains("invalid escape"));
+  assert!(stderr(ldb(db, &["get", "\\x1"])).contains("invalid escape"));
+  stdout(ldb(db, &["put", "ключ", "значение"]));
+  assert_eq!(stdout(ldb(db, &["get", "ключ", "--value-format", "utf8"])), "значение\n");
+}
+
+#[test]
+fn test_ldb_scan() {
+  let tmp = tmpdir("ldb_scan");
+  let db = tmp.path();
+  stdout(ldb(db, &["put", "a", "1", "--create"]));
+  for key in &["b1", "b2", "b3", "c"] {
+    stdout(ldb(db, &["put", key, "x"]));
+  }
+  assert_eq!(stdout(ldb(db, &["count"])), "5\n");
+  assert_eq!(stdout(ldb(db, &["scan", "--prefix", "b"])), "b1\tx\nb2\tx\nb3\tx\n");
+  assert_eq!(stdout(ldb(db, &["scan", "--from", "b2", "--to", "c"])), "b2\tx\nb3\tx\n");
+  assert_eq!(stdout(ldb(db, &["scan", "--limit", "1"])), "a\t1\n");
+  assert_eq!(stdout(ldb(db, &["count", "--from", "b"])), "4\n");
+}
+
+#[test]
+fn test_ldb_dump_load() {
+  let tmp = tmpdir("ldb_dump");
+  let source = tmp.path().join("source");
+  let target = tmp.path().join("target");
+  let file = tmp.path().join("dump");
+  stdout(ldb(&source, &["put", "a", "\\x00\\xff", "--create"]));
+  stdout(ldb(&source, &["put", "b", "2"]));
+  stdout(ldb(&source, &["dump", file.to_str().unwrap()]));
+
+  // the file uses the library's dump format
+  let entries: Vec<_> = DumpReader::new(File::open(&file).unwrap()).unwrap().map(|e| e.unwrap()).collect();
+  assert_eq!(entries, vec![(b"a".to_vec(), vec![0, 0xff]), (b"b".to_vec(), b"2".to_vec())]);
+
+  assert_eq!(stdout(ldb(&target, &["load", file.to_str().unwrap(), "--create"])), "loaded 2 entries\n");
+  assert_eq!(stdout(ldb(&target, &["scan"])), "a\t\\x00\\xff\nb\t2\n");
+}
@@ -0,0 +1,71 @@
+use utils::{open_database,tmpdir,db_put_simple};
+use leveldb::locking::ReadModifyWrite;
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+use std::time::Duration;
+
+#[test]
+fn test_update() {
+  let tmp = tmpdir("update");
+  let database = &mut open_database(tmp.path(), true);
+  db_put_simple(database, 1, &[1]);
+
+  let new = database.update(WriteOptions::new(), 1, |old| {
+    old.map(|v| vec![v[0] + 1])
+  });
+  assert_eq!(new.unwrap(), Some(vec![2]));
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![2]));
+
+  let deleted = database.update(WriteOptions::new(), 1, |_| None);
+  assert_eq!(deleted.unwrap(), None);
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), None);
+}
+
+#[test]
+fn test_compare_and_swap() {
+  let tmp = tmpdir("cas");
+  let database = &mut open_database(tmp.path(), true);
+
+  assert!(database.compare_and_swap(WriteOptions::new(), 1, None, Some(&[1])).unwrap());
+  assert!(!database.compare_and_swap(WriteOptions::new(), 1, None, Some(&[2])).unwrap());
+  assert!(database.compare_and_swap(WriteOptions::new(), 1, Some(&[1]), Some(&[3])).unwrap());
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![3]));
+}
+
+#[test]
+fn test_concurrent_updates() {
+  use std::sync::Arc;
+  use std::thread;
+
+  let tmp = tmpdir("concurrent_update");
+  let database = Arc::new(open_database::<i32>(tmp.path(), true));
+
+  let handles: Vec<_> = (0..8).map(|_| {
+    let local_db = database.clone();
+    thread::spawn(move || {
+      for _ in 0..25 {
+        local_db.update(WriteOptions::new(), 1, |old| {
+          Some(vec![old.map(|v| v[0]).unwrap_or(0) + 1])
+        }).unwrap();
+      }
+    })
+  }).collect();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![200]));
+}
+
+#[test]
+fn test_lock_timeout() {
+  let tmp = tmpdir("lock_timeout");
+  let database = open_database::<i32>(tmp.path(), true);
+  let locks = database.lock_manager();
+
+  let held = locks.lock_all(&[&1, &2]).unwrap();
+  let res = locks.lock_all_timeout(&[&2], Some(Duration::from_millis(10)));
+  assert!(res.is_err());
+  drop(held);
+  assert!(locks.lock_all_timeout(&[&2], Some(Duration::from_millis(10))).is_ok());
+}
@@ -0,0 +1,97 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::log::{log_files,LogReader,LogWriter,BLOCK_SIZE};
+use leveldb::options::WriteOptions;
+use leveldb::database::kv::KV;
+use leveldb::database::batch::{Batch,Writebatch,WritebatchIterator};
+use std::fs;
+use std::io::Cursor;
+
+struct Changes {
+  changes: Vec<String>,
+}
+
+impl WritebatchIterator for Changes {
+  type K = ByteKey;
+
+  fn put(&mut self, key: ByteKey, value: &[u8]) {
+    self.changes.push(format!("put {} {}", String::from_utf8_lossy(&key), value.len()));
+  }
+
+  fn deleted(&mut self, key: ByteKey) {
+    self.changes.push(format!("delete {}", String::from_utf8_lossy(&key)));
+  }
+}
+
+fn changes(reader: LogReader<Cursor<Vec<u8>>>) -> Vec<String> {
+  let mut visitor = Box::new(Changes { changes: vec![] });
+  for batch in reader {
+    visitor = batch.unwrap().iterate(visitor);
+  }
+  visitor.changes
+}
+
+#[test]
+fn test_log_read_batches() {
+  let tmp = tmpdir("log");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  database.put(WriteOptions::new(), ByteKey::from("a"), b"1").unwrap();
+  // spans several blocks
+  database.put(WriteOptions::new(), ByteKey::from("big"), &vec![7; 100000]).unwrap();
+  let mut batch = Writebatch::new();
+  batch.put(ByteKey::from("b"), b"2");
+  batch.delete(ByteKey::from("a"));
+  database.write(WriteOptions::new(), &batch).unwrap();
+
+  let path = log_files(tmp.path()).unwrap().pop().unwrap();
+  let mut reader = LogReader::open(&path).unwrap();
+  let sequences: Vec<u64> = reader.by_ref().map(|b| b.unwrap().sequence).collect();
+  assert_eq!(sequences, vec![1, 2, 3]);
+
+  let data = fs::read(&path).unwrap();
+  assert_eq!(changes(LogReader::new(Cursor::new(data))),
+             vec!["put a 1", "put big 100000", "put b 1", "delete a"]);
+}
+
+#[test]
+fn test_log_torn_tail() {
+  let tmp = tmpdir("log_torn");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  database.put(WriteOptions::new(), ByteKey::from("a"), b"1").unwrap();
+  database.put(WriteOptions::new(), ByteKey::from("big"), &vec![7; 100000]).unwrap();
+
+  let data = fs::read(log_files(tmp.path()).unwrap().pop().unwrap()).unwrap();
+  for cut in &[data.len() - 1, data.len() - 50000, 40] {
+    let torn = data[..*cut].to_vec();
+    assert_eq!(changes(LogReader::new(Cursor::new(torn))), vec!["put a 1"]);
+  }
+}
+
+#[test]
+fn test_log_detects_corruption() {
+  let tmp = tmpdir("log_corrupt");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  database.put(WriteOptions::new(), ByteKey::from("a"), b"1").unwrap();
+
+  let mut data = fs::read(log_files(tmp.path()).unwrap().pop().unwrap()).unwrap();
+  let last = data.len() - 1;
+  data[last] ^= 0xff;
+  let mut reader = LogReader::new(Cursor::new(data));
+  assert!(reader.read_record().is_err());
+  assert!(reader.read_record().unwrap().is_none());
+}
+
+#[test]
+fn test_log_writer_roundtrip() {
+  // the third record leaves too little of the first block for a header
+  let records: Vec<Vec<u8>> = vec![b"a".to_vec(), vec![], vec![1; BLOCK_SIZE - 25], vec![2; 100000], b"last".to_vec()];
+  let mut writer = LogWriter::new(vec![]);
+  for record in &records {
+    writer.add_record(record).unwrap();
+  }
+  let mut reader = LogReader::new(Cursor::new(writer.into_inner()));
+  for record in &records {
+    assert_eq!(&reader.read_record().unwrap().unwrap(), record);
+  }
+  assert!(reader.read_record().unwrap().is_none());
+}
@@ -0,0 +1,47 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::manifest::{current_manifest,read_manifest,VersionEdit,VersionSet,NUM_LEVELS};
+use leveldb::table::Table;
+use leveldb::options::WriteOptions;
+use leveldb::database::kv::KV;
+use leveldb::compaction::Compaction;
+
+#[test]
+fn test_manifest_level_layout() {
+  let tmp = tmpdir("manifest");
+  {
+    let database = open_database::<ByteKey>(tmp.path(), true);
+    for i in 0..1000 {
+      let key = ByteKey::from(format!("key{:05}", i).as_str());
+      database.put(WriteOptions::new(), key, &[1; 100]).unwrap();
+    }
+    database.compact(&ByteKey::from("key"), &ByteKey::from("kez"));
+  }
+
+  let edits = read_manifest(&current_manifest(tmp.path()).unwrap()).unwrap();
+  assert_eq!(edits[0].comparator, Some("leveldb.BytewiseComparator".to_string()));
+  for edit in &edits {
+    assert_eq!(&VersionEdit::decode(&edit.encode()).unwrap(), edit);
+  }
+
+  let version = VersionSet::recover(tmp.path()).unwrap();
+  assert_eq!(version.levels().len(), NUM_LEVELS);
+  assert!(version.last_sequence >= 1000);
+  assert!(version.next_file_number > version.log_number);
+
+  let files: Vec<_> = version.levels().iter().flat_map(|files| files.iter()).collect();
+  assert!(!files.is_empty());
+  assert_eq!(files.iter().map(|f| f.smallest.user_key.clone()).min().unwrap(), b"key00000".to_vec());
+  assert_eq!(files.iter().map(|f| f.largest.user_key.clone()).max().unwrap(), b"key00999".to_vec());
+  for file in files {
+    let table = Table::open(&VersionSet::table_path(tmp.path(), file)).unwrap();
+    let (first, _) = table.iter().next().unwrap().unwrap();
+    assert_eq!(first, file.smallest);
+  }
+}
+
+#[test]
+fn test_manifest_missing() {
+  let tmp = tmpdir("manifest_missing");
+  assert!(VersionSet::recover(tmp.path()).is_err());
+}
@@ -0,0 +1,197 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::database::Database;
+use leveldb::database::batch::{Batch,Writebatch};
+use leveldb::database::kv::KV;
+use leveldb::iterator::{Iterable,LevelDBIterator};
+use leveldb::merge::{Merge,MergeOperator,MAX_ENTRIES};
+use leveldb::multi_get::MultiGet;
+use leveldb::options::{Options,ReadOptions,WriteOptions};
+use leveldb::snapshots::Snapshots;
+use leveldb::comparator::OrdComparator;
+use std::path::Path;
+use std::sync::Arc;
+
+struct Counter;
+
+impl MergeOperator for Counter {
+  fn full_merge(&self, _key: &[u8], existing: Option<&[u8]>, operands: &[&[u8]]) -> Vec<u8> {
+    let start = existing.map(|v| v[0]).unwrap_or(0);
+    vec![operands.iter().fold(start, |acc, op| acc + op[0])]
+  }
+}
+
+fn open_merging(path: &Path) -> Database<i32> {
+  let mut options = Options::new();
+  options.create_if_missing = true;
+  options.merge_operator = Some(Arc::new(Counter));
+  Database::open(path, options).unwrap()
+}
+
+#[test]
+fn test_merge_get() {
+  let tmp = tmpdir("merge_get");
+  let database = open_merging(tmp.path());
+
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  database.merge(WriteOptions::new(), 1, &[2]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![3]));
+
+  database.put(WriteOptions::new(), 2, &[10]).unwrap();
+  database.merge(WriteOptions::new(), 2, &[5]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![15]));
+  assert_eq!(&database.get_bytes(ReadOptions::new(), 2).unwrap().unwrap()[..], &[15]);
+  assert_eq!(database.get(ReadOptions::new(), 3).unwrap(), None);
+
+  // a put replaces pending operands, a delete removes them
+  database.put(WriteOptions::new(), 1, &[7]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![7]));
+  database.delete(WriteOptions::new(), 2).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), None);
+  database.merge(WriteOptions::new(), 2, &[1]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![1]));
+}
+
+#[test]
+fn test_merge_snapshot() {
+  let tmp = tmpdir("merge_snapshot");
+  let database = open_merging(tmp.path());
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  let snapshot = database.snapshot();
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  database.merge(WriteOptions::new(), 2, &[1]).unwrap();
+
+  assert_eq!(snapshot.get(ReadOptions::new(), 1).unwrap(), Some(vec![1]));
+  assert_eq!(snapshot.get(ReadOptions::new(), 2).unwrap(), None);
+  assert_eq!(snapshot.iter(ReadOptions::new()).collect::<Vec<_>>(), vec![(1, vec![1])]);
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn test_merge_iter() {
+  let tmp = tmpdir("merge_iter");
+  let database = open_merging(tmp.path());
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  database.put(WriteOptions::new(), 2, &[7]).unwrap();
+  database.merge(WriteOptions::new(), 3, &[3]).unwrap();
+  database.delete(WriteOptions::new(), 3).unwrap();
+
+  let entries: Vec<_> = database.iter(ReadOptions::new()).collect();
+  assert_eq!(entries, vec![(1, vec![2]), (2, vec![7])]);
+  let reversed: Vec<_> = database.iter(ReadOptions::new()).reverse().collect();
+  assert_eq!(reversed, vec![(2, vec![7]), (1, vec![2])]);
+  assert_eq!(database.value_iter(ReadOptions::new()).collect::<Vec<_>>(), vec![vec![2], vec![7]]);
+  assert_eq!(database.value_iter(ReadOptions::new()).last(), Some(vec![7]));
+  assert_eq!(database.keys_iter(ReadOptions::new()).count(), 2);
+
+  let results = database.multi_get(ReadOptions::new(), &[2, 1, 3]);
+  let results: Vec<_> = results.into_iter().map(|r| r.unwrap()).collect();
+  assert_eq!(results, vec![Some(vec![7]), Some(vec![2]), None]);
+}
+
+#[test]
+fn test_merge_batch() {
+  let tmp = tmpdir("merge_batch");
+  let database = open_merging(tmp.path());
+  let mut batch = Writebatch::new();
+  batch.put(1, &[10]);
+  batch.merge(1, &[1]);
+  batch.merge(2, &[2]);
+  batch.delete(2);
+  batch.merge(3, &[3]);
+  database.write(WriteOptions::new(), &batch).unwrap();
+
+  let entries: Vec<_> = database.iter(ReadOptions::new()).collect();
+  assert_eq!(entries, vec![(1, vec![11]), (3, vec![3])]);
+
+  let plain = open_database::<i32>(&tmp.path().join("plain"), true);
+  assert!(plain.write(WriteOptions::new(), &batch).is_err());
+  assert!(plain.merge(WriteOptions::new(), 1, &[1]).is_err());
+}
+
+#[test]
+fn test_merge_reopen() {
+  let tmp = tmpdir("merge_reopen");
+  {
+    let database = open_merging(tmp.path());
+    database.put(WriteOptions::new(), 1, &[1]).unwrap();
+    database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  }
+  let database = open_merging(tmp.path());
+  // entries written after reopening still come after the earlier ones
+  database.put(WriteOptions::new(), 1, &[5]).unwrap();
+  database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![6]));
+}
+
+#[test]
+fn test_fold_range() {
+  let tmp = tmpdir("merge_fold_range");
+  let database = open_merging(tmp.path());
+  for key in 1..5 {
+    database.merge(WriteOptions::new(), key, &[key as u8]).unwrap();
+    database.merge(WriteOptions::new(), key, &[1]).unwrap();
+  }
+  database.merge(WriteOptions::new(), 6, &[1]).unwrap();
+  database.delete(WriteOptions::new(), 6).unwrap();
+  database.put(WriteOptions::new(), 7, &[7]).unwrap();
+
+  assert_eq!(database.fold_range(WriteOptions::new(), &2, &3).unwrap(), 2);
+  assert_eq!(database.fold_range(WriteOptions::new(), &2, &3).unwrap(), 0);
+  // deletes already removed their entries, single values are left alone
+  assert_eq!(database.fold_range(WriteOptions::new(), &5, &7).unwrap(), 0);
+
+  let entries: Vec<_> = database.iter(ReadOptions::new()).collect();
+  assert_eq!(entries, vec![(1, vec![2]), (2, vec![3]), (3, vec![4]), (4, vec![5]), (7, vec![7])]);
+
+  // operands recorded after folding apply to the folded value
+  database.merge(WriteOptions::new(), 2, &[1]).unwrap();
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![4]));
+}
+
+#[test]
+fn test_merge_entries_stay_bounded() {
+  let tmp = tmpdir("merge_bounded");
+  {
+    let database = open_merging(tmp.path());
+    for round in 0..4 {
+      for _ in 0..(MAX_ENTRIES * 3) {
+        database.merge(WriteOptions::new(), 1, &[1]).unwrap();
+      }
+      database.put(WriteOptions::new(), 2, &[round]).unwrap();
+      database.merge(WriteOptions::new(), 2, &[1]).unwrap();
+      let mut batch = Writebatch::new();
+      for _ in 0..(MAX_ENTRIES * 2) {
+        batch.merge(3, &[1]);
+      }
+      database.write(WriteOptions::new(), &batch).unwrap();
+      database.delete(WriteOptions::new(), 3).unwrap();
+    }
+    assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), Some(vec![(MAX_ENTRIES * 12) as u8]));
+    assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![4]));
+    assert_eq!(database.get(ReadOptions::new(), 3).unwrap(), None);
+  }
+
+  // count the entries in the reserved area directly
+  let mut options = Options::new();
+  options.create_if_missing = false;
+  let raw: Database<ByteKey> = Database::open(tmp.path(), options).unwrap();
+  let prefix = b"\xff\xff\xff\xffleveldb.merge\x00\x01";
+  let entries = raw.keys_iter(ReadOptions::new())
+                   .from(&ByteKey::new(prefix))
+                   .take_while(|key| key.starts_with(prefix))
+                   .count();
+  // at most a full list for key 1 and a value and operand for key 2
+  assert!(entries <= MAX_ENTRIES + 2, "{} entries", entries);
+}
+
+#[test]
+fn test_merge_requires_default_comparator() {
+  let tmp = tmpdir("merge_comparator");
+  let mut options = Options::new();
+  options.create_if_missing = true;
+  options.merge_operator = Some(Arc::new(Counter));
+  let result: Result<Database<i32>, _> = Database::open_with_comparator(tmp.path(), options, OrdComparator::<i32>::new("ord"));
+  assert!(result.is_err());
+}
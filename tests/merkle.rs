@@ -0,0 +1,72 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::merkle::{MerkleTree,DEFAULT_DEPTH};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use leveldb::snapshots::Snapshots;
+
+fn fill(database: &Database<ByteKey>) {
+  for i in 0..500 {
+    let key = ByteKey::from(format!("user/{:04}", i).as_str());
+    database.put(WriteOptions::new(), key, format!("{}", i).as_bytes()).unwrap();
+  }
+}
+
+#[test]
+fn test_merkle_finds_divergent_ranges() {
+  let tmp = tmpdir("merkle");
+  let a = open_database::<ByteKey>(tmp.path().join("a").as_path(), true);
+  let b = open_database::<ByteKey>(tmp.path().join("b").as_path(), true);
+  fill(&a);
+  fill(&b);
+  assert_eq!(MerkleTree::build(&a, 6).root(), MerkleTree::build(&b, 6).root());
+
+  b.put(WriteOptions::new(), ByteKey::from("user/0123"), b"changed").unwrap();
+  b.delete(WriteOptions::new(), ByteKey::from("user/0400")).unwrap();
+  b.put(WriteOptions::new(), ByteKey::from("x"), b"extra").unwrap();
+
+  let ranges = MerkleTree::build(&a, 8).diff(&MerkleTree::build(&b, 8)).unwrap();
+  for key in &["user/0123", "user/0400", "x"] {
+    assert!(ranges.iter().any(|r| r.contains(key.as_bytes())), "{} not in a range", key);
+  }
+  for key in &["user/0000", "user/0200", "user/0499"] {
+    assert!(!ranges.iter().any(|r| r.contains(key.as_bytes())), "{} in a range", key);
+  }
+
+  // repair b by copying the differing ranges from a
+  for range in &ranges {
+    for (key, _) in b.iter(ReadOptions::new()) {
+      if range.contains(&key) {
+        b.delete(WriteOptions::new(), key).unwrap();
+      }
+    }
+    for (key, value) in a.iter(ReadOptions::new()) {
+      if range.contains(&key) {
+        b.put(WriteOptions::new(), key, &value).unwrap();
+      }
+    }
+  }
+  assert_eq!(MerkleTree::build(&a, DEFAULT_DEPTH).diff(&MerkleTree::build(&b, DEFAULT_DEPTH)).unwrap(),
+             vec![]);
+}
+
+#[test]
+fn test_merkle_snapshot_and_encoding() {
+  let tmp = tmpdir("merkle_snapshot");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+  let snapshot = database.snapshot();
+  let before = MerkleTree::build(&snapshot, DEFAULT_DEPTH);
+  database.put(WriteOptions::new(), ByteKey::from("user/0001"), b"changed").unwrap();
+
+  assert_eq!(MerkleTree::build(&snapshot, DEFAULT_DEPTH), before);
+  assert_eq!(before.root().count, 500);
+  let after = MerkleTree::build(&database, DEFAULT_DEPTH);
+  assert!(after.root() != before.root());
+
+  let decoded = MerkleTree::decode(&before.encode()).unwrap();
+  assert_eq!(decoded, before);
+  assert!(decoded.diff(&MerkleTree::new(DEFAULT_DEPTH + 1)).is_err());
+}
@@ -0,0 +1,159 @@
+//! Runs random sequences of operations against a database and an in-memory
+//! model, checking that both agree.
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::batch::{Batch,Writebatch};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::{Iterable,LevelDBIterator};
+use leveldb::snapshots::{Snapshot,Snapshots};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+type Model = BTreeMap<Vec<u8>, Vec<u8>>;
+
+const SEEDS: u64 = 16;
+const SESSIONS: usize = 4;
+const STEPS: usize = 150;
+const KEYS: u64 = 48;
+
+struct Random(u64);
+
+impl Random {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  fn below(&mut self, n: u64) -> u64 {
+    self.next() % n
+  }
+
+  fn key(&mut self) -> Vec<u8> {
+    format!("k{:02}", self.below(KEYS)).into_bytes()
+  }
+
+  fn value(&mut self) -> Vec<u8> {
+    let len = self.below(12);
+    (0..len).map(|_| self.below(256) as u8).collect()
+  }
+}
+
+/// The entries an iterator bounded by `from` and `to` yields: the keys
+/// from `from` up to `to`, or down to it in reverse, both inclusive.
+fn expected(model: &Model, from: Option<&[u8]>, to: Option<&[u8]>, reverse: bool) -> Vec<(Vec<u8>, Vec<u8>)> {
+  let (low, high) = if reverse { (to, from) } else { (from, to) };
+  let entries = model.iter()
+    .filter(|e| low.map_or(true, |low| &e.0[..] >= low) && high.map_or(true, |high| &e.0[..] <= high))
+    .map(|(k, v)| (k.clone(), v.clone()));
+  if reverse {
+    entries.rev().collect()
+  } else {
+    entries.collect()
+  }
+}
+
+fn check_reads<'a, S: Iterable<'a, ByteKey>>(source: &'a S, get: &dyn Fn(&ByteKey) -> Option<Vec<u8>>,
+                                             model: &Model, random: &mut Random, context: &str) {
+  for _ in 0..4 {
+    let key = random.key();
+    assert_eq!(get(&ByteKey(key.clone())), model.get(&key).cloned(), "{}: get {:?}", context, key);
+  }
+
+  let all: Vec<_> = source.iter(ReadOptions::new()).map(|(k, v)| (k.0, v)).collect();
+  assert_eq!(all, expected(model, None, None, false), "{}: forward iteration", context);
+
+  let from = ByteKey(random.key());
+  let to = ByteKey(random.key());
+  let forward: Vec<_> = source.iter(ReadOptions::new()).from(&from).to(&to).map(|(k, v)| (k.0, v)).collect();
+  assert_eq!(forward, expected(model, Some(&from.0), Some(&to.0), false),
+             "{}: forward from {:?} to {:?}", context, from.0, to.0);
+
+  let open: Vec<_> = source.iter(ReadOptions::new()).from(&from).map(|(k, v)| (k.0, v)).collect();
+  assert_eq!(open, expected(model, Some(&from.0), None, false), "{}: forward from {:?}", context, from.0);
+
+  let reverse: Vec<_> = source.iter(ReadOptions::new()).reverse().from(&from).to(&to).map(|(k, v)| (k.0, v)).collect();
+  assert_eq!(reverse, expected(model, Some(&from.0), Some(&to.0), true),
+             "{}: reverse from {:?} to {:?}", context, from.0, to.0);
+
+  let keys: Vec<_> = source.keys_iter(ReadOptions::new()).reverse().to(&to).map(|k| k.0).collect();
+  let expected_keys: Vec<_> = expected(model, None, Some(&to.0), true).into_iter().map(|e| e.0).collect();
+  assert_eq!(keys, expected_keys, "{}: reverse keys to {:?}", context, to.0);
+
+  let last = source.iter(ReadOptions::new()).from(&from).to(&to).last().map(|(k, _)| k.0);
+  assert_eq!(last, expected(model, Some(&from.0), Some(&to.0), false).pop().map(|e| e.0),
+             "{}: last from {:?} to {:?}", context, from.0, to.0);
+}
+
+fn run_session(path: &Path, model: &mut Model, random: &mut Random, seed: u64, session: usize) {
+  let database: Database<ByteKey> = open_database(path, true);
+  let mut snapshots: Vec<(Snapshot<ByteKey>, Model)> = vec![];
+
+  for step in 0..STEPS {
+    let context = format!("seed {} session {} step {}", seed, session, step);
+    match random.below(10) {
+      0..=2 => {
+        let (key, value) = (random.key(), random.value());
+        database.put(WriteOptions::new(), ByteKey(key.clone()), &value).unwrap();
+        model.insert(key, value);
+      }
+      3 => {
+        let key = random.key();
+        database.delete(WriteOptions::new(), ByteKey(key.clone())).unwrap();
+        model.remove(&key);
+      }
+      4 | 5 => {
+        let mut batch = Writebatch::new();
+        for _ in 0..random.below(8) {
+          let key = random.key();
+          if random.below(3) == 0 {
+            batch.delete(ByteKey(key.clone()));
+            model.remove(&key);
+          } else {
+            let value = random.value();
+            batch.put(ByteKey(key.clone()), &value);
+            model.insert(key, value);
+          }
+        }
+        database.write(WriteOptions::new(), &batch).unwrap();
+      }
+      6 => {
+        if snapshots.len() < 3 {
+          snapshots.push((database.snapshot(), model.clone()));
+        } else {
+          let i = random.below(3) as usize;
+          snapshots.remove(i);
+        }
+      }
+      7 if !snapshots.is_empty() => {
+        let i = random.below(snapshots.len() as u64) as usize;
+        let (ref snapshot, ref expected) = snapshots[i];
+        let get = |key: &ByteKey| snapshot.get(ReadOptions::new(), key).unwrap();
+        check_reads(snapshot, &get, expected, random, &format!("{} snapshot {}", context, i));
+      }
+      _ => {
+        let get = |key: &ByteKey| database.get(ReadOptions::new(), key).unwrap();
+        check_reads(&database, &get, model, random, &context);
+      }
+    }
+  }
+
+  let get = |key: &ByteKey| database.get(ReadOptions::new(), key).unwrap();
+  check_reads(&database, &get, model, random, &format!("seed {} end of session {}", seed, session));
+}
+
+#[test]
+fn test_random_operations_match_model() {
+  for seed in 0..SEEDS {
+    let tmp = tmpdir("model");
+    let mut random = Random(0x9e37_79b9_7f4a_7c15 ^ (seed + 1));
+    let mut model = Model::new();
+    // every session after the first reopens the database
+    for session in 0..SESSIONS {
+      run_session(tmp.path(), &mut model, &mut random, seed, session);
+    }
+  }
+}
@@ -0,0 +1,66 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::multi_get::MultiGet;
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::snapshots::Snapshots;
+
+fn fill(database: &Database<ByteKey>) {
+  for i in 0..200 {
+    let key = ByteKey::from(format!("{:04}", i * 2).as_str());
+    database.put(WriteOptions::new(), key, format!("{}", i * 2).as_bytes()).unwrap();
+  }
+}
+
+fn lookup(keys: &[usize]) -> Vec<ByteKey> {
+  keys.iter().map(|i| ByteKey::from(format!("{:04}", i).as_str())).collect()
+}
+
+fn expected(keys: &[usize]) -> Vec<Option<Vec<u8>>> {
+  keys.iter().map(|i| if i % 2 == 0 && *i < 400 { Some(format!("{}", i).into_bytes()) } else { None }).collect()
+}
+
+#[test]
+fn test_multi_get_small_batch() {
+  let tmp = tmpdir("multi_get");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+
+  let keys = [10, 3, 398, 0, 10, 999];
+  let results: Vec<_> = database.multi_get(ReadOptions::new(), &lookup(&keys))
+    .into_iter().map(Result::unwrap).collect();
+  assert_eq!(results, expected(&keys));
+  assert!(database.multi_get(ReadOptions::new(), &[]).is_empty());
+}
+
+#[test]
+fn test_multi_get_large_batch() {
+  let tmp = tmpdir("multi_get_large");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+
+  let keys: Vec<usize> = (0..100).map(|i| (i * 37) % 450).collect();
+  let results: Vec<_> = database.multi_get(ReadOptions::new(), &lookup(&keys))
+    .into_iter().map(Result::unwrap).collect();
+  assert_eq!(results, expected(&keys));
+}
+
+#[test]
+fn test_multi_get_from_snapshot() {
+  let tmp = tmpdir("multi_get_snapshot");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+  let snapshot = database.snapshot();
+  database.put(WriteOptions::new(), ByteKey::from("0001"), b"new").unwrap();
+  database.delete(WriteOptions::new(), ByteKey::from("0002")).unwrap();
+
+  let keys: Vec<usize> = (0..20).collect();
+  let results: Vec<_> = snapshot.multi_get(ReadOptions::new(), &lookup(&keys))
+    .into_iter().map(Result::unwrap).collect();
+  assert_eq!(results, expected(&keys));
+
+  let current: Vec<_> = database.multi_get(ReadOptions::new(), &lookup(&[1, 2]))
+    .into_iter().map(Result::unwrap).collect();
+  assert_eq!(current, vec![Some(b"new".to_vec()), None]);
+}
@@ -0,0 +1,13 @@
+use utils::{open_database,tmpdir,db_put_simple};
+use leveldb::properties::Properties;
+
+#[test]
+fn test_property() {
+  let tmp = tmpdir("properties");
+  let database = &mut open_database::<i32>(tmp.path(), true);
+  db_put_simple(database, 1, &[1]);
+
+  assert_eq!(database.property("leveldb.num-files-at-level0"), Some("0".to_string()));
+  assert!(database.property("leveldb.stats").is_some());
+  assert_eq!(database.property("leveldb.no-such-property"), None);
+}
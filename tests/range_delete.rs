@@ -0,0 +1,71 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::range_delete::{RangeDelete,RangeDeleteOptions};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use leveldb::snapshots::Snapshots;
+
+fn fill(database: &Database<ByteKey>) {
+  for prefix in &["a/", "b/", "c/"] {
+    for i in 0..100 {
+      let key = ByteKey::from(format!("{}{:03}", prefix, i).as_str());
+      database.put(WriteOptions::new(), key, b"value").unwrap();
+    }
+  }
+}
+
+fn keys(database: &Database<ByteKey>) -> Vec<String> {
+  database.keys_iter(ReadOptions::new())
+    .map(|k| String::from_utf8(k.0).unwrap())
+    .collect()
+}
+
+#[test]
+fn test_delete_range() {
+  let tmp = tmpdir("range_delete");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+
+  let mut options = RangeDeleteOptions::new();
+  options.max_batch_bytes = 32;
+  let deleted = database.delete_range(options, &ByteKey::from("a/050"), &ByteKey::from("b/050")).unwrap();
+  assert_eq!(deleted, 100);
+
+  let keys = keys(&database);
+  assert_eq!(keys.len(), 200);
+  assert!(keys.contains(&"a/049".to_string()));
+  assert!(!keys.contains(&"a/050".to_string()));
+  assert!(!keys.contains(&"b/049".to_string()));
+  assert!(keys.contains(&"b/050".to_string()));
+}
+
+#[test]
+fn test_delete_prefix() {
+  let tmp = tmpdir("range_delete_prefix");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+
+  assert_eq!(database.delete_prefix(RangeDeleteOptions::new(), b"b/").unwrap(), 100);
+  assert_eq!(database.delete_prefix(RangeDeleteOptions::new(), b"b/").unwrap(), 0);
+  assert_eq!(database.delete_prefix(RangeDeleteOptions::new(), b"x/").unwrap(), 0);
+  let keys = keys(&database);
+  assert_eq!(keys.len(), 200);
+  assert!(keys.iter().all(|k| !k.starts_with("b/")));
+}
+
+#[test]
+fn test_delete_prefix_under_snapshot() {
+  let tmp = tmpdir("range_delete_snapshot");
+  let database = open_database::<ByteKey>(tmp.path(), true);
+  fill(&database);
+  let snapshot = database.snapshot();
+  database.put(WriteOptions::new(), ByteKey::from("c/new"), b"value").unwrap();
+
+  let mut options = RangeDeleteOptions::new();
+  options.snapshot = Some(&snapshot);
+  options.compact = false;
+  assert_eq!(database.delete_prefix(options, b"c/").unwrap(), 100);
+  assert_eq!(database.get(ReadOptions::new(), ByteKey::from("c/new")).unwrap(), Some(b"value".to_vec()));
+}
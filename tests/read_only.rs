@@ -0,0 +1,118 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::read_only::{ReadOnlyDatabase,ReadOnlyOptions};
+use leveldb::options::{Options,ReadOptions,WriteOptions};
+use leveldb::database::Database;
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use leveldb::compaction::Compaction;
+use std::fs;
+use std::path::Path;
+
+fn key(i: u32) -> ByteKey {
+  ByteKey(format!("key{:05}", i).into_bytes())
+}
+
+fn open_read_only(path: &Path, staging: &Path) -> ReadOnlyDatabase<ByteKey> {
+  let mut options = ReadOnlyOptions::new();
+  options.staging_dir = Some(staging.to_path_buf());
+  ReadOnlyDatabase::open(path, Options::new(), options).unwrap()
+}
+
+#[test]
+fn test_read_only_while_open_elsewhere() {
+  let tmp = tmpdir("read_only");
+  let staging = tmp.path().join("staging");
+  let path = tmp.path().join("db");
+  let database: Database<ByteKey> = open_database(&path, true);
+  for i in 0..1000 {
+    database.put(WriteOptions::new(), key(i), &[i as u8; 100]).unwrap();
+  }
+  // some entries in tables, others only in the log
+  database.compact(&key(0), &key(1000));
+  for i in 0..10 {
+    database.delete(WriteOptions::new(), key(i)).unwrap();
+  }
+
+  let read_only = open_read_only(&path, &staging);
+  database.put(WriteOptions::new(), key(5000), b"later").unwrap();
+
+  assert_eq!(read_only.get(ReadOptions::new(), key(5)).unwrap(), None);
+  assert_eq!(read_only.get(ReadOptions::new(), key(500)).unwrap(), Some(vec![244; 100]));
+  assert_eq!(read_only.get(ReadOptions::new(), key(5000)).unwrap(), None);
+  let keys: Vec<_> = read_only.keys_iter(ReadOptions::new()).collect();
+  assert_eq!(keys, (10..1000).map(key).collect::<Vec<_>>());
+
+  assert!(read_only.put(WriteOptions::new(), key(1), b"x").is_err());
+  assert!(read_only.delete(WriteOptions::new(), key(500)).is_err());
+  assert_eq!(database.get(ReadOptions::new(), key(500)).unwrap(), Some(vec![244; 100]));
+
+  // several views can be open at once
+  let second = open_read_only(&path, &staging);
+  assert_eq!(second.get(ReadOptions::new(), key(5000)).unwrap(), Some(b"later".to_vec()));
+
+  drop(read_only);
+  drop(second);
+  assert_eq!(fs::read_dir(&staging).unwrap().count(), 0);
+}
+
+#[test]
+fn test_read_only_missing_database() {
+  let tmp = tmpdir("read_only_missing");
+  let staging = tmp.path().join("staging");
+  let mut options = ReadOnlyOptions::new();
+  options.staging_dir = Some(staging.clone());
+  let result: Result<ReadOnlyDatabase<ByteKey>, _> =
+    ReadOnlyDatabase::open(&tmp.path().join("db"), Options::new(), options);
+  assert!(result.is_err());
+  assert!(!tmp.path().join("db").exists());
+  assert_eq!(fs::read_dir(&staging).map(|d| d.count()).unwrap_or(0), 0);
+}
+
+#[test]
+fn test_read_only_during_compactions() {
+  use leveldb::batch::{Batch,Writebatch};
+  use std::sync::Arc;
+  use std::sync::atomic::{AtomicBool,Ordering};
+  use std::thread;
+  use std::time::Duration;
+
+  let tmp = tmpdir("read_only_compactions");
+  let path = tmp.path().join("db");
+  let mut options = Options::new();
+  options.create_if_missing = true;
+  // flush the memtable, and replace logs, every few batches
+  options.write_buffer_size = Some(512 << 10);
+  let database: Arc<Database<ByteKey>> = Arc::new(Database::open(&path, options).unwrap());
+
+  let stop = Arc::new(AtomicBool::new(false));
+  let (writer, done) = (database.clone(), stop.clone());
+  let handle = thread::spawn(move || {
+    let mut batch_number = 0u32;
+    while !done.load(Ordering::SeqCst) {
+      let mut batch = Writebatch::new();
+      for i in 0..10 {
+        batch.put(ByteKey(format!("{:08}/{}", batch_number, i).into_bytes()), &[0; 1000]);
+      }
+      writer.write(WriteOptions::new(), &batch).unwrap();
+      batch_number += 1;
+      thread::sleep(Duration::from_micros(200));
+    }
+  });
+
+  for _ in 0..20 {
+    // the default staging directory is next to the database
+    let read_only: ReadOnlyDatabase<ByteKey> =
+      ReadOnlyDatabase::open(&path, Options::new(), ReadOnlyOptions::new()).unwrap();
+    assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    // every view holds whole batches 0..n
+    let keys: Vec<_> = read_only.keys_iter(ReadOptions::new()).map(|k| String::from_utf8(k.0).unwrap()).collect();
+    assert_eq!(keys.len() % 10, 0);
+    for (i, key) in keys.iter().enumerate() {
+      assert_eq!(key, &format!("{:08}/{}", i / 10, i % 10));
+    }
+  }
+  stop.store(true, Ordering::SeqCst);
+  handle.join().unwrap();
+  assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,215 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::changes::Change;
+use leveldb::database::Database;
+use leveldb::replication::{ChannelTransport,Follower,Message,Primary,StreamTransport,Transport};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+use leveldb::iterator::Iterable;
+use std::net::{TcpListener,TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration,Instant};
+
+fn key(k: &str) -> ByteKey {
+  ByteKey::from(k)
+}
+
+fn wait_for<F: Fn() -> bool>(condition: F) {
+  let deadline = Instant::now() + Duration::from_secs(10);
+  while !condition() {
+    assert!(Instant::now() < deadline, "replication did not converge");
+    thread::sleep(Duration::from_millis(10));
+  }
+}
+
+fn replicated(database: &Database<ByteKey>, k: &str) -> Option<Vec<u8>> {
+  database.get(ReadOptions::new(), key(k)).unwrap()
+}
+
+#[test]
+fn test_replication_streams_writes() {
+  let tmp = tmpdir("replication");
+  let primary_db = Arc::new(open_database::<ByteKey>(tmp.path().join("primary").as_path(), true));
+  primary_db.put(WriteOptions::new(), key("existing"), b"0").unwrap();
+  let primary = Arc::new(Primary::new(primary_db.clone(), 100));
+
+  let follower_db = Arc::new(open_database::<ByteKey>(tmp.path().join("follower").as_path(), true));
+  let (primary_end, mut follower_end) = ChannelTransport::pair();
+  let server = Primary::spawn_serve(&primary, primary_end);
+  let db = follower_db.clone();
+  let client = thread::spawn(move || {
+    let mut follower = Follower::new(&db).unwrap();
+    follower.run(&mut follower_end).unwrap();
+    follower.position()
+  });
+
+  primary_db.put(WriteOptions::new(), key("a"), b"1").unwrap();
+  primary_db.delete(WriteOptions::new(), key("existing")).unwrap();
+  // the follower may see the put in a snapshot and the delete after it
+  wait_for(|| replicated(&follower_db, "a").is_some() && replicated(&follower_db, "existing").is_none());
+
+  primary.shutdown();
+  server.join().unwrap().unwrap();
+  let (epoch, applied) = client.join().unwrap();
+  assert_eq!(epoch, primary.epoch());
+  assert_eq!(applied, 2);
+}
+
+/// Let `follower` catch up to `sequence`, returning whether a snapshot
+/// was needed.
+fn catch_up(primary: &Arc<Primary<ByteKey>>, follower: &mut Follower<ByteKey>, sequence: u64) -> bool {
+  let (primary_end, mut follower_end) = ChannelTransport::pair();
+  Primary::spawn_serve(primary, primary_end);
+  let (epoch, after) = follower.position();
+  follower_end.send(&Message::Subscribe { epoch, after }).unwrap();
+  let mut snapshot = false;
+  while follower.position().1 < sequence {
+    let message = follower_end.recv().unwrap().unwrap();
+    if let Message::SnapshotBegin { .. } = message {
+      snapshot = true;
+    }
+    follower.apply(message).unwrap();
+  }
+  snapshot
+}
+
+#[test]
+fn test_replication_catch_up_from_snapshot() {
+  let tmp = tmpdir("replication_catch_up");
+  let primary_db = Arc::new(open_database::<ByteKey>(tmp.path().join("primary").as_path(), true));
+  let primary = Arc::new(Primary::new(primary_db.clone(), 2));
+  let follower_db = open_database::<ByteKey>(tmp.path().join("follower").as_path(), true);
+  follower_db.put(WriteOptions::new(), key("stale"), b"x").unwrap();
+  let mut follower = Follower::new(&follower_db).unwrap();
+
+  // a new follower starts from a snapshot, replacing its data
+  primary_db.put(WriteOptions::new(), key("a"), b"1").unwrap();
+  assert!(catch_up(&primary, &mut follower, 1));
+  assert_eq!(replicated(&follower_db, "stale"), None);
+  assert_eq!(replicated(&follower_db, "a"), Some(b"1".to_vec()));
+
+  // a follower within the log catches up from it
+  primary_db.put(WriteOptions::new(), key("b"), b"2").unwrap();
+  assert!(!catch_up(&primary, &mut follower, 2));
+  assert_eq!(replicated(&follower_db, "b"), Some(b"2".to_vec()));
+
+  // a follower further behind than the log catches up from a snapshot
+  for i in 0..10 {
+    primary_db.put(WriteOptions::new(), key(&format!("k{}", i)), b"v").unwrap();
+  }
+  assert!(catch_up(&primary, &mut follower, 12));
+  assert_eq!(replicated(&follower_db, "k9"), Some(b"v".to_vec()));
+  assert_eq!(replicated(&follower_db, "a"), Some(b"1".to_vec()));
+
+  // the position survives the follower
+  let position = follower.position();
+  drop(follower);
+  let mut follower = Follower::new(&follower_db).unwrap();
+  assert_eq!(follower.position(), position);
+  primary_db.put(WriteOptions::new(), key("c"), b"3").unwrap();
+  assert!(!catch_up(&primary, &mut follower, 13));
+  assert_eq!(replicated(&follower_db, "c"), Some(b"3".to_vec()));
+  primary.shutdown();
+}
+
+#[test]
+fn test_replication_staged_snapshot() {
+  let tmp = tmpdir("replication_staged");
+  let follower_db = open_database::<ByteKey>(tmp.path().join("follower").as_path(), true);
+  follower_db.put(WriteOptions::new(), key("stale"), b"x").unwrap();
+  follower_db.put(WriteOptions::new(), key("same"), b"1").unwrap();
+  let mut follower = Follower::new(&follower_db).unwrap();
+
+  follower.apply(Message::SnapshotBegin { epoch: 1, sequence: 5 }).unwrap();
+  follower.apply(Message::SnapshotEntry { key: b"new".to_vec(), value: b"2".to_vec() }).unwrap();
+  follower.apply(Message::SnapshotEntry { key: b"same".to_vec(), value: b"1".to_vec() }).unwrap();
+  // nothing changes until the snapshot is complete
+  assert_eq!(replicated(&follower_db, "stale"), Some(b"x".to_vec()));
+  assert_eq!(replicated(&follower_db, "new"), None);
+  assert_eq!(follower.position(), (0, 0));
+
+  // an interrupted snapshot is discarded by the next one
+  follower.apply(Message::SnapshotBegin { epoch: 1, sequence: 6 }).unwrap();
+  follower.apply(Message::SnapshotEntry { key: b"same".to_vec(), value: b"1".to_vec() }).unwrap();
+  follower.apply(Message::SnapshotEnd).unwrap();
+  assert_eq!(replicated(&follower_db, "stale"), None);
+  assert_eq!(replicated(&follower_db, "new"), None);
+  assert_eq!(replicated(&follower_db, "same"), Some(b"1".to_vec()));
+  assert_eq!(follower.position(), (1, 6));
+  assert!(!tmp.path().join("follower.snapshot").exists());
+  assert_eq!(Follower::new(&follower_db).unwrap().position(), (1, 6));
+}
+
+#[test]
+fn test_replication_large_snapshot() {
+  let tmp = tmpdir("replication_large_snapshot");
+  let follower_db = open_database::<ByteKey>(tmp.path().join("follower").as_path(), true);
+  for i in 0..2500 {
+    follower_db.put(WriteOptions::new(), key(&format!("stale{:04}", i)), b"x").unwrap();
+  }
+  let mut follower = Follower::new(&follower_db).unwrap();
+
+  follower.apply(Message::SnapshotBegin { epoch: 2, sequence: 9 }).unwrap();
+  for i in 0..2500 {
+    let entry = Message::SnapshotEntry { key: format!("new{:04}", i).into_bytes(), value: b"1".to_vec() };
+    follower.apply(entry).unwrap();
+  }
+  follower.apply(Message::SnapshotEnd).unwrap();
+
+  let keys: Vec<ByteKey> = follower_db.keys_iter(ReadOptions::new()).collect();
+  // every new key, followed by the position key
+  assert_eq!(keys.len(), 2501);
+  assert!(keys[..2500].iter().all(|k| k.starts_with(b"new")));
+  assert_eq!(Follower::new(&follower_db).unwrap().position(), (2, 9));
+}
+
+#[test]
+fn test_replication_follower_requires_byte_keys() {
+  let tmp = tmpdir("replication_fixed_keys");
+  let database = open_database::<i32>(tmp.path(), true);
+  assert!(Follower::new(&database).is_err());
+}
+
+#[test]
+fn test_replication_rejects_oversized_messages() {
+  use leveldb::replication::MAX_MESSAGE_SIZE;
+  use std::io::Cursor;
+
+  let len = (MAX_MESSAGE_SIZE + 1) as u32;
+  let mut frame = vec![len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8, 0, 0, 0, 0];
+  frame.extend_from_slice(&[0; 16]);
+  let mut transport = StreamTransport::new(Cursor::new(frame));
+  assert!(transport.recv().is_err());
+}
+
+#[test]
+fn test_replication_stream_transport() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let address = listener.local_addr().unwrap();
+  let messages = vec![
+    Message::Subscribe { epoch: 7, after: 42 },
+    Message::Batch { sequence: 43, changes: vec![
+      Change::Put { key: b"a".to_vec(), value: vec![0; 1000] },
+      Change::Delete { key: b"b".to_vec() },
+    ] },
+    Message::SnapshotBegin { epoch: 7, sequence: 43 },
+    Message::SnapshotEntry { key: b"a".to_vec(), value: vec![] },
+    Message::SnapshotEnd,
+  ];
+
+  let sent = messages.clone();
+  let sender = thread::spawn(move || {
+    let mut transport = StreamTransport::new(TcpStream::connect(address).unwrap());
+    for message in &sent {
+      transport.send(message).unwrap();
+    }
+  });
+  let mut transport = StreamTransport::new(listener.accept().unwrap().0);
+  let mut received = vec![];
+  while let Some(message) = transport.recv().unwrap() {
+    received.push(message);
+  }
+  sender.join().unwrap();
+  assert_eq!(received, messages);
+}
@@ -0,0 +1,127 @@
+use utils::{open_database,tmpdir};
+use leveldb::byte_key::ByteKey;
+use leveldb::table::{InternalKey,Table,TableBuilder,TableOptions,ValueType};
+use leveldb::options::WriteOptions;
+use leveldb::database::kv::KV;
+use leveldb::compaction::Compaction;
+use std::fs;
+use std::path::{Path,PathBuf};
+
+fn table_files(dir: &Path) -> Vec<PathBuf> {
+  let mut files: Vec<PathBuf> = fs::read_dir(dir).unwrap()
+    .map(|e| e.unwrap().path())
+    .filter(|p| p.extension().map_or(false, |e| e == "ldb" || e == "sst"))
+    .collect();
+  files.sort();
+  files
+}
+
+fn write_tables(dir: &Path) {
+  let database = open_database::<ByteKey>(dir, true);
+  for i in 0..1000 {
+    let key = ByteKey::from(format!("key{:05}", i).as_str());
+    database.put(WriteOptions::new(), key, format!("value{}", i).as_bytes()).unwrap();
+  }
+  database.delete(WriteOptions::new(), ByteKey::from("key00500")).unwrap();
+  database.compact(&ByteKey::from("key"), &ByteKey::from("kez"));
+}
+
+#[test]
+fn test_table_read_entries() {
+  let tmp = tmpdir("table");
+  write_tables(tmp.path());
+
+  let mut keys = vec![];
+  for path in table_files(tmp.path()) {
+    let table = Table::open(&path).unwrap();
+    assert!(!table.data_blocks().unwrap().is_empty());
+    for entry in table.iter() {
+      let (key, value) = entry.unwrap();
+      if key.value_type == ValueType::Value {
+        assert_eq!(value, format!("value{}", &String::from_utf8_lossy(&key.user_key)[3..].parse::<u32>().unwrap()).into_bytes());
+      }
+      keys.push(key.user_key);
+    }
+  }
+  assert!(keys.len() >= 999);
+  assert!(keys.contains(&b"key00000".to_vec()));
+  assert!(keys.contains(&b"key00999".to_vec()));
+}
+
+#[test]
+fn test_table_seek() {
+  let tmp = tmpdir("table_seek");
+  write_tables(tmp.path());
+
+  for path in table_files(tmp.path()) {
+    let table = Table::open(&path).unwrap();
+    let mut iter = table.iter();
+    iter.seek(b"key00123").unwrap();
+    if let Some(entry) = iter.next() {
+      assert!(&entry.unwrap().0.user_key[..] >= &b"key00123"[..]);
+    }
+  }
+}
+
+#[test]
+fn test_table_detects_corruption() {
+  let tmp = tmpdir("table_corrupt");
+  write_tables(tmp.path());
+
+  let path = table_files(tmp.path()).pop().unwrap();
+  let mut data = fs::read(&path).unwrap();
+  data[10] ^= 0xff;
+  let table = Table::from_bytes(data).unwrap();
+  assert!(table.iter().any(|entry| entry.is_err()));
+
+  assert!(Table::from_bytes(vec![0; 100]).is_err());
+}
+
+fn internal_key(i: u32) -> InternalKey {
+  InternalKey {
+    user_key: format!("key{:05}", i).into_bytes(),
+    sequence: i as u64 + 1,
+    value_type: if i % 10 == 3 { ValueType::Deletion } else { ValueType::Value },
+  }
+}
+
+#[test]
+fn test_table_builder_roundtrip() {
+  let mut options = TableOptions::new();
+  options.block_size = 256;
+  options.bloom_bits_per_key = Some(10);
+  let mut builder = TableBuilder::new(vec![], options);
+  for i in 0..2000 {
+    builder.add(&internal_key(i), format!("value{}", i % 7).as_bytes()).unwrap();
+  }
+  let size = builder.finish().unwrap();
+  let data = builder.into_inner();
+  assert_eq!(size, data.len() as u64);
+
+  let table = Table::from_bytes(data).unwrap();
+  assert_eq!(table.filter_policy(), Some("leveldb.BuiltinBloomFilter2"));
+  let entries: Vec<_> = table.iter().map(|e| e.unwrap()).collect();
+  assert_eq!(entries.len(), 2000);
+  for (i, (key, value)) in entries.into_iter().enumerate() {
+    assert_eq!(key, internal_key(i as u32));
+    assert_eq!(value, format!("value{}", i % 7).into_bytes());
+  }
+
+  let blocks = table.data_blocks().unwrap();
+  assert!(blocks.len() > 10);
+  let matching = blocks.iter().filter(|block| table.key_may_match(block, b"key00042")).count();
+  assert!(matching >= 1 && matching < blocks.len() / 2);
+  let mut iter = table.iter();
+  iter.seek(b"key01234").unwrap();
+  assert_eq!(iter.next().unwrap().unwrap().0, internal_key(1234));
+}
+
+#[test]
+fn test_table_builder_rejects_unordered_keys() {
+  let mut builder = TableBuilder::new(vec![], TableOptions::new());
+  builder.add(&internal_key(2), b"").unwrap();
+  assert!(builder.add(&internal_key(1), b"").is_err());
+  assert!(builder.add(&internal_key(2), b"").is_err());
+  builder.finish().unwrap();
+  assert!(builder.add(&internal_key(3), b"").is_err());
+}
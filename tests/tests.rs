@@ -10,7 +10,32 @@ mod binary;
 mod iterator;
 mod snapshots;
 mod cache;
+mod filter_policy;
 mod writebatch;
 mod management;
 mod compaction;
-mod concurrent_access;
\ No newline at end of file
+mod concurrent_access;
+mod locking;
+mod merge;
+mod keyspace;
+mod ttl;
+mod index;
+mod properties;
+mod dump;
+mod backup;
+mod table;
+mod log;
+mod manifest;
+mod changes;
+mod replication;
+mod merkle;
+mod diff;
+mod range_delete;
+mod bulk;
+mod multi_get;
+mod ingest;
+mod read_only;
+mod model;
+mod crash;
+#[cfg(feature = "cli")]
+mod ldb;
@@ -0,0 +1,116 @@
+use utils::{open_database,tmpdir};
+use leveldb::ttl::{Ttl,Sweeper};
+use leveldb::options::{ReadOptions,WriteOptions};
+use leveldb::database::kv::KV;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_ttl_expiry() {
+  let tmp = tmpdir("ttl");
+  let database = open_database::<i32>(tmp.path(), true);
+  let ttl = Ttl::new(&database);
+  ttl.put(WriteOptions::new(), 1, &[1]).unwrap();
+  ttl.put_with_ttl(WriteOptions::new(), 2, &[2], Duration::from_millis(20)).unwrap();
+  ttl.put_with_ttl(WriteOptions::new(), 3, &[3], Duration::from_secs(3600)).unwrap();
+  assert_eq!(ttl.get(ReadOptions::new(), 2).unwrap(), Some(vec![2]));
+
+  thread::sleep(Duration::from_millis(50));
+  assert_eq!(ttl.get(ReadOptions::new(), 1).unwrap(), Some(vec![1]));
+  assert_eq!(ttl.get(ReadOptions::new(), 2).unwrap(), None);
+  assert_eq!(ttl.get(ReadOptions::new(), 3).unwrap(), Some(vec![3]));
+
+  let entries: Vec<_> = ttl.iter(ReadOptions::new()).map(Result::unwrap).collect();
+  assert_eq!(entries, vec![(1, vec![1]), (3, vec![3])]);
+}
+
+#[test]
+fn test_ttl_iter_bounds() {
+  let tmp = tmpdir("ttl_bounds");
+  let database = open_database::<i32>(tmp.path(), true);
+  let ttl = Ttl::new(&database);
+  for key in 1..7 {
+    ttl.put(WriteOptions::new(), key, &[key as u8]).unwrap();
+  }
+  ttl.put_with_ttl(WriteOptions::new(), 3, &[3], Duration::from_millis(1)).unwrap();
+  thread::sleep(Duration::from_millis(10));
+
+  let keys: Vec<_> = ttl.iter(ReadOptions::new()).from(&2).to(&5).map(|e| e.unwrap().0).collect();
+  assert_eq!(keys, vec![2, 4, 5]);
+  let keys: Vec<_> = ttl.iter(ReadOptions::new()).reverse().map(|e| e.unwrap().0).collect();
+  assert_eq!(keys, vec![6, 5, 4, 2, 1]);
+  let keys: Vec<_> = ttl.iter(ReadOptions::new()).reverse().from(&5).to(&2).map(|e| e.unwrap().0).collect();
+  assert_eq!(keys, vec![5, 4, 2]);
+}
+
+#[test]
+fn test_ttl_value_without_header() {
+  let tmp = tmpdir("ttl_no_header");
+  let database = open_database::<i32>(tmp.path(), true);
+  let ttl = Ttl::new(&database);
+  ttl.put(WriteOptions::new(), 1, &[1]).unwrap();
+  database.put(WriteOptions::new(), 2, &[2]).unwrap();
+
+  assert!(ttl.get(ReadOptions::new(), 2).is_err());
+  let entries: Vec<_> = ttl.iter(ReadOptions::new()).collect();
+  assert_eq!(entries.len(), 2);
+  assert_eq!(entries[0].as_ref().unwrap(), &(1, vec![1]));
+  assert!(entries[1].is_err());
+  assert!(ttl.sweep(WriteOptions::new(), 10).is_err());
+  assert_eq!(database.get(ReadOptions::new(), 2).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn test_ttl_sweep() {
+  let tmp = tmpdir("ttl_sweep");
+  let database = open_database::<i32>(tmp.path(), true);
+  let ttl = Ttl::new(&database);
+  for key in 0..10 {
+    ttl.put_with_ttl(WriteOptions::new(), key, &[key as u8], Duration::from_millis(1)).unwrap();
+  }
+  ttl.put(WriteOptions::new(), 10, &[10]).unwrap();
+  ttl.put_with_ttl(WriteOptions::new(), 11, &[11], Duration::from_secs(u64::MAX)).unwrap();
+  thread::sleep(Duration::from_millis(20));
+
+  assert_eq!(ttl.sweep(WriteOptions::new(), 3).unwrap(), 10);
+  assert_eq!(database.get(ReadOptions::new(), 5).unwrap(), None);
+  assert!(database.get(ReadOptions::new(), 10).unwrap().is_some());
+  assert_eq!(ttl.get(ReadOptions::new(), 11).unwrap(), Some(vec![11]));
+  assert_eq!(ttl.sweep(WriteOptions::new(), 3).unwrap(), 0);
+}
+
+#[test]
+fn test_sweep_keeps_rewritten_keys() {
+  use std::sync::Arc;
+
+  let tmp = tmpdir("ttl_sweep_rewrite");
+  let database = Arc::new(open_database::<i32>(tmp.path(), true));
+  Ttl::new(&*database).put_with_ttl(WriteOptions::new(), 1, &[1], Duration::from_millis(1)).unwrap();
+  thread::sleep(Duration::from_millis(20));
+
+  // the key expired when the sweep scanned it, but is rewritten before
+  // the sweep gets its lock
+  let lock = database.lock_manager().lock(&1).unwrap();
+  let sweeping = database.clone();
+  let sweep = thread::spawn(move || Ttl::new(&*sweeping).sweep(WriteOptions::new(), 10).unwrap());
+  thread::sleep(Duration::from_millis(50));
+  database.put(WriteOptions::new(), 1, &[0, 0, 0, 0, 0, 0, 0, 0, 2]).unwrap();
+  drop(lock);
+
+  assert_eq!(sweep.join().unwrap(), 0);
+  assert_eq!(Ttl::new(&*database).get(ReadOptions::new(), 1).unwrap(), Some(vec![2]));
+}
+
+#[test]
+fn test_background_sweeper() {
+  use std::sync::Arc;
+
+  let tmp = tmpdir("ttl_sweeper");
+  let database = Arc::new(open_database::<i32>(tmp.path(), true));
+  Ttl::new(&database).put_with_ttl(WriteOptions::new(), 1, &[1], Duration::from_millis(1)).unwrap();
+
+  let sweeper = Sweeper::spawn(database.clone(), Duration::from_millis(10));
+  thread::sleep(Duration::from_millis(100));
+  drop(sweeper);
+  assert_eq!(database.get(ReadOptions::new(), 1).unwrap(), None);
+}